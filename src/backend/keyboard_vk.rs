@@ -4,19 +4,20 @@ use num_traits::{FromPrimitive, ToPrimitive};
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 
-use crate::win::VIRTUAL_KEY;
+use crate::backend::VIRTUAL_KEY;
 
-impl Into<VIRTUAL_KEY> for KNOWN_VIRTUAL_KEY {
-    fn into(self) -> VIRTUAL_KEY {
-        self as u32
+impl From<KNOWN_VIRTUAL_KEY> for VIRTUAL_KEY {
+    fn from(vk: KNOWN_VIRTUAL_KEY) -> Self {
+        vk as u32
     }
 }
 
 pub struct UnknownVirtualKey(u32);
 
 impl UnknownVirtualKey {
+    #[allow(dead_code)]
     pub fn into_inner(self) -> u32 {
-        return self.0;
+        self.0
     }
 }
 
@@ -34,6 +35,7 @@ impl Display for UnknownVirtualKey {
 
 impl Error for UnknownVirtualKey {}
 
+#[allow(dead_code)]
 struct VirtualKeyEx(Result<KNOWN_VIRTUAL_KEY, UnknownVirtualKey>);
 
 impl Debug for VirtualKeyEx {
@@ -45,9 +47,9 @@ impl Debug for VirtualKeyEx {
     }
 }
 
-impl Into<u32> for VirtualKeyEx {
-    fn into(self) -> u32 {
-        match &self.0 {
+impl From<VirtualKeyEx> for u32 {
+    fn from(vk: VirtualKeyEx) -> Self {
+        match &vk.0 {
             Ok(vk) => vk.to_u32().expect("WTF"),
             Err(e) => e.0,
         }
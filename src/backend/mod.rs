@@ -0,0 +1,206 @@
+pub(crate) mod keyboard_vk;
+#[cfg(not(windows))]
+pub(crate) mod null;
+#[cfg(test)]
+pub(crate) mod recorder;
+
+use std::fmt::{Debug, Formatter};
+
+use crate::backend::KeyAction::Press;
+use crate::keycode::KeyCode;
+use crate::r#static::INPUT_BACKEND;
+
+/// Windows virtual key code. The engine itself works with [`KeyCode`].
+#[allow(non_camel_case_types)]
+pub type VIRTUAL_KEY = u32;
+
+/// Source of keyboard events.
+///
+/// Implementations block in [`CaptureBackend::run`] and feed every key event into
+/// `KEY_MANAGER_INSTANCE` (`keydown`/`keyup`). When the key manager reports that an event was
+/// handled by a hotkey, the backend should swallow it if the platform allows that.
+pub trait CaptureBackend: Send + Sync {
+    fn run(&self) -> anyhow::Result<()>;
+
+    /// Whether handled events are kept from applications. Withholding keys needs that.
+    fn can_swallow(&self) -> bool {
+        true
+    }
+}
+
+/// Sink for synthesized keyboard events.
+///
+/// Events sent through here must be recognisable by the capture half of the same backend, so
+/// they never re-trigger bindings.
+pub trait InjectionBackend: Send + Sync {
+    fn send_keystrokes(&self, strokes: &[KeyStroke]);
+}
+
+/// Translation of keys into the characters they type.
+pub trait KeyTranslator: Send + Sync {
+    /// Character produced by the key. When `localized` is set, the layout of whatever is
+    /// currently focused is used instead of the default one.
+    fn to_unicode(&self, key: KeyCode, localized: bool) -> Option<String>;
+}
+
+/// Capture, injection and key translation halves of a platform.
+pub trait InputBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn capture(&self) -> &dyn CaptureBackend;
+
+    fn injection(&self) -> &dyn InjectionBackend;
+
+    fn translator(&self) -> &dyn KeyTranslator;
+}
+
+/// Backend the daemon uses when nothing else was requested.
+#[cfg_attr(test, allow(dead_code))]
+pub(crate) fn platform_default() -> Box<dyn InputBackend> {
+    #[cfg(windows)]
+    {
+        Box::new(crate::win::backend::WindowsBackend)
+    }
+    #[cfg(target_os = "linux")]
+    {
+        crate::linux::backend_from_env()
+    }
+    #[cfg(not(any(windows, target_os = "linux")))]
+    {
+        Box::new(null::NullBackend)
+    }
+}
+
+/// Translator of the platform that works without setting up the backend, no hooks and no
+/// virtual keyboard. Used where the keyboard must not be touched, like `check`.
+pub(crate) fn offline_translator() -> &'static dyn KeyTranslator {
+    #[cfg(windows)]
+    {
+        &crate::win::backend::WindowsBackend
+    }
+    #[cfg(not(windows))]
+    {
+        &null::NullBackend
+    }
+}
+
+pub trait ToUnicode {
+    #[allow(dead_code)]
+    fn to_unicode(&self) -> Option<String>;
+
+    fn to_unicode_localized(&self) -> Option<String>;
+}
+
+impl ToUnicode for KeyCode {
+    fn to_unicode(&self) -> Option<String> {
+        INPUT_BACKEND.translator().to_unicode(*self, false)
+    }
+
+    fn to_unicode_localized(&self) -> Option<String> {
+        INPUT_BACKEND.translator().to_unicode(*self, true)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct KeyStroke {
+    pub(crate) key_type: KeyType,
+    /// `None` for unicode strokes
+    pub(crate) key: Option<KeyCode>,
+    /// Scancode of the key, or the character for unicode strokes
+    pub(crate) scancode: u32,
+    pub(crate) action: KeyAction,
+}
+
+impl Debug for KeyStroke {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{}] {:?} {:?} key 0x{:x}",
+            if self.action == Press {
+                "Pressing"
+            } else {
+                "Releasing"
+            },
+            self.key_type,
+            self.key,
+            self.scancode
+        )
+    }
+}
+
+impl KeyStroke {
+    pub fn classic(key: KeyCode, action: KeyAction) -> Self {
+        Self {
+            key_type: KeyType::Classic,
+            key: Some(key),
+            scancode: key.scancode(),
+            action,
+        }
+    }
+
+    pub fn unicode(char: char, action: KeyAction) -> Self {
+        Self {
+            key_type: KeyType::Unicode,
+            key: None,
+            scancode: char as u32,
+            action,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn clone_as_press(self) -> KeyStroke {
+        let mut pew = self;
+        pew.action = KeyAction::Press;
+        pew
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn clone_as_release(self) -> KeyStroke {
+        let mut pew = self;
+        pew.action = KeyAction::Release;
+        pew
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyType {
+    Unicode,
+    Classic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyAction {
+    Press,
+    Release,
+}
+
+pub fn send_key_sequence(pre_keys: &[KeyStroke], the_char: &[KeyStroke], post_keys: &[KeyStroke]) {
+    let mut inputs = Vec::new();
+    inputs.extend(pre_keys);
+    inputs.extend(the_char);
+    inputs.extend(post_keys);
+    INPUT_BACKEND.injection().send_keystrokes(&inputs);
+}
+
+/// Types every character of `text`.
+pub fn type_text(text: &str) {
+    let inputs = text
+        .chars()
+        .flat_map(|char| [KeyStroke::unicode(char, KeyAction::Press), KeyStroke::unicode(char, KeyAction::Release)])
+        .collect::<Vec<_>>();
+    INPUT_BACKEND.injection().send_keystrokes(&inputs);
+}
+
+/// Erases the last `erase` characters with backspace and sends `the_char` instead, like when a
+/// multi-tap binding moves on to its next character. `pre_keys` go first, so held modifiers can be
+/// released before the backspaces.
+pub fn replace_last_output(pre_keys: &[KeyStroke], erase: usize, the_char: &[KeyStroke]) {
+    let mut inputs = Vec::with_capacity(pre_keys.len() + erase * 2 + the_char.len());
+    inputs.extend(pre_keys);
+    for _ in 0..erase {
+        inputs.push(KeyStroke::classic(KeyCode::Backspace, KeyAction::Press));
+        inputs.push(KeyStroke::classic(KeyCode::Backspace, KeyAction::Release));
+    }
+    inputs.extend(the_char);
+    INPUT_BACKEND.injection().send_keystrokes(&inputs);
+}
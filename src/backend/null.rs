@@ -0,0 +1,54 @@
+use crate::backend::{CaptureBackend, InjectionBackend, InputBackend, KeyStroke, KeyTranslator};
+use crate::keycode::KeyCode;
+
+/// Backend that neither captures nor injects anything.
+///
+/// Used on platforms without a native backend, so the engine and the config pipeline still run
+/// (e.g. in CI). Keys are translated as if the US layout was active.
+pub struct NullBackend;
+
+impl InputBackend for NullBackend {
+    fn name(&self) -> &'static str {
+        "null"
+    }
+
+    fn capture(&self) -> &dyn CaptureBackend {
+        self
+    }
+
+    fn injection(&self) -> &dyn InjectionBackend {
+        self
+    }
+
+    fn translator(&self) -> &dyn KeyTranslator {
+        self
+    }
+}
+
+impl CaptureBackend for NullBackend {
+    fn can_swallow(&self) -> bool {
+        false
+    }
+
+    fn run(&self) -> anyhow::Result<()> {
+        Err(anyhow::anyhow!(
+            "No keyboard capture backend is available on this platform"
+        ))
+    }
+}
+
+impl InjectionBackend for NullBackend {
+    fn send_keystrokes(&self, strokes: &[KeyStroke]) {
+        log::debug!(target: "NullBackend", "Dropping injected keystrokes: {:?}", strokes);
+    }
+}
+
+impl KeyTranslator for NullBackend {
+    fn to_unicode(&self, key: KeyCode, _localized: bool) -> Option<String> {
+        if key.is_printable() {
+            char::from_u32(key.keysym()).map(String::from)
+        } else {
+            None
+        }
+    }
+}
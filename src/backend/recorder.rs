@@ -0,0 +1,61 @@
+use parking_lot::Mutex;
+
+use crate::backend::{CaptureBackend, InjectionBackend, InputBackend, KeyStroke, KeyTranslator};
+use crate::keycode::KeyCode;
+use crate::r#static::ACTION_DISPATCHER;
+
+/// What was typed since [`record`] started, `None` when nothing records.
+static TYPED: Mutex<Option<Vec<KeyStroke>>> = Mutex::new(None);
+/// Only one test records at a time, actions typing on the dispatcher thread end up in its list.
+static RECORDING: Mutex<()> = Mutex::new(());
+
+/// Backend of the tests. Nothing is captured, what would be typed is recorded and characters
+/// come from the layouts only.
+pub struct RecordingBackend;
+
+impl InputBackend for RecordingBackend {
+    fn name(&self) -> &'static str {
+        "recorder"
+    }
+
+    fn capture(&self) -> &dyn CaptureBackend {
+        self
+    }
+
+    fn injection(&self) -> &dyn InjectionBackend {
+        self
+    }
+
+    fn translator(&self) -> &dyn KeyTranslator {
+        self
+    }
+}
+
+impl CaptureBackend for RecordingBackend {
+    fn run(&self) -> anyhow::Result<()> {
+        Err(anyhow::anyhow!("The tests capture nothing"))
+    }
+}
+
+impl InjectionBackend for RecordingBackend {
+    fn send_keystrokes(&self, strokes: &[KeyStroke]) {
+        if let Some(typed) = TYPED.lock().as_mut() {
+            typed.extend_from_slice(strokes);
+        }
+    }
+}
+
+impl KeyTranslator for RecordingBackend {
+    fn to_unicode(&self, _key: KeyCode, _localized: bool) -> Option<String> {
+        None
+    }
+}
+
+/// Runs `test` and returns what it typed, the actions it queued included.
+pub(crate) fn record(test: impl FnOnce()) -> Vec<KeyStroke> {
+    let _recording = RECORDING.lock();
+    *TYPED.lock() = Some(Vec::new());
+    test();
+    ACTION_DISPATCHER.flush();
+    TYPED.lock().take().unwrap_or_default()
+}
@@ -0,0 +1,424 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+
+use log::LevelFilter;
+use simple_logger::SimpleLogger;
+
+use crate::backend::{replace_last_output, send_key_sequence, type_text, KeyAction, KeyStroke};
+use crate::keybindings::action::Action;
+use crate::keybindings::parser::{BindingsConfig, Diagnostic, Severity};
+use crate::keybindings::{bindings_from_config, watch, Bindings, BINDINGS_FILE, LAYERS_FILE};
+use crate::layout;
+use crate::r#static::{ACTION_DISPATCHER, HOTKEY_MANAGER_INSTANCE, INPUT_BACKEND, KEY_MANAGER_INSTANCE, RUNNING_COMMANDS};
+use crate::r#type::command;
+use crate::r#type::dualrole::DualRole;
+use crate::r#type::hotkeymanager::{Callback, HotkeyManager, KeySequence, LayerSwitch, TriggeredHotkey};
+
+use crate::r#type::Dump;
+
+/// Runs the daemon with the command line arguments, `check <file>` only checks a bindings file.
+pub fn run() {
+    SimpleLogger::new().with_threads(true).init().expect("Can't load logger.");
+
+    let args: Vec<String> = env::args().collect();
+
+    if args.get(1).map(String::as_str) == Some("check") {
+        log::set_max_level(LevelFilter::Warn);
+        let path = args.get(2).map_or(BINDINGS_FILE, String::as_str);
+        process::exit(check(Path::new(path)));
+    }
+
+    let level = match args.len() {
+        0|1 => LevelFilter::Error,
+        2 => LevelFilter::from_str(&args[1]).unwrap_or(LevelFilter::Error),
+        _ => LevelFilter::from_str(&args[1]).unwrap_or(LevelFilter::Trace),
+    };
+    println!("Current log level: {}", level);
+    log::set_max_level(level);
+    #[cfg(target_os = "linux")]
+    command::kill_on_signals();
+
+    if let Err(e) = layout::load_from_env() {
+        log::error!("Can't load keyboard layouts: {:?}", e);
+    }
+
+    let mut config = BindingsConfig::load(Path::new(BINDINGS_FILE)).unwrap_or_else(|e| {
+        log::error!("Can't open keybindings: {:?}", e);
+        BindingsConfig::default()
+    });
+    let bindings = bindings_from_config(&mut config);
+    config.diagnostics.iter().for_each(Diagnostic::log);
+
+    log::info!("Parsed keybindings:\n{}", bindings.dump());
+    let manager = hotkey_manager(bindings);
+    {
+        let mut instance = HOTKEY_MANAGER_INSTANCE.lock();
+        instance.replace_bindings(manager);
+        configure(&mut instance, &config);
+    }
+    configure_key_manager(&config);
+    watch::spawn(PathBuf::from(BINDINGS_FILE), reload);
+
+    log::info!("Capturing keyboard with the {} backend", INPUT_BACKEND.name());
+    if let Err(e) = INPUT_BACKEND.capture().run() {
+        log::error!("Keyboard capture stopped: {:?}", e);
+    }
+    log::info!("Typing queue: {}", ACTION_DISPATCHER.stats());
+    RUNNING_COMMANDS.lock().kill_on_exit();
+}
+
+/// Applies the `[options]` and the `[hold]` variants of the bindings file.
+fn configure(manager: &mut HotkeyManager, config: &BindingsConfig) {
+    let options = &config.options;
+    manager.set_withhold(options.withhold);
+    manager.set_sequence_timeout(options.sequence_timeout);
+    manager.set_tap_timeout(options.multi_tap_timeout);
+    let variants = config
+        .hold_variants
+        .iter()
+        .map(|entry| (entry.char, entry.variants.clone()))
+        .collect();
+    manager.set_hold(options.hold, variants);
+    manager.remember_layers(options.remember_layers.then(|| PathBuf::from(LAYERS_FILE)));
+}
+
+/// Applies the `[dual_role]` keys and the sticky modifiers. Not while holding the hotkey manager,
+/// the hook locks the key manager first.
+fn configure_key_manager(config: &BindingsConfig) {
+    let roles = config
+        .dual_roles
+        .iter()
+        .map(|entry| DualRole {
+            key: entry.key,
+            tap: entry.tap,
+            hold: entry.hold,
+        })
+        .collect();
+    let options = &config.options;
+    let mut key_manager = KEY_MANAGER_INSTANCE.write();
+    key_manager.set_dual_roles(roles, options.tapping_term, options.permissive_hold);
+    key_manager.set_sticky(options.sticky.clone(), options.multi_tap_timeout);
+}
+
+/// Table with a binding typing the character for every key combination, the multi-tap bindings,
+/// the dead keys, the actions and the layers.
+fn hotkey_manager(bindings: Bindings) -> HotkeyManager {
+    let mut manager = HotkeyManager::new();
+    for (mark, sequences) in bindings.dead_keys {
+        for steps in sequences {
+            manager.add_dead_key(steps, mark);
+        }
+    }
+    for ((layer, switch), sequences) in bindings.layer_switches {
+        for steps in sequences {
+            manager.add_layer_switch(steps, &layer, switch);
+        }
+    }
+    let actions = bindings
+        .chars
+        .into_iter()
+        .map(|(char_to_post, sequences)| (Action::Unicode(vec![char_to_post]), sequences))
+        .chain(bindings.multi_taps.into_iter().map(|(chars, sequences)| (Action::Unicode(chars), sequences)))
+        .chain(bindings.actions);
+    add_actions(&mut manager, actions, None);
+    for (layer, chars) in bindings.layers {
+        let actions = chars
+            .into_iter()
+            .map(|(char_to_post, sequences)| (Action::Unicode(vec![char_to_post]), sequences));
+        add_actions(&mut manager, actions, Some(&layer));
+    }
+    manager
+}
+
+/// Adds bindings running the actions, working only in `layer` if there is one.
+fn add_actions(
+    manager: &mut HotkeyManager,
+    actions: impl Iterator<Item = (Action, Vec<KeySequence>)>,
+    layer: Option<&str>,
+) {
+    actions.for_each(|(action, key_bindings)| {
+        key_bindings.into_iter().for_each(|mut steps| {
+            let (on_press, on_release) = action_callbacks(action.clone());
+            let the_binding = if steps.len() == 1 {
+                let chord = steps.remove(0);
+                manager.add_magic_binding(chord.keys, on_press, on_release, chord.ordered)
+            } else {
+                manager.add_magic_sequence(steps, on_press, on_release)
+            };
+            manager.move_to_layer(the_binding, layer);
+        });
+    });
+}
+
+/// Callbacks running the action on press and on release of its keys.
+fn action_callbacks(action: Action) -> (Callback, Callback) {
+    match action {
+        Action::Unicode(chars) => typing_callbacks(chars),
+        Action::Remap(key) => (
+            // Key repeat of the binding repeats the key too
+            Box::new(move |_| send_key_sequence(&[], &[KeyStroke::classic(key, KeyAction::Press)], &[])),
+            Box::new(move |_| send_key_sequence(&[], &[KeyStroke::classic(key, KeyAction::Release)], &[])),
+        ),
+        action => {
+            let action = Arc::new(action);
+            let release_action = action.clone();
+            let on_press: Callback = Box::new(move |triggered| {
+                let target = "[main::action]";
+                if triggered.0.triggered {
+                    return;
+                }
+                log::info!(target: target, "Running `{}` for hotkey {:?}", action, triggered.0);
+                // The keys of the binding would change what is typed, like for the characters
+                if action.types() && !triggered.0.withheld {
+                    let pre_keys: Vec<KeyStroke> = triggered.1.iter().rev()
+                        .map(|&vk| KeyStroke::classic(vk, KeyAction::Release))
+                        .collect();
+                    send_key_sequence(&pre_keys, &[], &[]);
+                }
+                run_action(&action, &triggered);
+            });
+            let on_release: Callback = Box::new(move |triggered| {
+                if release_action.types() && !triggered.0.triggered && !triggered.0.withheld {
+                    let post_keys: Vec<KeyStroke> = triggered.1.iter()
+                        .map(|&vk| KeyStroke::classic(vk, KeyAction::Press))
+                        .collect();
+                    send_key_sequence(&[], &[], &post_keys);
+                }
+            });
+            (on_press, on_release)
+        }
+    }
+}
+
+/// Runs an action all at once, the keys of the binding are released already.
+fn run_action(action: &Action, triggered: &TriggeredHotkey) {
+    match action {
+        Action::Unicode(chars) => type_text(&chars.iter().collect::<String>()),
+        Action::Text(text) => type_text(text),
+        Action::Chord(keys) => {
+            let press = keys.iter().map(|&key| KeyStroke::classic(key, KeyAction::Press)).collect::<Vec<_>>();
+            let release = keys.iter().rev().map(|&key| KeyStroke::classic(key, KeyAction::Release)).collect::<Vec<_>>();
+            send_key_sequence(&press, &[], &release);
+        }
+        Action::Remap(key) => {
+            let tap = [KeyStroke::classic(*key, KeyAction::Press), KeyStroke::classic(*key, KeyAction::Release)];
+            send_key_sequence(&[], &tap, &[]);
+        }
+        Action::Macro(steps) => steps.iter().for_each(|step| run_action(step, triggered)),
+        Action::Command(command) => command::spawn(command, triggered),
+        Action::Layer(layer, LayerSwitch::Toggle) => {
+            let mut manager = HOTKEY_MANAGER_INSTANCE.lock();
+            let on = !manager.active_layers().iter().any(|name| &**name == layer);
+            manager.set_layer(layer, on);
+        }
+        // Bound as a layer switch and refused in macros, nothing holds the keys here
+        Action::Layer(layer, LayerSwitch::Hold) => {
+            log::warn!("Layer {} is only on while its keys are held, it can't be run as an action", layer)
+        }
+        Action::Profile(path) => {
+            // Not on the typing thread, loading takes a while and replaces the binding running this
+            let path = path.clone();
+            thread::spawn(move || {
+                log::info!("Switching to the profile {:?}", path);
+                if load_bindings(&path) {
+                    watch::follow(&path);
+                }
+            });
+        }
+    }
+}
+
+/// Actions typing the character of a binding. With more than one character every tap in a row
+/// replaces the character typed by the tap before with the next one.
+fn typing_callbacks(chars: Vec<char>) -> (Callback, Callback) {
+    let chars = Arc::new(chars);
+    let release_chars = chars.clone();
+    let char_of = |chars: &[char], triggered: &TriggeredHotkey| chars[triggered.0.taps().saturating_sub(1) % chars.len()];
+
+    let on_press: Callback = Box::new(move |triggered| {
+        let target= "[main::hotkey_activation]";
+        log::debug!( target: target, "Triggered {:?} on keypress.", triggered);
+        // Holding the key down doesn't move on to the next character
+        if chars.len() > 1 && triggered.0.triggered {
+            return;
+        }
+
+        // Withheld keys never reached the application, nothing to release there
+        let mut pre_keys: Vec<KeyStroke> =  if triggered.0.triggered || triggered.0.withheld {
+            Vec::new()
+        } else {
+            log::info!( target: target, "Activating binding for hotkey {:?}", triggered.0);
+            log::debug!(target: target, "Hotkey is not yet activated, releasing pressed keys: {:?}", triggered.1);
+            /*filter_modifier_keys*/triggered.1.iter()
+                .map(|&vk| KeyStroke::classic(vk, KeyAction::Release))
+                .collect()
+        };
+        pre_keys.reverse();
+        let char_keystroke = KeyStroke::unicode(char_of(&chars, &triggered), KeyAction::Press);
+        if triggered.0.taps() > 1 {
+            replace_last_output(&pre_keys, 1, &[char_keystroke]);
+        } else {
+            send_key_sequence(&pre_keys, &[char_keystroke], &[]);
+        }
+    });
+    let on_release: Callback = Box::new(move |triggered| {
+        let target= "[main::hotkey_deactivation]";
+        log::debug!(target: target, "Triggered {:?} on keyrelease.", triggered);
+        let post_keys: Vec<KeyStroke> =  if triggered.0.triggered || triggered.0.withheld {
+            Vec::new()
+        } else {
+            log::debug!(target: target, "Hotkey is still activated, releasing pressed keys: {:?}", triggered.1);
+            /*filter_modifier_keys*/triggered.1.iter()
+                .map(|&vk| KeyStroke::classic(vk, KeyAction::Press))
+                .collect()
+        };
+        //post_keys.reverse();
+        let char_keystroke = KeyStroke::unicode(char_of(&release_chars, &triggered), KeyAction::Release);
+        send_key_sequence(&[], &[char_keystroke], &post_keys);
+    });
+    (on_press, on_release)
+}
+
+/// Re-reads the bindings file after it changed. The bindings in use stay when the file can't be
+/// read or has errors.
+fn reload(path: &Path) {
+    log::info!("{:?} changed, reloading keybindings", path);
+    load_bindings(path);
+}
+
+/// Replaces the bindings in use with the ones of `path`, unless it can't be read or has errors.
+/// Returns whether it did.
+fn load_bindings(path: &Path) -> bool {
+    let mut config = match BindingsConfig::load(path) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("Can't reload keybindings, keeping the last good ones: {:?}", e);
+            return false;
+        }
+    };
+    let bindings = bindings_from_config(&mut config);
+    config.diagnostics.iter().for_each(Diagnostic::log);
+    if config.has_errors() {
+        log::error!("{:?} has errors, keeping the last good keybindings", path);
+        return false;
+    }
+
+    log::info!("Reloaded keybindings:\n{}", bindings.dump());
+    // Built before locking, so the hook doesn't wait for the threads to start
+    let manager = hotkey_manager(bindings);
+    let previous = {
+        let mut instance = HOTKEY_MANAGER_INSTANCE.lock();
+        configure(&mut instance, &config);
+        instance.replace_bindings(manager)
+    };
+    drop(previous);
+    configure_key_manager(&config);
+    true
+}
+
+/// `diakritika check [path]` parses and expands the bindings the same way the daemon does, without
+/// touching the keyboard. Prints the diagnostics and the resulting bindings, returns the exit code.
+fn check(path: &Path) -> i32 {
+    if let Err(e) = layout::load_from_env() {
+        eprintln!("warning: can't load keyboard layouts: {:?}", e);
+    }
+    // Never the input backend, that would set up the virtual keyboard or connect to X
+    layout::load_offline();
+    let mut config = match BindingsConfig::load(path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {:?}", e);
+            return 1;
+        }
+    };
+
+    let bindings = bindings_from_config(&mut config);
+    config.diagnostics.iter().for_each(|diagnostic| eprintln!("{}", diagnostic));
+    println!("{}", bindings.dump());
+
+    let errors = config
+        .diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count();
+    eprintln!(
+        "{}: {} errors, {} warnings",
+        path.display(),
+        errors,
+        config.diagnostics.len() - errors
+    );
+    if config.has_errors() {
+        1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::backend::recorder::record;
+    use crate::keycode::KeyCode::{AltLeft, Backspace, KeyA, KeyB};
+    use crate::r#type::hotkeymanager::tests::{keys, tap};
+
+    /// Manager typing `á`, `à` and `ä` on taps of `alt+a`.
+    fn multi_tap(timeout: Duration) -> HotkeyManager {
+        let mut manager = HotkeyManager::new();
+        manager.set_tap_timeout(timeout);
+        let (on_press, on_release) = typing_callbacks(vec!['á', 'à', 'ä']);
+        manager.add_magic_binding(keys(&[AltLeft, KeyA]), on_press, on_release, false);
+        manager
+    }
+
+    /// The typed characters with `⌫` for each backspace erasing one.
+    fn typed_text(typed: &[KeyStroke]) -> String {
+        typed
+            .iter()
+            .filter(|stroke| stroke.action == KeyAction::Press)
+            .filter_map(|stroke| match stroke.key {
+                Some(Backspace) => Some('⌫'),
+                Some(_) => None,
+                None => char::from_u32(stroke.scancode),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn taps_in_a_row_replace_the_last_character_with_the_next() {
+        let mut manager = multi_tap(Duration::from_secs(10));
+        let typed = record(|| {
+            for _ in 0..4 {
+                tap(&mut manager, &[AltLeft, KeyA]);
+            }
+        });
+        assert_eq!(typed_text(&typed), "á⌫à⌫ä⌫á");
+    }
+
+    #[test]
+    fn taps_start_over_after_the_tap_timeout() {
+        let mut manager = multi_tap(Duration::from_millis(20));
+        let typed = record(|| {
+            tap(&mut manager, &[AltLeft, KeyA]);
+            thread::sleep(Duration::from_millis(200));
+            tap(&mut manager, &[AltLeft, KeyA]);
+        });
+        assert_eq!(typed_text(&typed), "áá");
+    }
+
+    #[test]
+    fn taps_start_over_after_another_key() {
+        let mut manager = multi_tap(Duration::from_secs(10));
+        let typed = record(|| {
+            tap(&mut manager, &[AltLeft, KeyA]);
+            tap(&mut manager, &[KeyB]);
+            tap(&mut manager, &[AltLeft, KeyA]);
+        });
+        assert_eq!(typed_text(&typed), "áá");
+    }
+}
@@ -0,0 +1,370 @@
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::anyhow;
+
+use crate::keybindings::parser::parse_millis;
+use crate::keycode::KeyCode;
+use crate::r#type::hotkeymanager::LayerSwitch;
+
+/// What a binding in `[actions]` does. The sections bind [`Action::Unicode`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+    /// `unicode á ä à`, types the character, the next one with every tap in a row.
+    Unicode(Vec<char>),
+    /// `text "Díky!"`, types the text.
+    Text(String),
+    /// `chord ctrl+shift+t`, presses the keys together and lets them go.
+    Chord(Vec<KeyCode>),
+    /// `remap escape`, the binding is another key, held down as long as the binding is.
+    Remap(KeyCode),
+    /// `macro text "x"; chord ctrl+s`, the actions one after another.
+    Macro(Vec<Action>),
+    /// `command timeout=5000ms notepad.exe "my file.txt"`, starts the program with the arguments.
+    Command(CommandAction),
+    /// `layer toggle diacritic` or `layer hold diacritic`.
+    Layer(String, LayerSwitch),
+    /// `profile czech.ini`, switches to the bindings of another file.
+    Profile(PathBuf),
+}
+
+impl Action {
+    /// Whether the action sends keys, so the keys of the binding have to be released first.
+    pub fn types(&self) -> bool {
+        match self {
+            Action::Unicode(_) | Action::Text(_) | Action::Chord(_) => true,
+            Action::Macro(steps) => steps.iter().any(Action::types),
+            Action::Remap(_) | Action::Command(_) | Action::Layer(..) | Action::Profile(_) => false,
+        }
+    }
+}
+
+/// Program started by a binding, with the options written before it:
+/// `command cwd=/tmp env=LANG=C timeout=5000ms limit=1 kill_on_exit clear_env pass_keys type_output -- date +%F`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CommandAction {
+    /// The program and its arguments.
+    pub argv: Vec<String>,
+    pub cwd: Option<PathBuf>,
+    pub env: Vec<(String, String)>,
+    /// Starts without the environment of the daemon, only with `env` and the binding variables.
+    pub clear_env: bool,
+    /// Passes the binding and the pressed keys in `DIAKRITIKA_BINDING` and `DIAKRITIKA_KEYS`.
+    pub pass_keys: bool,
+    /// The program is killed when it runs longer.
+    pub timeout: Option<Duration>,
+    /// The program is killed when the daemon stops, otherwise it keeps running.
+    pub kill_on_exit: bool,
+    /// How many times the command can run at once, the binding does nothing while it's reached.
+    pub limit: Option<usize>,
+    /// Types what the program printed once it exits successfully.
+    pub type_output: bool,
+}
+
+impl FromStr for CommandAction {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut command = CommandAction::default();
+        let mut args = split_args(value)?.into_iter().peekable();
+        while let Some(arg) = args.peek() {
+            let (name, option) = arg.split_once('=').unwrap_or((arg, ""));
+            match name {
+                "--" => {
+                    args.next();
+                    break;
+                }
+                "cwd" if option.is_empty() => return Err(anyhow!("expected `cwd=directory`")),
+                "cwd" => command.cwd = Some(PathBuf::from(option)),
+                "env" => match option.split_once('=') {
+                    Some((name, value)) if !name.is_empty() => command.env.push((name.to_owned(), value.to_owned())),
+                    _ => return Err(anyhow!("expected `env=NAME=value` instead of `{}`", arg)),
+                },
+                "timeout" => command.timeout = Some(parse_millis(option).map_err(|e| anyhow!(e))?),
+                "limit" => match option.parse() {
+                    Ok(limit) if limit > 0 => command.limit = Some(limit),
+                    _ => return Err(anyhow!("`{}` is not a number of commands running at once", option)),
+                },
+                "kill_on_exit" => command.kill_on_exit = true,
+                "clear_env" => command.clear_env = true,
+                "pass_keys" => command.pass_keys = true,
+                "type_output" => command.type_output = true,
+                _ => break,
+            }
+            args.next();
+        }
+        command.argv = args.collect();
+        if command.argv.is_empty() {
+            return Err(anyhow!("expected the program to run after the options"));
+        }
+        Ok(command)
+    }
+}
+
+impl Display for CommandAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut words = Vec::new();
+        if let Some(cwd) = &self.cwd {
+            words.push(format!("cwd={:?}", cwd));
+        }
+        words.extend(self.env.iter().map(|(name, value)| format!("env={}={:?}", name, value)));
+        if let Some(timeout) = self.timeout {
+            words.push(format!("timeout={}ms", timeout.as_millis()));
+        }
+        if let Some(limit) = self.limit {
+            words.push(format!("limit={}", limit));
+        }
+        let flags = [
+            (self.kill_on_exit, "kill_on_exit"),
+            (self.clear_env, "clear_env"),
+            (self.pass_keys, "pass_keys"),
+            (self.type_output, "type_output"),
+        ];
+        words.extend(flags.into_iter().filter(|(on, _)| *on).map(|(_, flag)| flag.to_owned()));
+        if !words.is_empty() {
+            words.push("--".to_owned());
+        }
+        words.extend(self.argv.iter().map(|arg| format!("{:?}", arg)));
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+/// What an action line can start with.
+const KINDS: [&str; 8] = ["unicode", "text", "chord", "remap", "macro", "command", "layer", "profile"];
+
+/// Splits `text` at `separator`, except inside double quotes.
+fn split_outside_quotes(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut quoted, mut escaped, mut start) = (false, false, 0);
+    for (at, char) in text.char_indices() {
+        match char {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            _ if char == separator && !quoted => {
+                parts.push(&text[start..at]);
+                start = at + char.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// Words separated by whitespace. `"two words"` is one, with `\"` and `\\` inside.
+fn split_args(text: &str) -> anyhow::Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&char) = chars.peek() {
+        if char.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut arg = String::new();
+        let mut quoted = false;
+        while let Some(char) = chars.next() {
+            match char {
+                '"' => quoted = !quoted,
+                '\\' if quoted => arg.extend(chars.next()),
+                _ if char.is_whitespace() && !quoted => break,
+                _ => arg.push(char),
+            }
+        }
+        if quoted {
+            return Err(anyhow!("missing closing `\"`"));
+        }
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+/// Physical key, generic modifiers are the left one.
+fn key(name: &str) -> anyhow::Result<KeyCode> {
+    let key = KeyCode::from_name(name).map_err(|_| anyhow!("unknown key `{}`", name))?;
+    Ok(key.sides().map_or(key, |(left, _)| left))
+}
+
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let (kind, rest) = value.split_once(char::is_whitespace).unwrap_or((value, ""));
+        let rest = rest.trim();
+        if !KINDS.contains(&kind) {
+            return Err(anyhow!("unknown action `{}`, expected {}", kind, KINDS.join(", ")));
+        }
+        if rest.is_empty() {
+            return Err(anyhow!("`{}` is missing what to do, like `text \"Hello\"` or `chord ctrl+c`", kind));
+        }
+        match kind {
+            "unicode" => {
+                let mut chars = Vec::new();
+                for item in rest.split_whitespace() {
+                    let mut item_chars = item.chars();
+                    match (item_chars.next(), item_chars.next()) {
+                        (Some(char), None) => chars.push(char),
+                        _ => return Err(anyhow!("`{}` is not a single character", item)),
+                    }
+                }
+                Ok(Action::Unicode(chars))
+            }
+            "text" => match &split_args(rest)?[..] {
+                [text] => Ok(Action::Text(text.clone())),
+                _ => Err(anyhow!("expected the text in quotes like `text \"Hello there\"`")),
+            },
+            "chord" => rest.split('+').map(|name| key(name.trim())).collect::<anyhow::Result<_>>().map(Action::Chord),
+            "remap" => key(rest).map(Action::Remap),
+            "macro" => {
+                let mut steps = Vec::new();
+                for step in split_outside_quotes(rest, ';').into_iter().filter(|step| !step.trim().is_empty()) {
+                    match step.parse()? {
+                        Action::Layer(_, LayerSwitch::Hold) | Action::Remap(_) => {
+                            return Err(anyhow!("`{}` needs the keys to be held, it can't be part of a macro", step.trim()))
+                        }
+                        Action::Unicode(chars) if chars.len() > 1 => {
+                            return Err(anyhow!("`{}` cycles on every tap, a macro runs once, use `text`", step.trim()))
+                        }
+                        action => steps.push(action),
+                    }
+                }
+                Ok(Action::Macro(steps))
+            }
+            "command" => rest.parse().map(Action::Command),
+            "layer" => match rest.split_whitespace().collect::<Vec<_>>()[..] {
+                ["toggle", name] => Ok(Action::Layer(name.to_owned(), LayerSwitch::Toggle)),
+                ["hold", name] => Ok(Action::Layer(name.to_owned(), LayerSwitch::Hold)),
+                _ => Err(anyhow!("expected `layer toggle name` or `layer hold name`")),
+            },
+            "profile" => match &split_args(rest)?[..] {
+                [path] => Ok(Action::Profile(PathBuf::from(path))),
+                _ => Err(anyhow!("expected the bindings file of the profile")),
+            },
+            _ => unreachable!("{} is one of the kinds", kind),
+        }
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::Unicode(chars) => {
+                let chars = chars.iter().map(char::to_string).collect::<Vec<_>>();
+                write!(f, "unicode {}", chars.join(" "))
+            }
+            Action::Text(text) => write!(f, "text {:?}", text),
+            Action::Chord(keys) => {
+                let keys = keys.iter().map(|key| key.name()).collect::<Vec<_>>();
+                write!(f, "chord {}", keys.join("+"))
+            }
+            Action::Remap(key) => write!(f, "remap {}", key.name()),
+            Action::Macro(steps) => {
+                let steps = steps.iter().map(Action::to_string).collect::<Vec<_>>();
+                write!(f, "macro {}", steps.join("; "))
+            }
+            Action::Command(command) => write!(f, "command {}", command),
+            Action::Layer(name, LayerSwitch::Toggle) => write!(f, "layer toggle {}", name),
+            Action::Layer(name, LayerSwitch::Hold) => write!(f, "layer hold {}", name),
+            Action::Profile(path) => write!(f, "profile {:?}", path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keycode::KeyCode::{ControlLeft, Escape, KeyS, KeyT, ShiftLeft};
+
+    fn parse(text: &str) -> Action {
+        text.parse().unwrap_or_else(|e| panic!("`{}` doesn't parse: {:#}", text, e))
+    }
+
+    fn error(text: &str) -> String {
+        match text.parse::<Action>() {
+            Ok(action) => panic!("`{}` parses as {:?}", text, action),
+            Err(e) => format!("{:#}", e),
+        }
+    }
+
+    /// Every kind, the way `Display` writes it.
+    fn every_kind() -> Vec<(&'static str, Action)> {
+        let command = CommandAction {
+            argv: vec!["date".to_owned(), "+%F".to_owned()],
+            timeout: Some(Duration::from_millis(5000)),
+            ..CommandAction::default()
+        };
+        vec![
+            ("unicode á ä à", Action::Unicode(vec!['á', 'ä', 'à'])),
+            ("text \"Díky, \\\"já\\\"!\"", Action::Text("Díky, \"já\"!".to_owned())),
+            ("chord lcontrol+lshift+t", Action::Chord(vec![ControlLeft, ShiftLeft, KeyT])),
+            ("remap escape", Action::Remap(Escape)),
+            (
+                "macro text \"a; b\"; chord lcontrol+s",
+                Action::Macro(vec![Action::Text("a; b".to_owned()), Action::Chord(vec![ControlLeft, KeyS])]),
+            ),
+            ("command timeout=5000ms -- \"date\" \"+%F\"", Action::Command(command)),
+            ("layer toggle diacritic", Action::Layer("diacritic".to_owned(), LayerSwitch::Toggle)),
+            ("layer hold diacritic", Action::Layer("diacritic".to_owned(), LayerSwitch::Hold)),
+            ("profile \"my czech.ini\"", Action::Profile(PathBuf::from("my czech.ini"))),
+        ]
+    }
+
+    #[test]
+    fn every_kind_parses_and_displays() {
+        for (text, action) in every_kind() {
+            assert_eq!(parse(text), action);
+            assert_eq!(action.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn displayed_actions_parse_back() {
+        let written = [
+            "chord ctrl+shift+t",
+            "remap alt",
+            "text \"it's \\\"fine\\\"\"",
+            "macro unicode é;layer toggle x ;",
+            "command cwd=/tmp env=LANG=C limit=2 kill_on_exit clear_env pass_keys type_output date",
+            "profile czech.ini",
+        ];
+        for text in written {
+            let action = parse(text);
+            assert_eq!(parse(&action.to_string()), action, "{}", action);
+        }
+    }
+
+    #[test]
+    fn generic_modifiers_are_the_left_key() {
+        assert_eq!(parse("chord ctrl+shift+t"), Action::Chord(vec![ControlLeft, ShiftLeft, KeyT]));
+    }
+
+    #[test]
+    fn held_and_cycling_actions_are_no_macro_steps() {
+        assert!(error("macro text \"x\"; remap escape").contains("`remap escape` needs the keys to be held"));
+        assert!(error("macro layer hold diacritic").contains("`layer hold diacritic` needs the keys to be held"));
+        assert!(error("macro unicode á ä").contains("`unicode á ä` cycles on every tap"));
+        assert_eq!(parse("macro unicode á"), Action::Macro(vec![Action::Unicode(vec!['á'])]));
+    }
+
+    #[test]
+    fn broken_actions_are_errors() {
+        assert!(error("type x").starts_with("unknown action `type`"));
+        assert!(error("text").contains("is missing what to do"));
+        assert!(error("unicode ab").contains("`ab` is not a single character"));
+        assert!(error("chord ctrl+nokey").contains("unknown key `nokey`"));
+        assert!(error("text one two").contains("expected the text in quotes"));
+        assert!(error("text \"open").contains("missing closing"));
+        assert!(error("layer switch x").contains("expected `layer toggle name`"));
+    }
+
+    #[test]
+    fn quotes_keep_arguments_together() {
+        assert_eq!(split_args("  a  \"b c\" d\"e f\"g ").unwrap(), ["a", "b c", "de fg"]);
+        assert_eq!(split_args(r#""\"quoted\"" "back\\slash" "" x\y"#).unwrap(), ["\"quoted\"", "back\\slash", "", "x\\y"]);
+        assert!(split_args("").unwrap().is_empty());
+        assert!(split_args("\"a\" \"b").is_err());
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+use crate::keybindings::action::Action;
+use crate::keycode::KeyCode;
+use crate::layout::{character_in, layout_count};
+use crate::r#type::hotkeymanager::{BindingChar, BindingKeys, Key, KeySequence, LayerSwitch};
+use crate::r#type::Dump;
+
+/// What happens to a binding that can be triggered by the same keys as an earlier one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    /// Report an error and drop the later binding.
+    Error,
+    /// Report a warning and drop the later binding.
+    #[default]
+    Warning,
+    /// Keep the binding of the character listed first in `priority`. Conflicts between characters
+    /// without a priority are reported as warnings.
+    Priority,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "error" => Ok(ConflictPolicy::Error),
+            "warning" => Ok(ConflictPolicy::Warning),
+            "priority" => Ok(ConflictPolicy::Priority),
+            other => Err(anyhow!("unknown conflict policy `{}`, expected error, warning or priority", other)),
+        }
+    }
+}
+
+/// What a binding types.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BindingOutput {
+    Char(BindingChar),
+    /// Combining mark a dead key puts on the next letter.
+    DeadKey(char),
+    /// Characters a multi-tap binding cycles through.
+    Cycle(Vec<char>),
+    /// Turns the layer of this name on.
+    Layer(String, LayerSwitch),
+    /// Anything else of the `[actions]` section.
+    Action(Action),
+}
+
+impl BindingOutput {
+    /// The character `priority` ranks the binding by.
+    fn char(&self) -> BindingChar {
+        match self {
+            BindingOutput::Char(char) | BindingOutput::DeadKey(char) => *char,
+            BindingOutput::Cycle(chars) => chars.first().copied().unwrap_or_default(),
+            BindingOutput::Layer(..) | BindingOutput::Action(_) => BindingChar::default(),
+        }
+    }
+}
+
+impl Display for BindingOutput {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BindingOutput::Char(char) => write!(f, "'{}'", char),
+            BindingOutput::DeadKey(mark) => write!(f, "dead key U+{:04X}", *mark as u32),
+            BindingOutput::Cycle(chars) => {
+                let chars = chars.iter().map(char::to_string).collect::<Vec<_>>();
+                write!(f, "'{}'", chars.join(" "))
+            }
+            BindingOutput::Layer(layer, LayerSwitch::Toggle) => write!(f, "toggling layer {}", layer),
+            BindingOutput::Layer(layer, LayerSwitch::Hold) => write!(f, "holding layer {}", layer),
+            BindingOutput::Action(action) => write!(f, "`{}`", action),
+        }
+    }
+}
+
+/// Binding after the modifiers were expanded, with the line it comes from.
+#[derive(Debug, Clone)]
+pub struct ExpandedBinding {
+    pub output: BindingOutput,
+    pub steps: KeySequence,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Conflict found by [`ConflictResolver::resolve`].
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    /// The binding that didn't make it.
+    pub dropped: ExpandedBinding,
+    /// The binding it conflicts with.
+    pub kept: ExpandedBinding,
+    /// Both are the same keys, not just keys that type the same character.
+    pub duplicate: bool,
+    /// Whether the policy picked the winner. Otherwise the conflict has to be reported.
+    pub resolved: bool,
+}
+
+impl Conflict {
+    pub fn message(&self) -> String {
+        let dropped = &self.dropped;
+        let kept = &self.kept;
+        let (steps, kept_steps) = (dropped.steps.dump(), kept.steps.dump());
+        let (target, kept_target) = (&dropped.output, &kept.output);
+        if self.duplicate {
+            format!(
+                "{} for {} is already bound to {} on line {}, dropped",
+                steps, target, kept_target, kept.line
+            )
+        } else if dropped.steps.len() != kept.steps.len() {
+            format!(
+                "{} for {} starts with the same keys as {} for {} on line {}, dropped",
+                steps, target, kept_steps, kept_target, kept.line
+            )
+        } else {
+            format!(
+                "{} for {} is pressed with the same keys as {} for {} on line {}, dropped",
+                steps, target, kept_steps, kept_target, kept.line
+            )
+        }
+    }
+}
+
+/// Finds bindings that fire on the same pressed keys.
+///
+/// Characters are resolved to every physical key that types them, so `alt+;` and `alt+oem_1` are
+/// caught too. Only one layout is active at a time, so bindings conflict when they do in one of
+/// the layouts: `alt+s` and `alt+ы` don't, `alt+ж` and `alt+oem_1` do with a Russian layout.
+pub struct ConflictResolver<'a> {
+    policy: ConflictPolicy,
+    priority: &'a [BindingChar],
+    /// Keys typing the character, by layout.
+    characters: HashMap<String, Vec<BTreeSet<KeyCode>>>,
+    /// Layout the characters are resolved in.
+    layout: usize,
+}
+
+impl<'a> ConflictResolver<'a> {
+    pub fn new(policy: ConflictPolicy, priority: &'a [BindingChar]) -> Self {
+        Self {
+            policy,
+            priority,
+            characters: HashMap::new(),
+            layout: 0,
+        }
+    }
+
+    /// Keys that can be pressed for `key`.
+    fn physical_keys(&mut self, key: &Key) -> BTreeSet<KeyCode> {
+        match key {
+            Key::Code(code) => match code.sides() {
+                Some((left, right)) => BTreeSet::from([left, right]),
+                None => BTreeSet::from([*code]),
+            },
+            Key::Scancode(scancode) => KeyCode::physical().filter(|code| code.has_scancode(*scancode)).collect(),
+            Key::Character(char) => self.characters.entry(char.clone()).or_insert_with(|| {
+                (0..layout_count())
+                    .map(|layout| {
+                        KeyCode::physical()
+                            .filter(|code| character_in(layout, *code).as_ref() == Some(char))
+                            .collect()
+                    })
+                    .collect()
+            })[self.layout]
+                .clone(),
+        }
+    }
+
+    /// Whether some keys trigger both bindings in one of the layouts. A sequence also conflicts
+    /// with a shorter one it starts with, that one fires before the sequence is finished.
+    fn overlaps(&mut self, a: &ExpandedBinding, b: &ExpandedBinding) -> bool {
+        (0..layout_count()).any(|layout| {
+            self.layout = layout;
+            a.steps
+                .iter()
+                .zip(&b.steps)
+                .all(|(a, b)| self.step_overlaps(a, b))
+        })
+    }
+
+    /// Whether some set of pressed keys is both chords. An ordered chord is tried before the
+    /// unordered ones, so it never conflicts with them.
+    fn step_overlaps(&mut self, a: &BindingKeys, b: &BindingKeys) -> bool {
+        if a.keys.len() != b.keys.len() || a.ordered != b.ordered {
+            return false;
+        }
+        let ordered = a.ordered;
+        let a = a.keys.iter().map(|key| self.physical_keys(key)).collect::<Vec<_>>();
+        let b = b.keys.iter().map(|key| self.physical_keys(key)).collect::<Vec<_>>();
+        if ordered {
+            return a.iter().zip(&b).all(|(a, b)| !a.is_disjoint(b));
+        }
+        let covered = |from: &[BTreeSet<KeyCode>], to: &[BTreeSet<KeyCode>]| {
+            from.iter()
+                .all(|keys| to.iter().any(|other| !keys.is_disjoint(other)))
+        };
+        covered(&a, &b) && covered(&b, &a)
+    }
+
+    fn is_duplicate(a: &ExpandedBinding, b: &ExpandedBinding) -> bool {
+        a.steps.len() == b.steps.len()
+            && a.steps.iter().zip(&b.steps).all(|(a, b)| {
+                let (a, b) = (&a.keys, &b.keys);
+                a.len() == b.len() && a.iter().all(|key| b.contains(key)) && b.iter().all(|key| a.contains(key))
+            })
+    }
+
+    /// Lower is better, characters without a priority come last. Capital letters share the rank of
+    /// the small ones they were added for.
+    fn rank(&self, output: &BindingOutput) -> usize {
+        let char = output.char();
+        let lower = char.to_lowercase().next().unwrap_or(char);
+        self.priority
+            .iter()
+            .position(|known| *known == char || *known == lower)
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Drops the bindings that conflict with an earlier one or lose by priority. Bindings of the
+    /// same character that only became equal by expanding the modifiers are dropped quietly.
+    /// Every conflict is reported once per pair of lines.
+    pub fn resolve(&mut self, bindings: Vec<ExpandedBinding>) -> (Vec<ExpandedBinding>, Vec<Conflict>) {
+        let mut kept: Vec<ExpandedBinding> = Vec::new();
+        let mut conflicts = Vec::new();
+        let mut reported = HashSet::new();
+
+        for binding in bindings {
+            let clashing = (0..kept.len())
+                .filter(|index| self.overlaps(&kept[*index], &binding))
+                .collect::<Vec<_>>();
+            if clashing.is_empty() {
+                kept.push(binding);
+                continue;
+            }
+            if clashing.iter().all(|index| kept[*index].output == binding.output) {
+                log::trace!(target: "conflicts", "Dropping {} for {}, already bound", binding.steps.dump(), binding.output);
+                continue;
+            }
+
+            let wins = self.policy == ConflictPolicy::Priority
+                && clashing
+                    .iter()
+                    .all(|index| self.rank(&binding.output) < self.rank(&kept[*index].output));
+            if wins {
+                // Remove from the back, so the indexes stay valid
+                for index in clashing.into_iter().rev() {
+                    let loser = kept.remove(index);
+                    if reported.insert((loser.line, binding.line)) {
+                        let duplicate = Self::is_duplicate(&loser, &binding);
+                        conflicts.push(Conflict {
+                            dropped: loser,
+                            kept: binding.clone(),
+                            duplicate,
+                            resolved: true,
+                        });
+                    }
+                }
+                kept.push(binding);
+            } else {
+                let winner = &kept[clashing[0]];
+                if reported.insert((binding.line, winner.line)) {
+                    let resolved = self.policy == ConflictPolicy::Priority
+                        && self.rank(&winner.output) < self.rank(&binding.output);
+                    conflicts.push(Conflict {
+                        duplicate: Self::is_duplicate(winner, &binding),
+                        kept: winner.clone(),
+                        dropped: binding,
+                        resolved,
+                    });
+                }
+            }
+        }
+        (kept, conflicts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::keybindings::bindings_from_config;
+    use crate::keybindings::parser::{BindingsConfig, Severity};
+    use crate::layout::load_test_layouts;
+
+    fn parse(text: &str) -> BindingsConfig {
+        load_test_layouts();
+        BindingsConfig::parse(text, Path::new("test.ini"))
+    }
+
+    fn dropped_lines(config: &BindingsConfig) -> Vec<(usize, Severity)> {
+        config
+            .diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.line, diagnostic.severity))
+            .collect()
+    }
+
+    fn code(code: KeyCode) -> Key {
+        Key::Code(code)
+    }
+
+    #[test]
+    fn modifiers_expand_to_both_sides() {
+        let mut config = parse("[š]\nalt+s\n");
+        let bindings = bindings_from_config(&mut config);
+        assert!(config.diagnostics.is_empty(), "{:?}", config.diagnostics);
+
+        let s = Key::Character("s".to_owned());
+        let keys = |sequences: &Vec<KeySequence>| {
+            sequences
+                .iter()
+                .map(|steps| steps[0].keys.clone())
+                .collect::<HashSet<_>>()
+        };
+        assert_eq!(
+            keys(&bindings.chars[&'š']),
+            HashSet::from([
+                vec![code(KeyCode::AltLeft), s.clone()],
+                vec![code(KeyCode::AltRight), s.clone()],
+            ])
+        );
+        assert_eq!(bindings.chars[&'Š'].len(), 4);
+        assert!(keys(&bindings.chars[&'Š']).contains(&vec![code(KeyCode::ShiftRight), code(KeyCode::AltLeft), s]));
+    }
+
+    #[test]
+    fn expanded_bindings_conflict_with_one_side() {
+        let mut config = parse("[š]\nalt+s\n[đ]\nralt+s\n[ś]\nlalt>s\n");
+        let bindings = bindings_from_config(&mut config);
+        assert_eq!(dropped_lines(&config), vec![(4, Severity::Warning)]);
+        assert!(!bindings.chars.contains_key(&'đ'));
+        // Ordered chords are tried first, they don't conflict with the unordered ones
+        assert_eq!(bindings.chars[&'ś'].len(), 1);
+    }
+
+    #[test]
+    fn same_character_is_dropped_quietly() {
+        let mut config = parse("[š]\nalt+s\nralt+s\n");
+        let bindings = bindings_from_config(&mut config);
+        assert!(config.diagnostics.is_empty(), "{:?}", config.diagnostics);
+        assert_eq!(bindings.chars[&'š'].len(), 2);
+    }
+
+    #[test]
+    fn characters_conflict_with_their_keys_in_one_layout() {
+        let mut config = parse("[ö]\nalt+;\n[ø]\nalt+oem_1\n[ж]\nctrl+ж\n[ы]\nctrl+ы\n[s]\nctrl+s\n");
+        bindings_from_config(&mut config);
+        // `;` and `ж` are both on oem_1, `ы` and `s` are in different layouts
+        assert_eq!(dropped_lines(&config), vec![(4, Severity::Warning)]);
+
+        let mut config = parse("[ö]\nalt+ж\n[ø]\nalt+oem_1\n");
+        bindings_from_config(&mut config);
+        assert_eq!(dropped_lines(&config), vec![(4, Severity::Warning)]);
+    }
+
+    #[test]
+    fn scancodes_without_prefix_are_both_keys() {
+        let mut config = parse("[š]\n0x38+s\n[đ]\nralt+s\n[ś]\n0xe01c\n[ć]\n0x1c\n");
+        bindings_from_config(&mut config);
+        assert_eq!(dropped_lines(&config), vec![(4, Severity::Warning), (8, Severity::Warning)]);
+
+        let mut config = parse("[š]\n0xe038+s\n[đ]\nlalt+s\n");
+        bindings_from_config(&mut config);
+        assert!(config.diagnostics.is_empty(), "{:?}", config.diagnostics);
+    }
+
+    #[test]
+    fn sequences_conflict_with_their_start() {
+        let mut config = parse("[é]\nctrl+x, e\n[×]\nctrl+x\n[è]\nctrl+x, e, e\n");
+        let bindings = bindings_from_config(&mut config);
+        assert_eq!(dropped_lines(&config), vec![(4, Severity::Warning), (6, Severity::Warning)]);
+        assert_eq!(bindings.chars.keys().copied().collect::<Vec<_>>(), vec!['É', 'é']);
+    }
+
+    #[test]
+    fn policies() {
+        let mut config = parse("[options]\nconflicts = error\n[š]\nalt+s\n[đ]\nalt+s\n");
+        bindings_from_config(&mut config);
+        assert_eq!(dropped_lines(&config), vec![(6, Severity::Error)]);
+
+        let mut config = parse("[options]\nconflicts = priority\npriority = ś\n[š]\nalt+s\n[ś]\nalt+s\n");
+        let bindings = bindings_from_config(&mut config);
+        assert!(config.diagnostics.is_empty(), "{:?}", config.diagnostics);
+        assert!(!bindings.chars.contains_key(&'š'));
+        assert!(!bindings.chars.contains_key(&'Š'));
+        assert_eq!(bindings.chars[&'ś'].len(), 2);
+        assert_eq!(bindings.chars[&'Ś'].len(), 4);
+    }
+}
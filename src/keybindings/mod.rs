@@ -0,0 +1,282 @@
+pub mod action;
+pub mod conflicts;
+pub mod parser;
+pub mod watch;
+
+use std::collections::BTreeMap;
+
+use crate::keybindings::action::Action;
+use crate::keybindings::conflicts::{BindingOutput, ConflictPolicy, ConflictResolver, ExpandedBinding};
+use crate::keybindings::parser::{BindingSection, BindingsConfig, Severity};
+use crate::keycode::KeyCode;
+use crate::r#type::Dump;
+use crate::r#type::hotkeymanager::{
+    BindingKeys, CharKeyBindings, HasCharacter, HasShift, Key, KeyBinding, KeyBindings, KeySequence, LayerSwitch,
+};
+
+pub const BINDINGS_FILE: &str = "bindings.ini";
+/// Layers toggled on, kept across restarts with `remember_layers = on`.
+pub const LAYERS_FILE: &str = "layers.state";
+
+/// Everything a bindings file binds, ready for a [`HotkeyManager`](crate::r#type::hotkeymanager::HotkeyManager).
+#[derive(Debug, Default)]
+pub struct Bindings {
+    pub chars: CharKeyBindings,
+    /// Dead keys by the combining mark they put on the next letter.
+    pub dead_keys: BTreeMap<char, Vec<KeySequence>>,
+    /// Multi-tap bindings by the characters they cycle through.
+    pub multi_taps: BTreeMap<Vec<char>, Vec<KeySequence>>,
+    /// Characters of the layers by layer name.
+    pub layers: BTreeMap<String, CharKeyBindings>,
+    /// Keys turning the layers on.
+    pub layer_switches: BTreeMap<(String, LayerSwitch), Vec<KeySequence>>,
+    /// Keys of the `[actions]` section by what they do.
+    pub actions: BTreeMap<Action, Vec<KeySequence>>,
+}
+
+impl Dump for Bindings {
+    fn dump(&self) -> String {
+        let mut dump = self.chars.dump();
+        for (mark, sequences) in &self.dead_keys {
+            dump.push_str(&format!("\nDead key U+{:04X}:\n{}\n", *mark as u32, sequences.dump()));
+        }
+        for (chars, sequences) in &self.multi_taps {
+            let chars = chars.iter().map(char::to_string).collect::<Vec<_>>();
+            dump.push_str(&format!("\n{}:\n{}\n", chars.join(" "), sequences.dump()));
+        }
+        for ((layer, switch), sequences) in &self.layer_switches {
+            dump.push_str(&format!("\n{:?} layer {}:\n{}\n", switch, layer, sequences.dump()));
+        }
+        for (action, sequences) in &self.actions {
+            dump.push_str(&format!("\n{}:\n{}\n", action, sequences.dump()));
+        }
+        for (layer, chars) in &self.layers {
+            dump.push_str(&format!("\nLayer {}:\n{}\n", layer, chars.dump()));
+        }
+        dump
+    }
+}
+
+/// Expands the parsed bindings to both sides of the modifiers, adds the capital letters and drops
+/// the conflicting ones as the `conflicts` option says. Conflicts are added to the diagnostics.
+pub(crate) fn bindings_from_config(config: &mut BindingsConfig) -> Bindings {
+    let mut expanded = expand_sections(&config.sections);
+    for entry in &config.dead_keys {
+        expanded.extend(expand_sequence(&entry.steps).into_iter().map(|steps| ExpandedBinding {
+            output: BindingOutput::DeadKey(entry.mark),
+            steps,
+            line: entry.line,
+            column: entry.column,
+        }));
+    }
+    for entry in &config.multi_taps {
+        let origin = |chars, steps| ExpandedBinding {
+            output: BindingOutput::Cycle(chars),
+            steps,
+            line: entry.line,
+            column: entry.column,
+        };
+        let ex = expand_sequence(&entry.steps);
+        // Capitals only when all of them are small letters, like for sections
+        let upper = match entry.chars.iter().all(|char| char.is_lowercase()) {
+            true => capitalize(entry.chars[0], &ex),
+            false => Vec::new(),
+        };
+        let upper_chars = entry
+            .chars
+            .iter()
+            .map(|char| char.to_uppercase().next().unwrap_or(*char))
+            .collect::<Vec<_>>();
+        expanded.extend(ex.into_iter().map(|steps| origin(entry.chars.clone(), steps)));
+        expanded.extend(upper.into_iter().map(|steps| origin(upper_chars.clone(), steps)));
+    }
+    for layer in &config.layers {
+        let switches = [(LayerSwitch::Toggle, &layer.toggle), (LayerSwitch::Hold, &layer.hold)];
+        for (switch, entries) in switches {
+            for entry in entries {
+                expanded.extend(expand_sequence(&entry.steps).into_iter().map(|steps| ExpandedBinding {
+                    output: BindingOutput::Layer(layer.name.clone(), switch),
+                    steps,
+                    line: entry.line,
+                    column: entry.column,
+                }));
+            }
+        }
+    }
+    for entry in &config.actions {
+        let output = match &entry.action {
+            Action::Layer(layer, switch) => BindingOutput::Layer(layer.clone(), *switch),
+            action => BindingOutput::Action(action.clone()),
+        };
+        expanded.extend(expand_sequence(&entry.steps).into_iter().map(|steps| ExpandedBinding {
+            output: output.clone(),
+            steps,
+            line: entry.line,
+            column: entry.column,
+        }));
+    }
+
+    let mut bindings = Bindings::default();
+    for binding in resolve_conflicts(config, expanded) {
+        match binding.output {
+            BindingOutput::Char(char) => bindings.chars.entry(char).or_default().push(binding.steps),
+            BindingOutput::DeadKey(mark) => bindings.dead_keys.entry(mark).or_default().push(binding.steps),
+            BindingOutput::Cycle(chars) => bindings.multi_taps.entry(chars).or_default().push(binding.steps),
+            BindingOutput::Layer(layer, switch) => {
+                bindings.layer_switches.entry((layer, switch)).or_default().push(binding.steps)
+            }
+            BindingOutput::Action(action) => bindings.actions.entry(action).or_default().push(binding.steps),
+        }
+    }
+
+    // Bindings of a layer only conflict with each other
+    let layers = config
+        .layers
+        .iter()
+        .map(|layer| (layer.name.clone(), expand_sections(&layer.sections)))
+        .collect::<Vec<_>>();
+    for (name, expanded) in layers {
+        let chars = bindings.layers.entry(name).or_default();
+        for binding in resolve_conflicts(config, expanded) {
+            if let BindingOutput::Char(char) = binding.output {
+                chars.entry(char).or_default().push(binding.steps);
+            }
+        }
+    }
+    bindings
+}
+
+/// The bindings of the sections with both sides of the modifiers and the capital letters.
+fn expand_sections(sections: &[BindingSection]) -> Vec<ExpandedBinding> {
+    let mut expanded = Vec::new();
+    for section in sections {
+        let char_to_post = section.char;
+        let upper_char = char_to_post.to_uppercase().next().unwrap_or(char_to_post);
+
+        for entry in &section.entries {
+            let origin = |char, steps| ExpandedBinding {
+                output: BindingOutput::Char(char),
+                steps,
+                line: entry.line,
+                column: entry.column,
+            };
+            let ex = expand_sequence(&entry.steps);
+            let upper = if entry.capitalize {
+                capitalize(char_to_post, &ex)
+            } else {
+                Vec::new()
+            };
+            expanded.extend(ex.into_iter().map(|steps| origin(char_to_post, steps)));
+            expanded.extend(upper.into_iter().map(|steps| origin(upper_char, steps)));
+        }
+    }
+    expanded
+}
+
+/// Drops the conflicting bindings as the `conflicts` option says and reports them.
+fn resolve_conflicts(config: &mut BindingsConfig, mut expanded: Vec<ExpandedBinding>) -> Vec<ExpandedBinding> {
+    // Earlier lines win, also over merged sections and the special sections further down
+    expanded.sort_by_key(|binding| binding.line);
+
+    let policy = config.options.conflicts;
+    let (kept, conflicts) = ConflictResolver::new(policy, &config.options.priority).resolve(expanded);
+    for conflict in conflicts {
+        let severity = match policy {
+            _ if conflict.resolved => {
+                log::debug!(target: "conflicts", "{}", conflict.message());
+                continue;
+            }
+            ConflictPolicy::Error => Severity::Error,
+            ConflictPolicy::Warning | ConflictPolicy::Priority => Severity::Warning,
+        };
+        let (line, column) = (conflict.dropped.line, conflict.dropped.column);
+        config.diagnostic(severity, line, column, conflict.message());
+    }
+    kept
+}
+
+fn expand_modifiers(binding: &KeyBinding) -> Vec<KeyBinding> {
+    let mut expanded_bindings: Vec<KeyBinding> = vec![binding.clone()]; // Start with the original binding
+
+    for (i, key) in binding.iter().enumerate() {
+        if let Key::Code(code) = key {
+            let Some((left, right)) = code.sides() else {
+                continue;
+            };
+
+            // Create variations for each expanded binding and add them to the list
+            expanded_bindings = expanded_bindings
+                .iter()
+                .flat_map(|current_binding| {
+                    let mut with_left = current_binding.clone();
+                    with_left[i] = Key::Code(left);
+
+                    let mut with_right = current_binding.clone();
+                    with_right[i] = Key::Code(right);
+
+                    vec![with_left, with_right]
+                })
+                .collect();
+        }
+    }
+
+    expanded_bindings
+}
+
+/// Every combination of the expanded modifiers of all steps.
+fn expand_sequence(steps: &[BindingKeys]) -> Vec<KeySequence> {
+    steps.iter().fold(vec![KeySequence::new()], |sequences, step| {
+        let variants = expand_modifiers(&step.keys);
+        sequences
+            .iter()
+            .flat_map(|sequence| {
+                variants.iter().map(move |keys| {
+                    let mut sequence = sequence.clone();
+                    sequence.push(BindingKeys {
+                        keys: keys.clone(),
+                        ordered: step.ordered,
+                    });
+                    sequence
+                })
+            })
+            .collect()
+    })
+}
+
+/// Capital letter variants of the sequences, `shift` goes to the last step.
+fn capitalize(char_to_post: char, sequences: &[KeySequence]) -> Vec<KeySequence> {
+    let mut capitalized = Vec::new();
+    for sequence in sequences {
+        let Some((last, steps)) = sequence.split_last() else {
+            continue;
+        };
+        let upper = clone_with_modifier_if_needed(char_to_post, &vec![last.keys.clone()], KeyCode::Shift);
+        for keys in upper.iter().flat_map(expand_modifiers) {
+            let mut sequence = steps.to_vec();
+            sequence.push(BindingKeys {
+                keys,
+                ordered: last.ordered,
+            });
+            capitalized.push(sequence);
+        }
+    }
+    capitalized
+}
+
+fn clone_with_modifier_if_needed(
+    char_to_post: char,
+    bindings: &KeyBindings,
+    modifier: KeyCode,
+) -> KeyBindings {
+    let mut created_bingdings = Vec::new();
+
+    for binding in bindings {
+        if binding.has_character() && !binding.has_shift() && char_to_post.is_lowercase() {
+            let mut modified_binding = binding.clone();
+            modified_binding.insert(0, Key::Code(modifier)); // Or KeyCode::ShiftLeft, if preferred
+            created_bingdings.push(modified_binding);
+        }
+    }
+
+    created_bingdings
+}
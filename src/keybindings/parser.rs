@@ -0,0 +1,1041 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Context;
+
+use crate::keybindings::action::Action;
+use crate::keybindings::conflicts::ConflictPolicy;
+use crate::keycode::KeyCode;
+use crate::layout::compose::is_combining_mark;
+use crate::r#type::hotkeymanager::{BindingChar, BindingKeys, Key, KeyBinding, KeySequence};
+use crate::r#type::Dump;
+
+/// Name of the section with [`BindingOptions`] instead of bindings.
+pub const OPTIONS_SECTION: &str = "options";
+/// Name of the section with [`DeadKeyEntry`] lines.
+pub const DEAD_KEYS_SECTION: &str = "dead_keys";
+/// Name of the section with [`MultiTapEntry`] lines.
+pub const MULTI_TAP_SECTION: &str = "multi_tap";
+/// Name of the section with [`HoldEntry`] lines.
+pub const HOLD_SECTION: &str = "hold";
+/// Name of the section with [`DualRoleEntry`] lines.
+pub const DUAL_ROLE_SECTION: &str = "dual_role";
+/// Name of the section with [`ActionEntry`] lines.
+pub const ACTIONS_SECTION: &str = "actions";
+/// Start of the name of a [`LayerConfig`] section, `[layer diacritic]`.
+pub const LAYER_SECTION_PREFIX: &str = "layer ";
+/// How long `withhold = on` keeps keys.
+pub const DEFAULT_WITHHOLD_TIMEOUT: Duration = Duration::from_millis(1000);
+/// How long a sequence waits for its next step.
+pub const DEFAULT_SEQUENCE_TIMEOUT: Duration = Duration::from_millis(1000);
+/// How long a multi-tap binding waits for the next tap.
+pub const DEFAULT_MULTI_TAP_TIMEOUT: Duration = Duration::from_millis(600);
+/// How long a letter is held before `hold = on` offers its variants.
+pub const DEFAULT_HOLD_THRESHOLD: Duration = Duration::from_millis(500);
+/// How long a dual-role key has to be down to count as held.
+pub const DEFAULT_TAPPING_TERM: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Problem in a bindings file and where it is. Lines and columns start at 1.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn log(&self) {
+        match self.severity {
+            Severity::Warning => log::warn!(target: "bindings", "{}", self),
+            Severity::Error => log::error!(target: "bindings", "{}", self),
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}: {}",
+            self.file.display(),
+            self.line,
+            self.column,
+            self.severity,
+            self.message
+        )
+    }
+}
+
+/// `alt+s` or `alt+s = value`. Any value turns off the automatic capital letter binding.
+/// `alt>s` only fires when the keys are pressed in this order. `ralt, ', e` is a sequence, each
+/// step is released before the next one is pressed.
+#[derive(Debug, Clone)]
+pub struct BindingEntry {
+    pub steps: KeySequence,
+    pub capitalize: bool,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// `ralt+' = U+0301` in `[dead_keys]`. The keys put the combining mark on the next letter typed.
+#[derive(Debug, Clone)]
+pub struct DeadKeyEntry {
+    pub steps: KeySequence,
+    pub mark: char,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// `alt+a = á ä à` in `[multi_tap]`. Every tap within the timeout replaces the character typed
+/// by the one before with the next one.
+#[derive(Debug, Clone)]
+pub struct MultiTapEntry {
+    pub steps: KeySequence,
+    pub chars: Vec<char>,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// `e = é ě è ê ë` in `[hold]`, what holding the letter offers instead of the variants found in
+/// Unicode.
+#[derive(Debug, Clone)]
+pub struct HoldEntry {
+    pub char: char,
+    pub variants: Vec<char>,
+    pub line: usize,
+}
+
+/// `capital = escape` or `capital = escape rcontrol` in `[dual_role]`. Tapping the key types the
+/// first key, holding it holds the second one, the key itself when there is none.
+#[derive(Debug, Clone)]
+pub struct DualRoleEntry {
+    pub key: KeyCode,
+    pub tap: KeyCode,
+    pub hold: KeyCode,
+    pub line: usize,
+}
+
+/// `ctrl+alt+t = command alacritty` in `[actions]`, the keys do something else than typing a letter.
+#[derive(Debug, Clone)]
+pub struct ActionEntry {
+    pub steps: KeySequence,
+    pub action: Action,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// `[layer diacritic]`, bindings that only work while the layer is on and the keys turning it on.
+/// `s = š` binds `s` to `š`, with a capital letter like a section. `toggle = ralt+d` turns the
+/// layer on and off, `hold = capital` keeps it on while the key is down.
+#[derive(Debug, Clone)]
+pub struct LayerConfig {
+    pub name: String,
+    pub line: usize,
+    pub sections: Vec<BindingSection>,
+    pub toggle: Vec<BindingEntry>,
+    pub hold: Vec<BindingEntry>,
+}
+
+/// `[š]` and the bindings below it.
+#[derive(Debug, Clone)]
+pub struct BindingSection {
+    pub char: BindingChar,
+    pub line: usize,
+    pub entries: Vec<BindingEntry>,
+}
+
+/// `[options]`, `name = value` lines.
+#[derive(Debug, Clone)]
+pub struct BindingOptions {
+    /// `conflicts = error|warning|priority`
+    pub conflicts: ConflictPolicy,
+    /// `priority = і ї є`, the characters that win conflicts, best first.
+    pub priority: Vec<BindingChar>,
+    /// `withhold = on|off|300ms`, how long keys that can still become a hotkey are kept from
+    /// applications.
+    pub withhold: Option<Duration>,
+    /// `sequence_timeout = 1000ms`, how long a sequence waits for its next step.
+    pub sequence_timeout: Duration,
+    /// `multi_tap_timeout = 600ms`, how long a multi-tap binding waits for the next tap.
+    pub multi_tap_timeout: Duration,
+    /// `hold = on|off|500ms`, how long a letter is held before a digit can pick one of its variants.
+    pub hold: Option<Duration>,
+    /// `tapping_term = 200ms`, how long a dual-role key has to be down to count as held.
+    pub tapping_term: Duration,
+    /// `permissive_hold = on|off`, whether a key pressed and released while a dual-role key is
+    /// down makes it held even before the tapping term.
+    pub permissive_hold: bool,
+    /// `remember_layers = on|off`, whether the layers turned on stay on after a restart.
+    pub remember_layers: bool,
+    /// `sticky = on|off|alt shift`, the modifiers that latch for the next key when tapped, both
+    /// sides of them.
+    pub sticky: Vec<KeyCode>,
+}
+
+impl Default for BindingOptions {
+    fn default() -> Self {
+        Self {
+            conflicts: ConflictPolicy::default(),
+            priority: Vec::new(),
+            withhold: None,
+            sequence_timeout: DEFAULT_SEQUENCE_TIMEOUT,
+            multi_tap_timeout: DEFAULT_MULTI_TAP_TIMEOUT,
+            hold: None,
+            tapping_term: DEFAULT_TAPPING_TERM,
+            permissive_hold: false,
+            remember_layers: false,
+            sticky: Vec::new(),
+        }
+    }
+}
+
+/// Timeout in milliseconds like `300` or `300ms`.
+pub(crate) fn parse_millis(value: &str) -> Result<Duration, String> {
+    value
+        .strip_suffix("ms")
+        .unwrap_or(value)
+        .trim()
+        .parse()
+        .map(Duration::from_millis)
+        .map_err(|_| format!("`{}` is not a timeout in milliseconds", value))
+}
+
+/// `U+0301` or the combining mark itself.
+fn parse_mark(value: &str) -> Result<char, String> {
+    let mark = match value.strip_prefix("U+").or_else(|| value.strip_prefix("u+")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+        None => {
+            let mut chars = value.chars();
+            chars.next().filter(|_| chars.next().is_none())
+        }
+    };
+    mark.filter(|mark| is_combining_mark(*mark))
+        .ok_or_else(|| format!("`{}` is not a combining mark like U+0301", value))
+}
+
+/// `off`, `on` (the `default`) or a timeout in milliseconds like `300` or `300ms`.
+fn parse_switch(value: &str, default: Duration) -> Result<Option<Duration>, String> {
+    match value {
+        "off" | "false" | "no" => Ok(None),
+        "on" | "true" | "yes" => Ok(Some(default)),
+        _ => parse_millis(value)
+            .map(Some)
+            .map_err(|_| format!("`{}` is not on, off or a timeout in milliseconds", value)),
+    }
+}
+
+/// `on` or `off`.
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "off" | "false" | "no" => Ok(false),
+        "on" | "true" | "yes" => Ok(true),
+        _ => Err(format!("`{}` is not on or off", value)),
+    }
+}
+
+/// `off`, `on` for all the modifiers, or modifiers like `alt shift`. Generic ones stand for both sides.
+fn parse_modifiers(value: &str) -> Result<Vec<KeyCode>, String> {
+    let names = match value {
+        "off" | "false" | "no" => "",
+        "on" | "true" | "yes" => "shift control alt meta",
+        _ => value,
+    };
+    let mut modifiers = Vec::new();
+    for name in names.split_whitespace() {
+        let key = KeyCode::from_name(name).map_err(|_| format!("unknown key `{}`", name))?;
+        if !key.is_modifier() {
+            return Err(format!("`{}` is not a modifier", name));
+        }
+        match key.sides() {
+            Some((left, right)) => modifiers.extend([left, right]),
+            None => modifiers.push(key),
+        }
+    }
+    Ok(modifiers)
+}
+
+/// Single characters separated by whitespace, like `á ä à`.
+fn parse_chars(value: &str) -> Result<Vec<char>, String> {
+    let mut chars = Vec::new();
+    for item in value.split_whitespace() {
+        let mut item_chars = item.chars();
+        match (item_chars.next(), item_chars.next()) {
+            (Some(char), None) => chars.push(char),
+            _ => return Err(format!("`{}` is not a single character", item)),
+        }
+    }
+    Ok(chars)
+}
+
+/// Where the lines below a section header go.
+#[derive(Clone, Copy)]
+enum Target {
+    Section(usize),
+    Options,
+    DeadKeys,
+    MultiTap,
+    Hold,
+    DualRole,
+    Actions,
+    Layer(usize),
+}
+
+/// Parsed bindings file.
+///
+/// Broken lines are left out and described in `diagnostics`, everything else is kept, so the
+/// daemon can run with a partially broken file.
+#[derive(Debug, Default)]
+pub struct BindingsConfig {
+    pub file: PathBuf,
+    pub sections: Vec<BindingSection>,
+    pub dead_keys: Vec<DeadKeyEntry>,
+    pub multi_taps: Vec<MultiTapEntry>,
+    pub hold_variants: Vec<HoldEntry>,
+    pub dual_roles: Vec<DualRoleEntry>,
+    pub actions: Vec<ActionEntry>,
+    pub layers: Vec<LayerConfig>,
+    pub options: BindingOptions,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Column of the byte offset `at` in `line`.
+fn column(line: &str, at: usize) -> usize {
+    line[..at].chars().count() + 1
+}
+
+/// Drops `;` and `#` comments, either on their own line or after whitespace. After a key
+/// separator they are keys, like in `ralt, ;` and `alt+ #`.
+fn strip_comment(line: &str) -> &str {
+    let mut previous = None;
+    let mut last_visible = None;
+    for (at, char) in line.char_indices() {
+        if (char == ';' || char == '#')
+            && previous.is_none_or(char::is_whitespace)
+            && !last_visible.is_some_and(|last| matches!(last, ',' | '+' | '>'))
+        {
+            return &line[..at];
+        }
+        previous = Some(char);
+        if !char.is_whitespace() {
+            last_visible = Some(char);
+        }
+    }
+    line
+}
+
+/// Byte offset of the `=` separating keys from the value. `alt+=` and `ralt, =` bind the `=` key.
+fn find_separator(line: &str) -> Option<usize> {
+    line.char_indices()
+        .find(|(at, char)| *char == '=' && *at > 0 && !line[..*at].trim_end().ends_with(['+', ',']))
+        .map(|(at, _)| at)
+}
+
+/// Splits `ralt, ', e` into its steps, with their byte offsets. `,` is a key at the start of a
+/// step and after `+` or `>`, like in `alt+,`.
+fn split_steps(text: &str) -> Vec<(usize, &str)> {
+    let mut steps = Vec::new();
+    let mut step_start = 0;
+    for (at, char) in text.char_indices() {
+        let step = text[step_start..at].trim();
+        if char == ',' && !step.is_empty() && !step.ends_with(['+', '>']) {
+            steps.push((step_start, &text[step_start..at]));
+            step_start = at + 1;
+        }
+    }
+    steps.push((step_start, &text[step_start..]));
+    steps
+}
+
+impl BindingsConfig {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("Can't read {:?}", path))?;
+        Ok(Self::parse(&text, path))
+    }
+
+    pub fn parse(text: &str, file: &Path) -> Self {
+        let mut config = Self {
+            file: file.to_owned(),
+            ..Default::default()
+        };
+        let mut current: Option<Target> = None;
+        let mut skipping_section = false;
+
+        for (index, raw) in text.lines().enumerate() {
+            let line = index + 1;
+            let raw = raw.strip_prefix('\u{feff}').unwrap_or(raw);
+            let content = strip_comment(raw);
+            let Some(start) = content.find(|char: char| !char.is_whitespace()) else {
+                continue;
+            };
+            let trimmed = content.trim_end();
+
+            if trimmed[start..].starts_with('[') {
+                current = config.section(raw, line, start, &trimmed[start..]);
+                skipping_section = current.is_none();
+                continue;
+            }
+
+            let section = match current {
+                Some(Target::Section(section)) => section,
+                Some(Target::Options) => {
+                    config.option(raw, line, start, trimmed);
+                    continue;
+                }
+                Some(Target::DeadKeys) => {
+                    config.dead_key(raw, line, start, trimmed);
+                    continue;
+                }
+                Some(Target::MultiTap) => {
+                    config.multi_tap(raw, line, start, trimmed);
+                    continue;
+                }
+                Some(Target::Hold) => {
+                    config.hold(raw, line, start, trimmed);
+                    continue;
+                }
+                Some(Target::DualRole) => {
+                    config.dual_role(raw, line, start, trimmed);
+                    continue;
+                }
+                Some(Target::Actions) => {
+                    config.action(raw, line, start, trimmed);
+                    continue;
+                }
+                Some(Target::Layer(layer)) => {
+                    config.layer_line(layer, raw, line, start, trimmed);
+                    continue;
+                }
+                None => {
+                    if !skipping_section {
+                        config.error(line, column(raw, start), "binding outside of a section, line skipped".to_owned());
+                    }
+                    continue;
+                }
+            };
+
+            if let Some(entry) = config.entry(raw, line, start, trimmed) {
+                let entries = &mut config.sections[section].entries;
+                if let Some(existing) = entries.iter().find(|existing| existing.steps == entry.steps) {
+                    let message = format!("{} is already bound on line {}", entry.steps.dump(), existing.line);
+                    config.warning(line, column(raw, start), message);
+                } else {
+                    entries.push(entry);
+                }
+            }
+        }
+        config
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    pub(crate) fn diagnostic(&mut self, severity: Severity, line: usize, column: usize, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            file: self.file.clone(),
+            line,
+            column,
+            message,
+        });
+    }
+
+    fn error(&mut self, line: usize, column: usize, message: String) {
+        self.diagnostic(Severity::Error, line, column, message)
+    }
+
+    fn warning(&mut self, line: usize, column: usize, message: String) {
+        self.diagnostic(Severity::Warning, line, column, message)
+    }
+
+    /// `[č]`. Sections of the same character are merged.
+    fn section(&mut self, raw: &str, line: usize, start: usize, header: &str) -> Option<Target> {
+        let Some(name) = header[1..].strip_suffix(']') else {
+            self.error(line, column(raw, start + header.len()), "expected `]`, section skipped".to_owned());
+            return None;
+        };
+        if name.trim() == OPTIONS_SECTION {
+            return Some(Target::Options);
+        }
+        if name.trim() == DEAD_KEYS_SECTION {
+            return Some(Target::DeadKeys);
+        }
+        if name.trim() == MULTI_TAP_SECTION {
+            return Some(Target::MultiTap);
+        }
+        if name.trim() == HOLD_SECTION {
+            return Some(Target::Hold);
+        }
+        if name.trim() == DUAL_ROLE_SECTION {
+            return Some(Target::DualRole);
+        }
+        if name.trim() == ACTIONS_SECTION {
+            return Some(Target::Actions);
+        }
+        if let Some(layer) = name.trim().strip_prefix(LAYER_SECTION_PREFIX) {
+            return self.layer(raw, line, start, layer.trim());
+        }
+        let mut chars = name.trim().chars();
+        let (Some(char), None) = (chars.next(), chars.next()) else {
+            let message = format!("section name `{}` has to be a single character, section skipped", name.trim());
+            self.error(line, column(raw, start + 1), message);
+            return None;
+        };
+        if let Some(index) = self.sections.iter().position(|section| section.char == char) {
+            let message = format!("section [{}] is already defined on line {}, merging", char, self.sections[index].line);
+            self.warning(line, column(raw, start), message);
+            return Some(Target::Section(index));
+        }
+        self.sections.push(BindingSection {
+            char,
+            line,
+            entries: Vec::new(),
+        });
+        Some(Target::Section(self.sections.len() - 1))
+    }
+
+    /// `conflicts = priority` in `[options]`.
+    fn option(&mut self, raw: &str, line: usize, start: usize, trimmed: &str) {
+        let Some((name, value)) = trimmed[start..].split_once('=') else {
+            self.error(line, column(raw, start), "expected `name = value`, line skipped".to_owned());
+            return;
+        };
+        let value_column = column(raw, trimmed.len() - value.trim_start().len());
+        let (name, value) = (name.trim(), value.trim());
+        match name {
+            "conflicts" => match value.parse() {
+                Ok(policy) => self.options.conflicts = policy,
+                Err(e) => self.error(line, value_column, format!("{}, line skipped", e)),
+            },
+            "priority" => {
+                self.options.priority = value
+                    .split_whitespace()
+                    .flat_map(|chars| chars.chars())
+                    .collect()
+            }
+            "withhold" => match parse_switch(value, DEFAULT_WITHHOLD_TIMEOUT) {
+                Ok(withhold) => self.options.withhold = withhold,
+                Err(message) => self.error(line, value_column, format!("{}, line skipped", message)),
+            },
+            "sequence_timeout" => match parse_millis(value) {
+                Ok(timeout) => self.options.sequence_timeout = timeout,
+                Err(message) => self.error(line, value_column, format!("{}, line skipped", message)),
+            },
+            "multi_tap_timeout" => match parse_millis(value) {
+                Ok(timeout) => self.options.multi_tap_timeout = timeout,
+                Err(message) => self.error(line, value_column, format!("{}, line skipped", message)),
+            },
+            "hold" => match parse_switch(value, DEFAULT_HOLD_THRESHOLD) {
+                Ok(threshold) => self.options.hold = threshold,
+                Err(message) => self.error(line, value_column, format!("{}, line skipped", message)),
+            },
+            "tapping_term" => match parse_millis(value) {
+                Ok(term) => self.options.tapping_term = term,
+                Err(message) => self.error(line, value_column, format!("{}, line skipped", message)),
+            },
+            "permissive_hold" => match parse_bool(value) {
+                Ok(permissive) => self.options.permissive_hold = permissive,
+                Err(message) => self.error(line, value_column, format!("{}, line skipped", message)),
+            },
+            "sticky" => match parse_modifiers(value) {
+                Ok(modifiers) => self.options.sticky = modifiers,
+                Err(message) => self.error(line, value_column, format!("{}, line skipped", message)),
+            },
+            "remember_layers" => match parse_bool(value) {
+                Ok(remember) => self.options.remember_layers = remember,
+                Err(message) => self.error(line, value_column, format!("{}, line skipped", message)),
+            },
+            _ => self.warning(line, column(raw, start), format!("unknown option `{}`, line skipped", name)),
+        }
+    }
+
+    /// `ralt+' = U+0301` in `[dead_keys]`.
+    fn dead_key(&mut self, raw: &str, line: usize, start: usize, trimmed: &str) {
+        let Some(separator) = find_separator(&trimmed[start..]) else {
+            self.error(line, column(raw, start), "expected `keys = U+0301`, line skipped".to_owned());
+            return;
+        };
+        let value = trimmed[start + separator + 1..].trim();
+        let mark = match parse_mark(value) {
+            Ok(mark) => mark,
+            Err(message) => {
+                let value_column = column(raw, trimmed.len() - value.len());
+                self.error(line, value_column, format!("{}, line skipped", message));
+                return;
+            }
+        };
+        let Some(entry) = self.entry(raw, line, start, trimmed) else {
+            return;
+        };
+        if let Some(existing) = self.dead_keys.iter().find(|existing| existing.steps == entry.steps) {
+            let message = format!("{} is already a dead key on line {}", entry.steps.dump(), existing.line);
+            self.warning(line, entry.column, message);
+            return;
+        }
+        self.dead_keys.push(DeadKeyEntry {
+            steps: entry.steps,
+            mark,
+            line,
+            column: entry.column,
+        });
+    }
+
+    /// `alt+a = á ä à` in `[multi_tap]`.
+    fn multi_tap(&mut self, raw: &str, line: usize, start: usize, trimmed: &str) {
+        let Some(separator) = find_separator(&trimmed[start..]) else {
+            self.error(line, column(raw, start), "expected `keys = á ä à`, line skipped".to_owned());
+            return;
+        };
+        let value = trimmed[start + separator + 1..].trim();
+        let value_column = column(raw, trimmed.len() - value.len());
+        let chars = match parse_chars(value) {
+            Ok(chars) => chars,
+            Err(message) => {
+                self.error(line, value_column, format!("{}, line skipped", message));
+                return;
+            }
+        };
+        if chars.is_empty() {
+            self.error(line, value_column, "expected the characters to cycle through, line skipped".to_owned());
+            return;
+        }
+        let Some(entry) = self.entry(raw, line, start, trimmed) else {
+            return;
+        };
+        if let Some(existing) = self.multi_taps.iter().find(|existing| existing.steps == entry.steps) {
+            let message = format!("{} is already a multi-tap binding on line {}", entry.steps.dump(), existing.line);
+            self.warning(line, entry.column, message);
+            return;
+        }
+        self.multi_taps.push(MultiTapEntry {
+            steps: entry.steps,
+            chars,
+            line,
+            column: entry.column,
+        });
+    }
+
+    /// `e = é ě è` in `[hold]`.
+    fn hold(&mut self, raw: &str, line: usize, start: usize, trimmed: &str) {
+        let Some((letter, value)) = trimmed[start..].split_once('=') else {
+            self.error(line, column(raw, start), "expected `e = é ě è`, line skipped".to_owned());
+            return;
+        };
+        let value_column = column(raw, trimmed.len() - value.trim_start().len());
+        let mut letters = letter.trim().chars();
+        let (Some(char), None) = (letters.next(), letters.next()) else {
+            let message = format!("`{}` is not a single letter, line skipped", letter.trim());
+            self.error(line, column(raw, start), message);
+            return;
+        };
+        let variants = match parse_chars(value.trim()) {
+            Ok(variants) if !variants.is_empty() => variants,
+            Ok(_) => {
+                self.error(line, value_column, "expected the variants of the letter, line skipped".to_owned());
+                return;
+            }
+            Err(message) => {
+                self.error(line, value_column, format!("{}, line skipped", message));
+                return;
+            }
+        };
+        if let Some(existing) = self.hold_variants.iter().find(|existing| existing.char == char) {
+            let message = format!("variants of `{}` are already listed on line {}", char, existing.line);
+            self.warning(line, column(raw, start), message);
+            return;
+        }
+        self.hold_variants.push(HoldEntry { char, variants, line });
+    }
+
+    /// `ctrl+alt+t = command alacritty` in `[actions]`.
+    fn action(&mut self, raw: &str, line: usize, start: usize, trimmed: &str) {
+        let Some(separator) = find_separator(&trimmed[start..]) else {
+            self.error(line, column(raw, start), "expected `keys = action`, line skipped".to_owned());
+            return;
+        };
+        let value = trimmed[start + separator + 1..].trim();
+        let value_column = column(raw, trimmed.len() - value.len());
+        let action = match value.parse::<Action>() {
+            Ok(action) => action,
+            Err(e) => {
+                self.error(line, value_column, format!("{}, line skipped", e));
+                return;
+            }
+        };
+        let Some(entry) = self.entry(raw, line, start, trimmed) else {
+            return;
+        };
+        if let Some(existing) = self.actions.iter().find(|existing| existing.steps == entry.steps) {
+            let message = format!("{} is already an action on line {}", entry.steps.dump(), existing.line);
+            self.warning(line, entry.column, message);
+            return;
+        }
+        self.actions.push(ActionEntry {
+            steps: entry.steps,
+            action,
+            line,
+            column: entry.column,
+        });
+    }
+
+    /// `capital = escape rcontrol` in `[dual_role]`. `alt` sets up both of the keys.
+    fn dual_role(&mut self, raw: &str, line: usize, start: usize, trimmed: &str) {
+        let Some((name, value)) = trimmed[start..].split_once('=') else {
+            self.error(line, column(raw, start), "expected `key = tap_key [hold_key]`, line skipped".to_owned());
+            return;
+        };
+        let value_column = column(raw, trimmed.len() - value.trim_start().len());
+        let keys = match KeyCode::from_name(name.trim()) {
+            Ok(key) => key.sides().map_or(vec![key], |(left, right)| vec![left, right]),
+            Err(_) => {
+                self.error(line, column(raw, start), format!("unknown key `{}`, line skipped", name.trim()));
+                return;
+            }
+        };
+        let mut roles = Vec::new();
+        for part in value.split_whitespace() {
+            match KeyCode::from_name(part) {
+                Ok(key) => roles.push(key),
+                Err(_) => {
+                    self.error(line, value_column, format!("unknown key `{}`, line skipped", part));
+                    return;
+                }
+            }
+        }
+        let (tap, hold) = match roles[..] {
+            [tap] => (tap, None),
+            [tap, hold] => (tap, Some(hold)),
+            _ => {
+                self.error(line, value_column, "expected the key to tap and the one to hold, line skipped".to_owned());
+                return;
+            }
+        };
+        // `alt = alt` keeps the side of the key, other generic keys can't be typed
+        let side = |role: KeyCode, key: KeyCode| match role.sides() {
+            Some((left, right)) if key == left || key == right => Ok(key),
+            Some((left, right)) => Err(format!("`{}` has to be {} or {}, line skipped", role.name(), left.name(), right.name())),
+            None => Ok(role),
+        };
+        for key in keys {
+            let (tap, hold) = match (side(tap, key), side(hold.unwrap_or(key), key)) {
+                (Ok(tap), Ok(hold)) => (tap, hold),
+                (Err(message), _) | (_, Err(message)) => {
+                    self.error(line, value_column, message);
+                    return;
+                }
+            };
+            if let Some(existing) = self.dual_roles.iter().find(|existing| existing.key == key) {
+                let message = format!("{} already has two roles on line {}", key.name(), existing.line);
+                self.warning(line, column(raw, start), message);
+                continue;
+            }
+            self.dual_roles.push(DualRoleEntry {
+                key,
+                tap,
+                hold,
+                line,
+            });
+        }
+    }
+
+    /// `[layer diacritic]`. Layers of the same name are merged.
+    fn layer(&mut self, raw: &str, line: usize, start: usize, name: &str) -> Option<Target> {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            let message = format!("layer name `{}` has to be a single word, section skipped", name);
+            self.error(line, column(raw, start + 1), message);
+            return None;
+        }
+        if let Some(index) = self.layers.iter().position(|layer| layer.name == name) {
+            let message = format!("layer {} is already defined on line {}, merging", name, self.layers[index].line);
+            self.warning(line, column(raw, start), message);
+            return Some(Target::Layer(index));
+        }
+        self.layers.push(LayerConfig {
+            name: name.to_owned(),
+            line,
+            sections: Vec::new(),
+            toggle: Vec::new(),
+            hold: Vec::new(),
+        });
+        Some(Target::Layer(self.layers.len() - 1))
+    }
+
+    /// `s = š`, `toggle = ralt+d` or `hold = capital` in a layer.
+    fn layer_line(&mut self, layer: usize, raw: &str, line: usize, start: usize, trimmed: &str) {
+        let Some(separator) = find_separator(&trimmed[start..]) else {
+            self.error(line, column(raw, start), "expected `keys = š`, line skipped".to_owned());
+            return;
+        };
+        let value = trimmed[start + separator + 1..].trim();
+        let value_start = trimmed.len() - value.len();
+        let switch = trimmed[start..start + separator].trim();
+        if switch == "toggle" || switch == "hold" {
+            if value.is_empty() {
+                self.error(line, column(raw, value_start), "expected the keys, line skipped".to_owned());
+                return;
+            }
+            let Some(entry) = self.entry(raw, line, value_start, trimmed) else {
+                return;
+            };
+            let layer = &mut self.layers[layer];
+            match switch {
+                "toggle" => layer.toggle.push(entry),
+                _ => layer.hold.push(entry),
+            }
+            return;
+        }
+
+        let mut chars = value.chars();
+        let (Some(char), None) = (chars.next(), chars.next()) else {
+            let message = format!("`{}` is not a single character, line skipped", value);
+            self.error(line, column(raw, value_start), message);
+            return;
+        };
+        let Some(mut entry) = self.entry(raw, line, start, trimmed) else {
+            return;
+        };
+        // The value is the character here, so the capital letter comes like in a section
+        entry.capitalize = true;
+        let sections = &mut self.layers[layer].sections;
+        let index = match sections.iter().position(|section| section.char == char) {
+            Some(index) => index,
+            None => {
+                sections.push(BindingSection {
+                    char,
+                    line,
+                    entries: Vec::new(),
+                });
+                sections.len() - 1
+            }
+        };
+        let entries = &mut self.layers[layer].sections[index].entries;
+        if let Some(existing) = entries.iter().find(|existing| existing.steps == entry.steps) {
+            let message = format!("{} is already bound on line {}", entry.steps.dump(), existing.line);
+            self.warning(line, entry.column, message);
+            return;
+        }
+        entries.push(entry);
+    }
+
+    /// `lshift+alt+b+0x18`, the ordered `alt>s` or the sequence `ctrl+x, 8, e`, with an optional
+    /// `= value`.
+    fn entry(&mut self, raw: &str, line: usize, start: usize, trimmed: &str) -> Option<BindingEntry> {
+        let (keys_end, value) = match find_separator(&trimmed[start..]) {
+            Some(separator) => (start + separator, Some(trimmed[start + separator + 1..].trim())),
+            None => (trimmed.len(), None),
+        };
+
+        let mut steps = KeySequence::new();
+        let mut broken = false;
+        for (step_start, text) in split_steps(&raw[start..keys_end]) {
+            match self.step(raw, line, start + step_start, text) {
+                Some(step) => steps.push(step),
+                None => broken = true,
+            }
+        }
+
+        (!broken).then_some(BindingEntry {
+            steps,
+            capitalize: value.is_none(),
+            line,
+            column: column(raw, start),
+        })
+    }
+
+    /// One chord of an entry, `text` starts at the byte offset `start` of `raw`.
+    fn step(&mut self, raw: &str, line: usize, start: usize, text: &str) -> Option<BindingKeys> {
+        // `alt+>` binds the `>` key, `>` only separates keys when there is no `+`
+        let ordered = !text.contains('+') && text.trim() != ">" && text.contains('>');
+        let separator = if ordered { '>' } else { '+' };
+
+        let mut keys = KeyBinding::new();
+        let mut broken = false;
+        let mut offset = start;
+        for part in text.split(separator) {
+            let part_start = offset + (part.len() - part.trim_start().len());
+            offset += part.len() + 1;
+            if part.trim().chars().count() > 1 && part.contains('>') {
+                self.error(line, column(raw, part_start), "`+` and `>` can't be mixed, line skipped".to_owned());
+                broken = true;
+                continue;
+            }
+            match Self::key(part.trim()) {
+                Ok(key) => keys.push(key),
+                Err(message) => {
+                    self.error(line, column(raw, part_start), format!("{}, line skipped", message));
+                    broken = true;
+                }
+            }
+        }
+
+        (!broken).then_some(BindingKeys { keys, ordered })
+    }
+
+    /// Scancode like `0x1c`, key name like `lalt` or a single character.
+    fn key(part: &str) -> Result<Key, String> {
+        if part.is_empty() {
+            return Err("missing key".to_owned());
+        }
+        if let Some(hex) = part.strip_prefix("0x") {
+            return u32::from_str_radix(hex, 16)
+                .map(Key::Scancode)
+                .map_err(|_| format!("`{}` is not a scancode", part));
+        }
+        if part.chars().count() > 1 {
+            return KeyCode::from_name(part)
+                .map(Key::Code)
+                .map_err(|_| format!("unknown key `{}`", part));
+        }
+        // Characters are matched against what the key types without shift
+        Ok(Key::Character(part.to_lowercase()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> BindingsConfig {
+        BindingsConfig::parse(text, Path::new("test.ini"))
+    }
+
+    fn code(name: &str) -> Key {
+        Key::Code(KeyCode::from_name(name).expect("Known key"))
+    }
+
+    fn character(char: &str) -> Key {
+        Key::Character(char.to_owned())
+    }
+
+    fn chord(keys: Vec<Key>) -> BindingKeys {
+        BindingKeys { keys, ordered: false }
+    }
+
+    fn messages(config: &BindingsConfig) -> Vec<(usize, Severity)> {
+        config
+            .diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.line, diagnostic.severity))
+            .collect()
+    }
+
+    #[test]
+    fn comma_after_plus_is_a_key() {
+        let config = parse("[é]\nalt+,\nralt, ', e\n");
+        assert!(config.diagnostics.is_empty(), "{:?}", config.diagnostics);
+        let entries = &config.sections[0].entries;
+        assert_eq!(entries[0].steps, vec![chord(vec![code("alt"), character(",")])]);
+        assert_eq!(
+            entries[1].steps,
+            vec![chord(vec![code("ralt")]), chord(vec![character("'")]), chord(vec![character("e")])]
+        );
+    }
+
+    #[test]
+    fn equals_after_plus_is_a_key() {
+        assert_eq!(find_separator("alt+="), None);
+        assert_eq!(find_separator("alt+= = x"), Some(6));
+        assert_eq!(find_separator("ralt, = = x"), Some(8));
+
+        let config = parse("[≠]\nalt+=\nalt+s =\n[dead_keys]\nralt+= = U+0301\n");
+        assert!(config.diagnostics.is_empty(), "{:?}", config.diagnostics);
+        let entries = &config.sections[0].entries;
+        assert_eq!(entries[0].steps, vec![chord(vec![code("alt"), character("=")])]);
+        assert!(entries[0].capitalize);
+        assert!(!entries[1].capitalize);
+        assert_eq!(config.dead_keys[0].steps, vec![chord(vec![code("ralt"), character("=")])]);
+        assert_eq!(config.dead_keys[0].mark, '\u{301}');
+    }
+
+    #[test]
+    fn comments_are_stripped() {
+        let config = parse("; comment\n# comment\n[š] ; trailing\nalt+s # also\nalt+;\n");
+        assert!(config.diagnostics.is_empty(), "{:?}", config.diagnostics);
+        assert_eq!(config.sections.len(), 1);
+        let entries = &config.sections[0].entries;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].steps, vec![chord(vec![code("alt"), character("s")])]);
+        assert_eq!(entries[1].steps, vec![chord(vec![code("alt"), character(";")])]);
+    }
+
+    #[test]
+    fn comment_characters_after_a_separator_are_keys() {
+        let config = parse("[š]\nralt, ;\nalt+#\nctrl+ # ; comment\nralt, # ; comment\n");
+        assert!(config.diagnostics.is_empty(), "{:?}", config.diagnostics);
+        let entries = &config.sections[0].entries;
+        assert_eq!(entries[0].steps, vec![chord(vec![code("ralt")]), chord(vec![character(";")])]);
+        assert_eq!(entries[1].steps, vec![chord(vec![code("alt"), character("#")])]);
+        assert_eq!(entries[2].steps, vec![chord(vec![code("ctrl"), character("#")])]);
+        assert_eq!(entries[3].steps, vec![chord(vec![code("ralt")]), chord(vec![character("#")])]);
+    }
+
+    #[test]
+    fn byte_order_mark_is_skipped() {
+        let config = parse("\u{feff}[š]\nalt+s\n");
+        assert!(config.diagnostics.is_empty(), "{:?}", config.diagnostics);
+        assert_eq!(config.sections[0].char, 'š');
+        assert_eq!(config.sections[0].entries.len(), 1);
+    }
+
+    #[test]
+    fn sections_of_one_character_are_merged() {
+        let config = parse("[š]\nalt+s\n[č]\nalt+c\n[š]\nralt+s\nalt+s\n");
+        assert_eq!(messages(&config), vec![(5, Severity::Warning), (7, Severity::Warning)]);
+        assert_eq!(config.sections.len(), 2);
+        let entries = &config.sections[0].entries;
+        assert_eq!(entries.iter().map(|entry| entry.line).collect::<Vec<_>>(), vec![2, 6]);
+    }
+
+    #[test]
+    fn broken_lines_are_reported() {
+        let config = parse("alt+s\n[šš]\nalt+s\n[š]\nalt+nokey\n");
+        assert_eq!(
+            messages(&config),
+            vec![(1, Severity::Error), (2, Severity::Error), (5, Severity::Error)]
+        );
+        assert!(config.sections[0].entries.is_empty());
+    }
+
+    #[test]
+    fn permissive_hold_is_on_or_off() {
+        let config = parse("[options]\npermissive_hold = on\n");
+        assert!(config.diagnostics.is_empty());
+        assert!(config.options.permissive_hold);
+
+        let config = parse("[options]\npermissive_hold = on\npermissive_hold = 300\n");
+        assert_eq!(messages(&config), vec![(3, Severity::Error)]);
+        assert!(config.options.permissive_hold);
+    }
+
+    #[test]
+    fn remember_layers_is_on_or_off() {
+        let config = parse("[options]\nremember_layers = yes\nremember_layers = 5s\n");
+        assert_eq!(messages(&config), vec![(3, Severity::Error)]);
+        assert!(config.options.remember_layers);
+
+        let config = parse("[options]\nremember_layers = off\n");
+        assert!(config.diagnostics.is_empty());
+        assert!(!config.options.remember_layers);
+    }
+}
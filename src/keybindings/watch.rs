@@ -0,0 +1,167 @@
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use parking_lot::Mutex;
+
+/// File whose changes are reported, the bindings of the active profile.
+static WATCHED: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Calls `on_change` from a background thread every time the watched file is written or
+/// replaced, starting with `path`. Editors that save through a temporary file and a rename are
+/// caught too.
+pub fn spawn(path: PathBuf, mut on_change: impl FnMut(&Path) + Send + 'static) {
+    log::info!(target: "watch", "Watching {:?} for changes", path);
+    *WATCHED.lock() = Some(path);
+    thread::Builder::new()
+        .name("bindings::watch".to_string())
+        .spawn(move || {
+            if let Err(e) = watch(&mut on_change) {
+                log::error!(target: "watch", "Stopped watching for changes, bindings won't be reloaded: {:?}", e);
+            }
+        })
+        .unwrap_or_else(|e| panic!("Thread bindings::watch failed. {:?}", e));
+}
+
+/// Watches `path` instead of the file watched so far, after switching to its profile.
+pub fn follow(path: &Path) {
+    log::info!(target: "watch", "Watching {:?} for changes", path);
+    *WATCHED.lock() = Some(path.to_owned());
+    #[cfg(target_os = "linux")]
+    inotify::add(path);
+}
+
+fn watched() -> Option<PathBuf> {
+    WATCHED.lock().clone()
+}
+
+/// inotify on the directories, the file itself is gone after a rename.
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::ffi::{c_int, CString, OsStr};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    use anyhow::{anyhow, Context};
+
+    /// Instance of the watching thread, -1 before it started.
+    pub(super) static FD: AtomicI32 = AtomicI32::new(-1);
+
+    /// Directory and name of the file.
+    pub(super) fn location(path: &Path) -> anyhow::Result<(&Path, &OsStr)> {
+        let directory = path
+            .parent()
+            .filter(|directory| !directory.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("{:?} is not a file", path))?;
+        Ok((directory, name))
+    }
+
+    /// Watches the directory of `path`. Directories watched already keep their watch descriptor.
+    pub(super) fn watch_directory(fd: c_int, path: &Path) -> anyhow::Result<c_int> {
+        let (directory, _) = location(path)?;
+        let c_directory = CString::new(directory.as_os_str().as_bytes())?;
+        let watch = unsafe { libc::inotify_add_watch(fd, c_directory.as_ptr(), libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO) };
+        if watch < 0 {
+            return Err(io::Error::last_os_error()).with_context(|| format!("Can't watch {:?}", directory));
+        }
+        Ok(watch)
+    }
+
+    /// Starts watching the directory of a file the thread doesn't know of yet.
+    pub(super) fn add(path: &Path) {
+        let fd = FD.load(Ordering::Acquire);
+        if fd < 0 {
+            return;
+        }
+        if let Err(e) = watch_directory(fd, path) {
+            log::error!(target: "watch", "Changes of {:?} won't be reloaded: {:?}", path, e);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn watch(on_change: &mut dyn FnMut(&Path)) -> anyhow::Result<()> {
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::mem::size_of;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::FromRawFd;
+    use std::sync::atomic::Ordering;
+
+    use anyhow::Context;
+
+    let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error()).context("inotify_init1 failed");
+    }
+    let mut inotify = unsafe { File::from_raw_fd(fd) };
+    if let Some(path) = watched() {
+        inotify::watch_directory(fd, &path)?;
+    }
+    inotify::FD.store(fd, Ordering::Release);
+
+    const HEADER: usize = size_of::<libc::inotify_event>();
+    let mut buffer = [0u8; 4096];
+    loop {
+        let read = inotify.read(&mut buffer).context("Can't read inotify events")?;
+        let Some(path) = watched() else {
+            continue;
+        };
+        let (_, name) = inotify::location(&path)?;
+        // The descriptor of the directory the watched file is in now
+        let directory = inotify::watch_directory(fd, &path)?;
+        let mut changed = false;
+        let mut offset = 0;
+        while offset + HEADER <= read {
+            let event = unsafe { (buffer[offset..].as_ptr() as *const libc::inotify_event).read_unaligned() };
+            let name_end = (offset + HEADER + event.len as usize).min(read);
+            // The name is padded with zeros
+            let event_name = buffer[offset + HEADER..name_end]
+                .split(|byte| *byte == 0)
+                .next()
+                .unwrap_or_default();
+            changed |= event.wd == directory && event_name == name.as_bytes();
+            offset = name_end;
+        }
+        if changed {
+            on_change(&path);
+        }
+    }
+}
+
+/// Polls the modification time where there is no inotify.
+#[cfg(not(target_os = "linux"))]
+fn watch(on_change: &mut dyn FnMut(&Path)) -> anyhow::Result<()> {
+    use std::fs;
+    use std::time::Duration;
+
+    const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+    let modified = |path: &Option<PathBuf>| {
+        path.as_deref()
+            .and_then(|path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+    };
+    let mut path = watched();
+    let mut last = modified(&path);
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let current_path = watched();
+        if current_path != path {
+            // Switched to another profile, it was just loaded
+            path = current_path;
+            last = modified(&path);
+            continue;
+        }
+        let current = modified(&path);
+        if current.is_some() && current != last {
+            last = current;
+            if let Some(path) = &path {
+                on_change(path);
+            }
+        }
+    }
+}
@@ -0,0 +1,383 @@
+use std::fmt::{Display, Formatter};
+
+use crate::backend::keyboard_vk::KNOWN_VIRTUAL_KEY;
+use crate::backend::keyboard_vk::KNOWN_VIRTUAL_KEY::*;
+
+/// Every platform representation of a key.
+struct KeyCodeMapping {
+    code: KeyCode,
+    /// Name used in `bindings.ini`. Same as the Windows virtual key name without `VK_`, so every
+    /// binding written for [`KNOWN_VIRTUAL_KEY::from_human`] keeps its meaning.
+    name: &'static str,
+    /// Windows virtual key
+    vk: u32,
+    /// PC set 1 scancode, extended keys carry the `0xE0` prefix (`0xE038` is the right Alt)
+    scancode: u32,
+    /// Linux input event code, `KEY_*` from `linux/input-event-codes.h`
+    evdev: u16,
+    /// X11 keysym of the key on the US layout
+    keysym: u32,
+}
+
+macro_rules! key_codes {
+    ($($code:ident => $name:literal, $vk:expr, $scancode:expr, $evdev:expr, $keysym:expr;)*) => {
+        /// Physical key, named after its position on a US keyboard (like `KeyboardEvent.code`).
+        ///
+        /// Unlike a virtual key it keeps the left/right, extended and numpad distinctions, so the
+        /// same binding means the same key on every platform.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub enum KeyCode {
+            $($code,)*
+        }
+
+        const KEY_CODES: &[KeyCodeMapping] = &[
+            $(KeyCodeMapping {
+                code: KeyCode::$code,
+                name: $name,
+                vk: $vk,
+                scancode: $scancode,
+                evdev: $evdev,
+                keysym: $keysym,
+            },)*
+        ];
+    };
+}
+
+key_codes! {
+    Escape => "escape", VK_ESCAPE as u32, 0x1, 1, 0xff1b;
+    Digit1 => "1", VK_1 as u32, 0x2, 2, 0x31;
+    Digit2 => "2", VK_2 as u32, 0x3, 3, 0x32;
+    Digit3 => "3", VK_3 as u32, 0x4, 4, 0x33;
+    Digit4 => "4", VK_4 as u32, 0x5, 5, 0x34;
+    Digit5 => "5", VK_5 as u32, 0x6, 6, 0x35;
+    Digit6 => "6", VK_6 as u32, 0x7, 7, 0x36;
+    Digit7 => "7", VK_7 as u32, 0x8, 8, 0x37;
+    Digit8 => "8", VK_8 as u32, 0x9, 9, 0x38;
+    Digit9 => "9", VK_9 as u32, 0xa, 10, 0x39;
+    Digit0 => "0", VK_0 as u32, 0xb, 11, 0x30;
+    Minus => "oem_minus", VK_OEM_MINUS as u32, 0xc, 12, 0x2d;
+    Equal => "oem_plus", VK_OEM_PLUS as u32, 0xd, 13, 0x3d;
+    Backspace => "back", VK_BACK as u32, 0xe, 14, 0xff08;
+    Tab => "tab", VK_TAB as u32, 0xf, 15, 0xff09;
+    KeyQ => "q", 0x51, 0x10, 16, 0x71;
+    KeyW => "w", 0x57, 0x11, 17, 0x77;
+    KeyE => "e", 0x45, 0x12, 18, 0x65;
+    KeyR => "r", 0x52, 0x13, 19, 0x72;
+    KeyT => "t", 0x54, 0x14, 20, 0x74;
+    KeyY => "y", 0x59, 0x15, 21, 0x79;
+    KeyU => "u", 0x55, 0x16, 22, 0x75;
+    KeyI => "i", 0x49, 0x17, 23, 0x69;
+    KeyO => "o", 0x4f, 0x18, 24, 0x6f;
+    KeyP => "p", 0x50, 0x19, 25, 0x70;
+    BracketLeft => "oem_4", VK_OEM_4 as u32, 0x1a, 26, 0x5b;
+    BracketRight => "oem_6", VK_OEM_6 as u32, 0x1b, 27, 0x5d;
+    Enter => "return", VK_RETURN as u32, 0x1c, 28, 0xff0d;
+    ControlLeft => "lcontrol", VK_LCONTROL as u32, 0x1d, 29, 0xffe3;
+    KeyA => "a", 0x41, 0x1e, 30, 0x61;
+    KeyS => "s", 0x53, 0x1f, 31, 0x73;
+    KeyD => "d", 0x44, 0x20, 32, 0x64;
+    KeyF => "f", 0x46, 0x21, 33, 0x66;
+    KeyG => "g", 0x47, 0x22, 34, 0x67;
+    KeyH => "h", 0x48, 0x23, 35, 0x68;
+    KeyJ => "j", 0x4a, 0x24, 36, 0x6a;
+    KeyK => "k", 0x4b, 0x25, 37, 0x6b;
+    KeyL => "l", 0x4c, 0x26, 38, 0x6c;
+    Semicolon => "oem_1", VK_OEM_1 as u32, 0x27, 39, 0x3b;
+    Quote => "oem_7", VK_OEM_7 as u32, 0x28, 40, 0x27;
+    Backquote => "oem_3", VK_OEM_3 as u32, 0x29, 41, 0x60;
+    ShiftLeft => "lshift", VK_LSHIFT as u32, 0x2a, 42, 0xffe1;
+    Backslash => "oem_5", VK_OEM_5 as u32, 0x2b, 43, 0x5c;
+    KeyZ => "z", 0x5a, 0x2c, 44, 0x7a;
+    KeyX => "x", 0x58, 0x2d, 45, 0x78;
+    KeyC => "c", 0x43, 0x2e, 46, 0x63;
+    KeyV => "v", 0x56, 0x2f, 47, 0x76;
+    KeyB => "b", 0x42, 0x30, 48, 0x62;
+    KeyN => "n", 0x4e, 0x31, 49, 0x6e;
+    KeyM => "m", 0x4d, 0x32, 50, 0x6d;
+    Comma => "oem_comma", VK_OEM_COMMA as u32, 0x33, 51, 0x2c;
+    Period => "oem_period", VK_OEM_PERIOD as u32, 0x34, 52, 0x2e;
+    Slash => "oem_2", VK_OEM_2 as u32, 0x35, 53, 0x2f;
+    ShiftRight => "rshift", VK_RSHIFT as u32, 0x36, 54, 0xffe2;
+    NumpadMultiply => "multiply", VK_MULTIPLY as u32, 0x37, 55, 0xffaa;
+    AltLeft => "lmenu", VK_LMENU as u32, 0x38, 56, 0xffe9;
+    Space => "space", VK_SPACE as u32, 0x39, 57, 0x20;
+    CapsLock => "capital", VK_CAPITAL as u32, 0x3a, 58, 0xffe5;
+    F1 => "f1", VK_F1 as u32, 0x3b, 59, 0xffbe;
+    F2 => "f2", VK_F2 as u32, 0x3c, 60, 0xffbf;
+    F3 => "f3", VK_F3 as u32, 0x3d, 61, 0xffc0;
+    F4 => "f4", VK_F4 as u32, 0x3e, 62, 0xffc1;
+    F5 => "f5", VK_F5 as u32, 0x3f, 63, 0xffc2;
+    F6 => "f6", VK_F6 as u32, 0x40, 64, 0xffc3;
+    F7 => "f7", VK_F7 as u32, 0x41, 65, 0xffc4;
+    F8 => "f8", VK_F8 as u32, 0x42, 66, 0xffc5;
+    F9 => "f9", VK_F9 as u32, 0x43, 67, 0xffc6;
+    F10 => "f10", VK_F10 as u32, 0x44, 68, 0xffc7;
+    NumLock => "numlock", VK_NUMLOCK as u32, 0xe045, 69, 0xff7f;
+    ScrollLock => "scroll", VK_SCROLL as u32, 0x46, 70, 0xff14;
+    Numpad7 => "numpad7", VK_NUMPAD7 as u32, 0x47, 71, 0xffb7;
+    Numpad8 => "numpad8", VK_NUMPAD8 as u32, 0x48, 72, 0xffb8;
+    Numpad9 => "numpad9", VK_NUMPAD9 as u32, 0x49, 73, 0xffb9;
+    NumpadSubtract => "subtract", VK_SUBTRACT as u32, 0x4a, 74, 0xffad;
+    Numpad4 => "numpad4", VK_NUMPAD4 as u32, 0x4b, 75, 0xffb4;
+    Numpad5 => "numpad5", VK_NUMPAD5 as u32, 0x4c, 76, 0xffb5;
+    Numpad6 => "numpad6", VK_NUMPAD6 as u32, 0x4d, 77, 0xffb6;
+    NumpadAdd => "add", VK_ADD as u32, 0x4e, 78, 0xffab;
+    Numpad1 => "numpad1", VK_NUMPAD1 as u32, 0x4f, 79, 0xffb1;
+    Numpad2 => "numpad2", VK_NUMPAD2 as u32, 0x50, 80, 0xffb2;
+    Numpad3 => "numpad3", VK_NUMPAD3 as u32, 0x51, 81, 0xffb3;
+    Numpad0 => "numpad0", VK_NUMPAD0 as u32, 0x52, 82, 0xffb0;
+    NumpadDecimal => "decimal", VK_DECIMAL as u32, 0x53, 83, 0xffae;
+    IntlBackslash => "oem_102", VK_OEM_102 as u32, 0x56, 86, 0x3c;
+    F11 => "f11", VK_F11 as u32, 0x57, 87, 0xffc8;
+    F12 => "f12", VK_F12 as u32, 0x58, 88, 0xffc9;
+    NumpadEnter => "numpad_return", VK_RETURN as u32, 0xe01c, 96, 0xff8d;
+    ControlRight => "rcontrol", VK_RCONTROL as u32, 0xe01d, 97, 0xffe4;
+    NumpadDivide => "divide", VK_DIVIDE as u32, 0xe035, 98, 0xffaf;
+    PrintScreen => "snapshot", VK_SNAPSHOT as u32, 0xe037, 99, 0xff61;
+    AltRight => "rmenu", VK_RMENU as u32, 0xe038, 100, 0xffea;
+    Home => "home", VK_HOME as u32, 0xe047, 102, 0xff50;
+    ArrowUp => "up", VK_UP as u32, 0xe048, 103, 0xff52;
+    PageUp => "prior", VK_PRIOR as u32, 0xe049, 104, 0xff55;
+    ArrowLeft => "left", VK_LEFT as u32, 0xe04b, 105, 0xff51;
+    ArrowRight => "right", VK_RIGHT as u32, 0xe04d, 106, 0xff53;
+    End => "end", VK_END as u32, 0xe04f, 107, 0xff57;
+    ArrowDown => "down", VK_DOWN as u32, 0xe050, 108, 0xff54;
+    PageDown => "next", VK_NEXT as u32, 0xe051, 109, 0xff56;
+    Insert => "insert", VK_INSERT as u32, 0xe052, 110, 0xff63;
+    Delete => "delete", VK_DELETE as u32, 0xe053, 111, 0xffff;
+    AudioVolumeMute => "volume_mute", VK_VOLUME_MUTE as u32, 0xe020, 113, 0x1008ff12;
+    AudioVolumeDown => "volume_down", VK_VOLUME_DOWN as u32, 0xe02e, 114, 0x1008ff11;
+    AudioVolumeUp => "volume_up", VK_VOLUME_UP as u32, 0xe030, 115, 0x1008ff13;
+    Pause => "pause", VK_PAUSE as u32, 0x45, 119, 0xff13;
+    MetaLeft => "lwin", VK_LWIN as u32, 0xe05b, 125, 0xffeb;
+    MetaRight => "rwin", VK_RWIN as u32, 0xe05c, 126, 0xffec;
+    ContextMenu => "apps", VK_APPS as u32, 0xe05d, 127, 0xff67;
+    MediaTrackNext => "media_next_track", VK_MEDIA_NEXT_TRACK as u32, 0xe019, 163, 0x1008ff17;
+    MediaPlayPause => "media_play_pause", VK_MEDIA_PLAY_PAUSE as u32, 0xe022, 164, 0x1008ff14;
+    MediaTrackPrevious => "media_prev_track", VK_MEDIA_PREV_TRACK as u32, 0xe010, 165, 0x1008ff16;
+    MediaStop => "media_stop", VK_MEDIA_STOP as u32, 0xe024, 166, 0x1008ff15;
+    F13 => "f13", VK_F13 as u32, 0x64, 183, 0xffca;
+    F14 => "f14", VK_F14 as u32, 0x65, 184, 0xffcb;
+    F15 => "f15", VK_F15 as u32, 0x66, 185, 0xffcc;
+    F16 => "f16", VK_F16 as u32, 0x67, 186, 0xffcd;
+    F17 => "f17", VK_F17 as u32, 0x68, 187, 0xffce;
+    F18 => "f18", VK_F18 as u32, 0x69, 188, 0xffcf;
+    F19 => "f19", VK_F19 as u32, 0x6a, 189, 0xffd0;
+    F20 => "f20", VK_F20 as u32, 0x6b, 190, 0xffd1;
+    F21 => "f21", VK_F21 as u32, 0x6c, 191, 0xffd2;
+    F22 => "f22", VK_F22 as u32, 0x6d, 192, 0xffd3;
+    F23 => "f23", VK_F23 as u32, 0x6e, 193, 0xffd4;
+    F24 => "f24", VK_F24 as u32, 0x76, 194, 0xffd5;
+    // Either side of a modifier. Only used in bindings, never pressed.
+    Shift => "shift", VK_SHIFT as u32, 0, 0, 0;
+    Control => "control", VK_CONTROL as u32, 0, 0, 0;
+    Alt => "menu", VK_MENU as u32, 0, 0, 0;
+    Meta => "win", 0, 0, 0, 0;
+}
+
+impl KeyCode {
+    fn mapping(self) -> &'static KeyCodeMapping {
+        &KEY_CODES[self as usize]
+    }
+
+    fn find(predicate: impl Fn(&KeyCodeMapping) -> bool) -> Option<KeyCode> {
+        KEY_CODES
+            .iter()
+            .find(|mapping| predicate(mapping))
+            .map(|mapping| mapping.code)
+    }
+
+    /// Accepts the names of [`KNOWN_VIRTUAL_KEY::from_human`]: `alt`, `lctrl`, `meta`, `f3`...
+    pub fn from_name(human_readable: &str) -> Result<Self, anyhow::Error> {
+        let name = human_readable
+            .to_lowercase()
+            .replace("alt", "menu")
+            .replace("meta", "win")
+            .replace("mod4", "win")
+            .replace("ctrl", "control");
+        match Self::find(|mapping| mapping.name == name) {
+            Some(code) => Ok(code),
+            None => {
+                let vk = KNOWN_VIRTUAL_KEY::from_human(human_readable)?;
+                Self::from_vk(vk as u32)
+                    .ok_or_else(|| anyhow::anyhow!("{:?} is not a key on a keyboard", vk))
+            }
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        self.mapping().name
+    }
+
+    /// Every key that can actually be pressed, without the generic modifiers.
+    pub fn physical() -> impl Iterator<Item = KeyCode> {
+        KEY_CODES
+            .iter()
+            .map(|mapping| mapping.code)
+            .filter(|code| !code.is_generic())
+    }
+
+    /// `Shift`, `Control`, `Alt` and `Meta` stand for either side of the modifier.
+    pub fn is_generic(self) -> bool {
+        matches!(
+            self,
+            KeyCode::Shift | KeyCode::Control | KeyCode::Alt | KeyCode::Meta
+        )
+    }
+
+    /// Left and right variants of a generic modifier.
+    pub fn sides(self) -> Option<(KeyCode, KeyCode)> {
+        match self {
+            KeyCode::Shift => Some((KeyCode::ShiftLeft, KeyCode::ShiftRight)),
+            KeyCode::Control => Some((KeyCode::ControlLeft, KeyCode::ControlRight)),
+            KeyCode::Alt => Some((KeyCode::AltLeft, KeyCode::AltRight)),
+            KeyCode::Meta => Some((KeyCode::MetaLeft, KeyCode::MetaRight)),
+            _ => None,
+        }
+    }
+
+    pub fn is_shift(self) -> bool {
+        matches!(
+            self,
+            KeyCode::Shift | KeyCode::ShiftLeft | KeyCode::ShiftRight
+        )
+    }
+
+    /// Shift, Control, Alt or Meta, either side.
+    pub fn is_modifier(self) -> bool {
+        self.is_generic()
+            || [KeyCode::Shift, KeyCode::Control, KeyCode::Alt, KeyCode::Meta]
+                .iter()
+                .filter_map(|generic| generic.sides())
+                .any(|(left, right)| self == left || self == right)
+    }
+
+    pub fn is_meta_or_alt(self) -> bool {
+        matches!(
+            self,
+            KeyCode::AltLeft | KeyCode::AltRight | KeyCode::MetaLeft | KeyCode::MetaRight
+        )
+    }
+
+    /// Keys that type a character on the US layout (letters, digits, punctuation, space).
+    pub fn is_printable(self) -> bool {
+        matches!(self.mapping().keysym, 0x20..=0x7e)
+    }
+
+    #[cfg(windows)]
+    pub fn vk(self) -> u32 {
+        self.mapping().vk
+    }
+
+    /// First key with this virtual key. Keys sharing one (Enter and numpad Enter) resolve to the
+    /// main block one, use `KeyCode::from_windows` when the scancode is known.
+    pub fn from_vk(vk: u32) -> Option<Self> {
+        Self::find(|mapping| mapping.vk == vk)
+    }
+
+    pub fn scancode(self) -> u32 {
+        self.mapping().scancode
+    }
+
+    pub fn from_scancode(scancode: u32) -> Option<Self> {
+        Self::find(|mapping| mapping.scancode == scancode && !mapping.code.is_generic())
+    }
+
+    /// Whether a scancode of the bindings file means this key. Scancodes without the `0xE0` prefix
+    /// also mean the extended key, like they did before the prefix was added, so `0x38` is either
+    /// Alt and `0xe038` only the right one.
+    pub fn has_scancode(self, scancode: u32) -> bool {
+        let own = self.scancode();
+        own == scancode || (scancode & 0xFF00 == 0 && own == 0xE000 | scancode)
+    }
+
+    /// What the Windows keyboard hook reports. The scancode decides, the virtual key is only a
+    /// fallback for injected events that come without one.
+    #[cfg(windows)]
+    pub fn from_windows(vk: u32, scancode: u32, extended: bool) -> Option<Self> {
+        let scancode = if extended { 0xE000 | scancode } else { scancode };
+        Self::from_scancode(scancode).or_else(|| Self::from_vk(vk))
+    }
+
+    #[cfg(windows)]
+    pub fn is_extended(self) -> bool {
+        self.scancode() & 0xFF00 == 0xE000
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn evdev(self) -> u16 {
+        self.mapping().evdev
+    }
+
+    pub fn from_evdev(code: u16) -> Option<Self> {
+        Self::find(|mapping| mapping.evdev == code && code != 0)
+    }
+
+    #[cfg(not(windows))]
+    pub fn keysym(self) -> u32 {
+        self.mapping().keysym
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn from_keysym(keysym: u32) -> Option<Self> {
+        Self::find(|mapping| mapping.keysym == keysym && keysym != 0)
+    }
+}
+
+impl Display for KeyCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// The other keys already using a code of the table.
+    fn shared<T: std::hash::Hash + Eq>(code: impl Fn(&KeyCodeMapping) -> T) -> Vec<(KeyCode, KeyCode)> {
+        let mut seen = HashMap::new();
+        KEY_CODES
+            .iter()
+            .filter(|mapping| !mapping.code.is_generic())
+            .filter_map(|mapping| Some((seen.insert(code(mapping), mapping.code)?, mapping.code)))
+            .collect()
+    }
+
+    #[test]
+    fn every_key_round_trips() {
+        for (index, mapping) in KEY_CODES.iter().enumerate() {
+            let code = mapping.code;
+            assert_eq!(code as usize, index, "{:?} is out of place", code);
+            assert_eq!(KeyCode::from_name(code.name()).ok(), Some(code));
+            if code.is_generic() {
+                continue;
+            }
+            assert_eq!(KeyCode::from_scancode(code.scancode()), Some(code));
+            assert_eq!(KeyCode::from_evdev(mapping.evdev), Some(code));
+            #[cfg(target_os = "linux")]
+            assert_eq!(KeyCode::from_keysym(code.keysym()), Some(code));
+        }
+    }
+
+    #[test]
+    fn no_two_keys_share_a_code() {
+        assert_eq!(shared(|mapping| mapping.name), vec![]);
+        assert_eq!(shared(|mapping| mapping.scancode), vec![]);
+        assert_eq!(shared(|mapping| mapping.evdev), vec![]);
+        assert_eq!(shared(|mapping| mapping.keysym), vec![]);
+        // Told apart by the scancode, see `KeyCode::from_vk`
+        assert_eq!(shared(|mapping| mapping.vk), vec![(KeyCode::Enter, KeyCode::NumpadEnter)]);
+    }
+
+    #[test]
+    fn scancodes_without_the_prefix_mean_extended_keys_too() {
+        assert!(KeyCode::AltRight.has_scancode(0xe038));
+        assert!(KeyCode::AltRight.has_scancode(0x38));
+        assert!(KeyCode::AltLeft.has_scancode(0x38));
+        assert!(!KeyCode::AltLeft.has_scancode(0xe038));
+        assert!(!KeyCode::AltRight.has_scancode(0xe01c));
+    }
+}
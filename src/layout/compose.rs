@@ -0,0 +1,67 @@
+use std::iter::once;
+
+use unicode_normalization::char::decompose_canonical;
+use unicode_normalization::UnicodeNormalization;
+
+pub use unicode_normalization::char::is_combining_mark;
+
+/// `base` with the combining `mark` on it, precomposed through NFC when Unicode has such a
+/// character, like `é` for `e` and U+0301. Otherwise the base followed by the mark.
+pub fn compose(base: &str, mark: char) -> String {
+    base.chars().chain(once(mark)).nfc().collect()
+}
+
+/// Whether a dead key can put a mark on what `key` typed, it's a single letter.
+pub fn can_compose(typed: &str) -> bool {
+    let mut chars = typed.chars();
+    chars.next().is_some_and(char::is_alphabetic) && chars.next().is_none()
+}
+
+/// Letters of Latin-1 Supplement and Latin Extended-A that are `base` with marks on it, like
+/// `è é ê ë ē ĕ ė ę ě` for `e`.
+pub fn variants_of(base: char) -> Vec<char> {
+    ('\u{c0}'..='\u{17f}')
+        .filter(|char| {
+            let mut first = None;
+            decompose_canonical(*char, |part| {
+                first.get_or_insert(part);
+            });
+            *char != base && first == Some(base)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precomposes_when_unicode_has_the_letter() {
+        assert_eq!(compose("e", '\u{301}'), "é");
+        assert_eq!(compose("E", '\u{301}'), "É");
+        assert_eq!(compose("c", '\u{30c}'), "č");
+    }
+
+    #[test]
+    fn keeps_the_mark_after_a_base_without_such_letter() {
+        assert_eq!(compose("q", '\u{301}'), "q\u{301}");
+    }
+
+    #[test]
+    fn only_single_letters_compose() {
+        assert!(can_compose("e"));
+        assert!(can_compose("ж"));
+        assert!(!can_compose(""));
+        assert!(!can_compose("1"));
+        assert!(!can_compose(";"));
+        assert!(!can_compose("ab"));
+    }
+
+    #[test]
+    fn variants_are_marked_letters_of_the_base() {
+        let variants = variants_of('e');
+        assert!(variants.contains(&'é') && variants.contains(&'ě'), "{:?}", variants);
+        assert!(!variants.contains(&'e'));
+        assert!(!variants.contains(&'á'));
+    }
+}
@@ -0,0 +1,258 @@
+/// Named X11 keysyms outside of Latin-1 (`X11/keysymdef.h`) and the characters they produce.
+///
+/// Latin-1 keysyms are equal to their code points and every other character has the Unicode
+/// keysym `0x0100_0000 + code point`, so only the legacy ranges need a table.
+const KEYSYMS: &[(&str, u32, char)] = &[
+    // Latin-2
+    ("Aogonek", 0x1a1, '\u{104}'),
+    ("breve", 0x1a2, '\u{2d8}'),
+    ("Lstroke", 0x1a3, '\u{141}'),
+    ("Lcaron", 0x1a5, '\u{13d}'),
+    ("Sacute", 0x1a6, '\u{15a}'),
+    ("Scaron", 0x1a9, '\u{160}'),
+    ("Scedilla", 0x1aa, '\u{15e}'),
+    ("Tcaron", 0x1ab, '\u{164}'),
+    ("Zacute", 0x1ac, '\u{179}'),
+    ("Zcaron", 0x1ae, '\u{17d}'),
+    ("Zabovedot", 0x1af, '\u{17b}'),
+    ("aogonek", 0x1b1, '\u{105}'),
+    ("ogonek", 0x1b2, '\u{2db}'),
+    ("lstroke", 0x1b3, '\u{142}'),
+    ("lcaron", 0x1b5, '\u{13e}'),
+    ("sacute", 0x1b6, '\u{15b}'),
+    ("caron", 0x1b7, '\u{2c7}'),
+    ("scaron", 0x1b9, '\u{161}'),
+    ("scedilla", 0x1ba, '\u{15f}'),
+    ("tcaron", 0x1bb, '\u{165}'),
+    ("zacute", 0x1bc, '\u{17a}'),
+    ("doubleacute", 0x1bd, '\u{2dd}'),
+    ("zcaron", 0x1be, '\u{17e}'),
+    ("zabovedot", 0x1bf, '\u{17c}'),
+    ("Racute", 0x1c0, '\u{154}'),
+    ("Abreve", 0x1c3, '\u{102}'),
+    ("Lacute", 0x1c5, '\u{139}'),
+    ("Cacute", 0x1c6, '\u{106}'),
+    ("Ccaron", 0x1c8, '\u{10c}'),
+    ("Eogonek", 0x1ca, '\u{118}'),
+    ("Ecaron", 0x1cc, '\u{11a}'),
+    ("Dcaron", 0x1cf, '\u{10e}'),
+    ("Dstroke", 0x1d0, '\u{110}'),
+    ("Nacute", 0x1d1, '\u{143}'),
+    ("Ncaron", 0x1d2, '\u{147}'),
+    ("Odoubleacute", 0x1d5, '\u{150}'),
+    ("Rcaron", 0x1d8, '\u{158}'),
+    ("Uring", 0x1d9, '\u{16e}'),
+    ("Udoubleacute", 0x1db, '\u{170}'),
+    ("Tcedilla", 0x1de, '\u{162}'),
+    ("racute", 0x1e0, '\u{155}'),
+    ("abreve", 0x1e3, '\u{103}'),
+    ("lacute", 0x1e5, '\u{13a}'),
+    ("cacute", 0x1e6, '\u{107}'),
+    ("ccaron", 0x1e8, '\u{10d}'),
+    ("eogonek", 0x1ea, '\u{119}'),
+    ("ecaron", 0x1ec, '\u{11b}'),
+    ("dcaron", 0x1ef, '\u{10f}'),
+    ("dstroke", 0x1f0, '\u{111}'),
+    ("nacute", 0x1f1, '\u{144}'),
+    ("ncaron", 0x1f2, '\u{148}'),
+    ("odoubleacute", 0x1f5, '\u{151}'),
+    ("rcaron", 0x1f8, '\u{159}'),
+    ("uring", 0x1f9, '\u{16f}'),
+    ("udoubleacute", 0x1fb, '\u{171}'),
+    ("tcedilla", 0x1fe, '\u{163}'),
+    ("abovedot", 0x1ff, '\u{2d9}'),
+    // Cyrillic
+    ("Serbian_dje", 0x6a1, '\u{452}'),
+    ("Macedonia_gje", 0x6a2, '\u{453}'),
+    ("Cyrillic_io", 0x6a3, '\u{451}'),
+    ("Ukrainian_ie", 0x6a4, '\u{454}'),
+    ("Macedonia_dse", 0x6a5, '\u{455}'),
+    ("Ukrainian_i", 0x6a6, '\u{456}'),
+    ("Ukrainian_yi", 0x6a7, '\u{457}'),
+    ("Cyrillic_je", 0x6a8, '\u{458}'),
+    ("Cyrillic_lje", 0x6a9, '\u{459}'),
+    ("Cyrillic_nje", 0x6aa, '\u{45a}'),
+    ("Serbian_tshe", 0x6ab, '\u{45b}'),
+    ("Macedonia_kje", 0x6ac, '\u{45c}'),
+    ("Ukrainian_ghe_with_upturn", 0x6ad, '\u{491}'),
+    ("Byelorussian_shortu", 0x6ae, '\u{45e}'),
+    ("Cyrillic_dzhe", 0x6af, '\u{45f}'),
+    ("numerosign", 0x6b0, '\u{2116}'),
+    ("Serbian_DJE", 0x6b1, '\u{402}'),
+    ("Macedonia_GJE", 0x6b2, '\u{403}'),
+    ("Cyrillic_IO", 0x6b3, '\u{401}'),
+    ("Ukrainian_IE", 0x6b4, '\u{404}'),
+    ("Macedonia_DSE", 0x6b5, '\u{405}'),
+    ("Ukrainian_I", 0x6b6, '\u{406}'),
+    ("Ukrainian_YI", 0x6b7, '\u{407}'),
+    ("Cyrillic_JE", 0x6b8, '\u{408}'),
+    ("Cyrillic_LJE", 0x6b9, '\u{409}'),
+    ("Cyrillic_NJE", 0x6ba, '\u{40a}'),
+    ("Serbian_TSHE", 0x6bb, '\u{40b}'),
+    ("Macedonia_KJE", 0x6bc, '\u{40c}'),
+    ("Ukrainian_GHE_WITH_UPTURN", 0x6bd, '\u{490}'),
+    ("Byelorussian_SHORTU", 0x6be, '\u{40e}'),
+    ("Cyrillic_DZHE", 0x6bf, '\u{40f}'),
+    ("Cyrillic_yu", 0x6c0, '\u{44e}'),
+    ("Cyrillic_a", 0x6c1, '\u{430}'),
+    ("Cyrillic_be", 0x6c2, '\u{431}'),
+    ("Cyrillic_tse", 0x6c3, '\u{446}'),
+    ("Cyrillic_de", 0x6c4, '\u{434}'),
+    ("Cyrillic_ie", 0x6c5, '\u{435}'),
+    ("Cyrillic_ef", 0x6c6, '\u{444}'),
+    ("Cyrillic_ghe", 0x6c7, '\u{433}'),
+    ("Cyrillic_ha", 0x6c8, '\u{445}'),
+    ("Cyrillic_i", 0x6c9, '\u{438}'),
+    ("Cyrillic_shorti", 0x6ca, '\u{439}'),
+    ("Cyrillic_ka", 0x6cb, '\u{43a}'),
+    ("Cyrillic_el", 0x6cc, '\u{43b}'),
+    ("Cyrillic_em", 0x6cd, '\u{43c}'),
+    ("Cyrillic_en", 0x6ce, '\u{43d}'),
+    ("Cyrillic_o", 0x6cf, '\u{43e}'),
+    ("Cyrillic_pe", 0x6d0, '\u{43f}'),
+    ("Cyrillic_ya", 0x6d1, '\u{44f}'),
+    ("Cyrillic_er", 0x6d2, '\u{440}'),
+    ("Cyrillic_es", 0x6d3, '\u{441}'),
+    ("Cyrillic_te", 0x6d4, '\u{442}'),
+    ("Cyrillic_u", 0x6d5, '\u{443}'),
+    ("Cyrillic_zhe", 0x6d6, '\u{436}'),
+    ("Cyrillic_ve", 0x6d7, '\u{432}'),
+    ("Cyrillic_softsign", 0x6d8, '\u{44c}'),
+    ("Cyrillic_yeru", 0x6d9, '\u{44b}'),
+    ("Cyrillic_ze", 0x6da, '\u{437}'),
+    ("Cyrillic_sha", 0x6db, '\u{448}'),
+    ("Cyrillic_e", 0x6dc, '\u{44d}'),
+    ("Cyrillic_shcha", 0x6dd, '\u{449}'),
+    ("Cyrillic_che", 0x6de, '\u{447}'),
+    ("Cyrillic_hardsign", 0x6df, '\u{44a}'),
+    ("Cyrillic_YU", 0x6e0, '\u{42e}'),
+    ("Cyrillic_A", 0x6e1, '\u{410}'),
+    ("Cyrillic_BE", 0x6e2, '\u{411}'),
+    ("Cyrillic_TSE", 0x6e3, '\u{426}'),
+    ("Cyrillic_DE", 0x6e4, '\u{414}'),
+    ("Cyrillic_IE", 0x6e5, '\u{415}'),
+    ("Cyrillic_EF", 0x6e6, '\u{424}'),
+    ("Cyrillic_GHE", 0x6e7, '\u{413}'),
+    ("Cyrillic_HA", 0x6e8, '\u{425}'),
+    ("Cyrillic_I", 0x6e9, '\u{418}'),
+    ("Cyrillic_SHORTI", 0x6ea, '\u{419}'),
+    ("Cyrillic_KA", 0x6eb, '\u{41a}'),
+    ("Cyrillic_EL", 0x6ec, '\u{41b}'),
+    ("Cyrillic_EM", 0x6ed, '\u{41c}'),
+    ("Cyrillic_EN", 0x6ee, '\u{41d}'),
+    ("Cyrillic_O", 0x6ef, '\u{41e}'),
+    ("Cyrillic_PE", 0x6f0, '\u{41f}'),
+    ("Cyrillic_YA", 0x6f1, '\u{42f}'),
+    ("Cyrillic_ER", 0x6f2, '\u{420}'),
+    ("Cyrillic_ES", 0x6f3, '\u{421}'),
+    ("Cyrillic_TE", 0x6f4, '\u{422}'),
+    ("Cyrillic_U", 0x6f5, '\u{423}'),
+    ("Cyrillic_ZHE", 0x6f6, '\u{416}'),
+    ("Cyrillic_VE", 0x6f7, '\u{412}'),
+    ("Cyrillic_SOFTSIGN", 0x6f8, '\u{42c}'),
+    ("Cyrillic_YERU", 0x6f9, '\u{42b}'),
+    ("Cyrillic_ZE", 0x6fa, '\u{417}'),
+    ("Cyrillic_SHA", 0x6fb, '\u{428}'),
+    ("Cyrillic_E", 0x6fc, '\u{42d}'),
+    ("Cyrillic_SHCHA", 0x6fd, '\u{429}'),
+    ("Cyrillic_CHE", 0x6fe, '\u{427}'),
+    ("Cyrillic_HARDSIGN", 0x6ff, '\u{42a}'),
+    // Currency
+    ("EuroSign", 0x20ac, '\u{20ac}'),
+];
+
+/// Names of the keysyms 0x20-0x7e. Letters and digits are named by themselves.
+const ASCII_NAMES: [&str; 0x5f] = [
+    "space", "exclam", "quotedbl", "numbersign", "dollar", "percent", "ampersand", "apostrophe",
+    "parenleft", "parenright", "asterisk", "plus", "comma", "minus", "period", "slash",
+    "0", "1", "2", "3", "4", "5", "6", "7", "8", "9",
+    "colon", "semicolon", "less", "equal", "greater", "question", "at",
+    "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M",
+    "N", "O", "P", "Q", "R", "S", "T", "U", "V", "W", "X", "Y", "Z",
+    "bracketleft", "backslash", "bracketright", "asciicircum", "underscore", "grave",
+    "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m",
+    "n", "o", "p", "q", "r", "s", "t", "u", "v", "w", "x", "y", "z",
+    "braceleft", "bar", "braceright", "asciitilde",
+];
+
+/// Names of the keysyms 0xa0-0xff.
+const LATIN1_NAMES: [&str; 0x60] = [
+    "nobreakspace", "exclamdown", "cent", "sterling", "currency", "yen", "brokenbar", "section",
+    "diaeresis", "copyright", "ordfeminine", "guillemotleft", "notsign", "hyphen", "registered",
+    "macron", "degree", "plusminus", "twosuperior", "threesuperior", "acute", "mu", "paragraph",
+    "periodcentered", "cedilla", "onesuperior", "masculine", "guillemotright", "onequarter",
+    "onehalf", "threequarters", "questiondown", "Agrave", "Aacute", "Acircumflex", "Atilde",
+    "Adiaeresis", "Aring", "AE", "Ccedilla", "Egrave", "Eacute", "Ecircumflex", "Ediaeresis",
+    "Igrave", "Iacute", "Icircumflex", "Idiaeresis", "ETH", "Ntilde", "Ograve", "Oacute",
+    "Ocircumflex", "Otilde", "Odiaeresis", "multiply", "Oslash", "Ugrave", "Uacute",
+    "Ucircumflex", "Udiaeresis", "Yacute", "THORN", "ssharp", "agrave", "aacute", "acircumflex",
+    "atilde", "adiaeresis", "aring", "ae", "ccedilla", "egrave", "eacute", "ecircumflex",
+    "ediaeresis", "igrave", "iacute", "icircumflex", "idiaeresis", "eth", "ntilde", "ograve",
+    "oacute", "ocircumflex", "otilde", "odiaeresis", "division", "oslash", "ugrave", "uacute",
+    "ucircumflex", "udiaeresis", "yacute", "thorn", "ydiaeresis",
+];
+
+/// Deprecated spellings that layouts still use.
+const ALIASES: &[(&str, u32)] = &[
+    ("quoteright", 0x27),
+    ("quoteleft", 0x60),
+    ("guillemetleft", 0xab),
+    ("guillemetright", 0xbb),
+    ("ordmasculine", 0xba),
+    ("Eth", 0xd0),
+    ("Ooblique", 0xd8),
+    ("Thorn", 0xde),
+    ("ooblique", 0xf8),
+];
+
+pub const UNICODE_KEYSYM_OFFSET: u32 = 0x0100_0000;
+
+/// Keysym of a name used in XKB files: `Cyrillic_yeru`, `U044B` or `0x100044b`.
+pub fn keysym_from_name(name: &str) -> Option<u32> {
+    if let Some(index) = ASCII_NAMES.iter().position(|known| *known == name) {
+        return Some(0x20 + index as u32);
+    }
+    if let Some(index) = LATIN1_NAMES.iter().position(|known| *known == name) {
+        return Some(0xa0 + index as u32);
+    }
+    if let Some(hex) = name.strip_prefix("0x") {
+        return u32::from_str_radix(hex, 16).ok();
+    }
+    if let Some(hex) = name.strip_prefix('U').filter(|hex| hex.len() >= 4) {
+        if let Ok(code) = u32::from_str_radix(hex, 16) {
+            return Some(code + UNICODE_KEYSYM_OFFSET);
+        }
+    }
+    ALIASES
+        .iter()
+        .map(|(known, keysym)| (*known, *keysym))
+        .chain(KEYSYMS.iter().map(|(known, keysym, _)| (*known, *keysym)))
+        .find(|(known, _)| *known == name)
+        .map(|(_, keysym)| keysym)
+}
+
+/// Character a keysym produces, if it produces one.
+pub fn keysym_to_char(keysym: u32) -> Option<char> {
+    match keysym {
+        0x20..=0x7e | 0xa0..=0xff => char::from_u32(keysym),
+        0x0100_0100..=0x0110_ffff => char::from_u32(keysym - UNICODE_KEYSYM_OFFSET),
+        _ => KEYSYMS
+            .iter()
+            .find(|(_, known, _)| *known == keysym)
+            .map(|(_, _, char)| *char),
+    }
+}
+
+/// Keysym that produces `char`. Prefers the legacy keysym so remapped keys look the same as
+/// the ones of a real layout.
+#[cfg(feature = "x11")]
+pub fn char_to_keysym(char: char) -> u32 {
+    match char as u32 {
+        code @ (0x20..=0x7e | 0xa0..=0xff) => code,
+        code => KEYSYMS
+            .iter()
+            .find(|(_, _, known)| *known == char)
+            .map(|(_, keysym, _)| *keysym)
+            .unwrap_or(code + UNICODE_KEYSYM_OFFSET),
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context};
+
+use crate::keycode::KeyCode;
+use crate::layout::{KeyboardLayout, ShiftLevel};
+
+/// `SHIFTSTATE` values of the columns we care about: none, Shift, Ctrl+Alt and Shift+Ctrl+Alt.
+/// Ctrl alone only produces control characters.
+fn shift_level(shift_state: u32) -> Option<ShiftLevel> {
+    match shift_state {
+        0 => Some(ShiftLevel::Base),
+        1 => Some(ShiftLevel::Shift),
+        6 => Some(ShiftLevel::AltGr),
+        7 => Some(ShiftLevel::ShiftAltGr),
+        _ => None,
+    }
+}
+
+/// Header fields and sections without anything that affects the characters.
+const OTHER_KEYWORDS: &[&str] = &[
+    "COPYRIGHT",
+    "COMPANY",
+    "LOCALENAME",
+    "LOCALEID",
+    "VERSION",
+    "ATTRIBUTES",
+    "DEADKEY",
+    "KEYNAME",
+    "KEYNAME_EXT",
+    "KEYNAME_DEAD",
+    "DESCRIPTIONS",
+    "LANGUAGENAMES",
+];
+
+enum Section {
+    Header,
+    ShiftState,
+    Layout,
+    Ligature,
+    Other,
+}
+
+/// `.klc` files are saved by MSKLC as UTF-16 with a byte order mark.
+fn decode(bytes: &[u8]) -> anyhow::Result<String> {
+    let utf16 = |bytes: &[u8], from: fn([u8; 2]) -> u16| {
+        let units = bytes
+            .chunks_exact(2)
+            .map(|pair| from([pair[0], pair[1]]))
+            .collect::<Vec<_>>();
+        String::from_utf16(&units).context("Invalid UTF-16")
+    };
+    match bytes {
+        [0xff, 0xfe, rest @ ..] => utf16(rest, u16::from_le_bytes),
+        [0xfe, 0xff, rest @ ..] => utf16(rest, u16::from_be_bytes),
+        [0xef, 0xbb, 0xbf, rest @ ..] => String::from_utf8(rest.to_vec()).context("Invalid UTF-8"),
+        _ => String::from_utf8(bytes.to_vec()).context("Invalid UTF-8"),
+    }
+}
+
+/// A character column: `-1` for nothing, a letter or digit as is, otherwise four hex digits.
+/// Dead keys (`@`) are skipped and ligatures (`%%`) come from the `LIGATURE` section.
+fn parse_character(column: &str) -> anyhow::Result<Option<String>> {
+    if column == "-1" || column == "%%" || column.ends_with('@') {
+        return Ok(None);
+    }
+    let mut chars = column.chars();
+    if let (Some(char), None) = (chars.next(), chars.next()) {
+        return Ok(Some(char.to_string()));
+    }
+    let code = u32::from_str_radix(column, 16).with_context(|| format!("bad character {column}"))?;
+    let char = char::from_u32(code).ok_or_else(|| anyhow!("bad character {column}"))?;
+    Ok(Some(char.to_string()))
+}
+
+/// Parses a Microsoft Keyboard Layout Creator source file.
+///
+/// Keys are placed by their scancode, so the layout stays right for keyboards where the virtual
+/// keys are remapped.
+pub fn parse(text: &str, default_name: &str) -> anyhow::Result<KeyboardLayout> {
+    let mut layout = KeyboardLayout::new(default_name);
+    let mut section = Section::Header;
+    let mut shift_states: Vec<u32> = Vec::new();
+    let mut keys_by_vk: HashMap<String, KeyCode> = HashMap::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line
+            .split("//")
+            .next()
+            .and_then(|line| line.split(';').next())
+            .unwrap_or_default()
+            .trim();
+        if line.is_empty() {
+            continue;
+        }
+        let columns = line.split_whitespace().collect::<Vec<_>>();
+        let context = || format!("line {}", number + 1);
+
+        match columns[0] {
+            "KBD" => {
+                // KBD	KBDRU	"Russian"
+                if let Some(start) = line.find('"') {
+                    layout.name = line[start..].trim_matches('"').to_owned();
+                }
+                section = Section::Header;
+                continue;
+            }
+            "SHIFTSTATE" => {
+                section = Section::ShiftState;
+                continue;
+            }
+            "LAYOUT" => {
+                section = Section::Layout;
+                continue;
+            }
+            "LIGATURE" => {
+                section = Section::Ligature;
+                continue;
+            }
+            "ENDKBD" => break,
+            keyword if OTHER_KEYWORDS.contains(&keyword) => {
+                section = Section::Other;
+                continue;
+            }
+            _ => {}
+        }
+
+        match section {
+            Section::ShiftState => {
+                let state = columns[0].parse().with_context(|| format!("{}: bad shift state", context()))?;
+                shift_states.push(state);
+            }
+            Section::Layout => {
+                // SC	VK_		Cap	0	1	2	6	7
+                if columns.len() < 3 {
+                    bail!("{}: expected scancode, virtual key and caps lock columns", context());
+                }
+                // -1	-1		0	...	after a key with SGCap, what it types with caps lock on.
+                // Layouts have no caps lock level, the key keeps what it types with shift.
+                if columns[0] == "-1" {
+                    continue;
+                }
+                let scancode = u32::from_str_radix(columns[0], 16)
+                    .with_context(|| format!("{}: bad scancode {}", context(), columns[0]))?;
+                let Some(key) = KeyCode::from_scancode(scancode) else {
+                    log::debug!(target: "klc", "{}: unknown scancode 0x{:x}", context(), scancode);
+                    continue;
+                };
+                keys_by_vk.insert(columns[1].to_owned(), key);
+                for (state, column) in shift_states.iter().zip(&columns[3..]) {
+                    let Some(level) = shift_level(*state) else {
+                        continue;
+                    };
+                    if let Some(output) = parse_character(column).with_context(context)? {
+                        layout.set(key, level, output);
+                    }
+                }
+            }
+            Section::Ligature => {
+                // VK_	Mod#	Char0	Char1	Char2	Char3
+                if columns.len() < 3 {
+                    bail!("{}: expected virtual key, modifier and characters", context());
+                }
+                let key = *keys_by_vk
+                    .get(columns[0])
+                    .ok_or_else(|| anyhow!("{}: {} is not in the layout", context(), columns[0]))?;
+                let column: usize = columns[1].parse().with_context(|| format!("{}: bad modifier", context()))?;
+                let Some(level) = shift_states.get(column).copied().and_then(shift_level) else {
+                    continue;
+                };
+                let output = columns[2..]
+                    .iter()
+                    .map(|column| parse_character(column))
+                    .collect::<anyhow::Result<Vec<_>>>()
+                    .with_context(context)?
+                    .into_iter()
+                    .flatten()
+                    .collect::<String>();
+                layout.set(key, level, output);
+            }
+            Section::Header | Section::Other => {}
+        }
+    }
+
+    if layout.is_empty() {
+        bail!("No LAYOUT section");
+    }
+    Ok(layout)
+}
+
+pub fn load(path: &Path) -> anyhow::Result<KeyboardLayout> {
+    let bytes = fs::read(path).with_context(|| format!("Can't read {:?}", path))?;
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    decode(&bytes)
+        .and_then(|text| parse(&text, &name))
+        .with_context(|| format!("Can't parse {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KLC: &str = "\
+KBD\tKBDTEST\t\"Test\"
+
+SHIFTSTATE
+
+0\t//Column 4
+1\t//Column 5 : Shft
+2\t//Column 6 :       Ctrl
+6\t//Column 7 :       Ctrl Alt
+7\t//Column 8 : Shft  Ctrl Alt
+
+LAYOUT\t\t;an extra '@' at the end is a dead key
+
+//SC\tVK_\t\tCap\t0\t1\t2\t6\t7
+//--\t----\t\t----\t----\t----\t----\t----\t----
+
+1e\tA\t\t1\ta\tA\t-1\t00e1\t00c1\t// a, A, á, Á
+1f\tS\t\tSGCap\ts\tS\t-1\t%%\t-1\t// s, S
+-1\t-1\t\t0\tS\ts\t// caps lock row
+10\tQ\t\t1\t%%\tQ\t-1\t-1\t-1
+12\tE\t\t1\te\tE\t-1\t00b4@\t-1
+
+LIGATURE
+
+//VK_\tMod#\tChar0\tChar1
+//----\t----\t----\t----
+
+S\t3\t0073\t030c\t// s + combining caron
+Q\t0\tq\tu
+
+DEADKEY\t00b4
+
+0065\t00e9
+
+ENDKBD
+";
+
+    #[test]
+    fn ligatures_and_caps_rows() {
+        let layout = parse(KLC, "default").unwrap();
+        assert_eq!(layout.name, "Test");
+        assert_eq!(layout.output(KeyCode::KeyA, ShiftLevel::AltGr), Some("á"));
+        assert_eq!(layout.output(KeyCode::KeyA, ShiftLevel::ShiftAltGr), Some("Á"));
+        // The caps lock row doesn't overwrite the key above it
+        assert_eq!(layout.output(KeyCode::KeyS, ShiftLevel::Base), Some("s"));
+        assert_eq!(layout.output(KeyCode::KeyS, ShiftLevel::Shift), Some("S"));
+        assert_eq!(layout.output(KeyCode::KeyS, ShiftLevel::AltGr), Some("s\u{30c}"));
+        assert_eq!(layout.output(KeyCode::KeyQ, ShiftLevel::Base), Some("qu"));
+        // Dead keys type nothing by themselves
+        assert_eq!(layout.output(KeyCode::KeyE, ShiftLevel::AltGr), None);
+    }
+
+    #[test]
+    fn broken_files_are_rejected() {
+        assert!(parse("KBD\tKBDTEST\t\"Test\"\nENDKBD\n", "default").is_err());
+        assert!(parse("SHIFTSTATE\n0\nLAYOUT\n1e\tA\t0\tzz\n", "default").is_err());
+        assert!(parse("SHIFTSTATE\n0\nLAYOUT\n1e\tA\t0\ta\nLIGATURE\nB\t0\tb\tc\n", "default").is_err());
+    }
+
+    #[test]
+    fn utf16_is_decoded() {
+        let mut bytes = vec![0xff, 0xfe];
+        bytes.extend("KBD".encode_utf16().flat_map(u16::to_le_bytes));
+        assert_eq!(decode(&bytes).unwrap(), "KBD");
+    }
+}
@@ -0,0 +1,418 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+
+use crate::backend::{offline_translator, KeyTranslator, ToUnicode};
+use crate::keycode::KeyCode;
+use crate::r#static::KEYBOARD_LAYOUTS;
+use crate::r#type::Dump;
+
+pub mod compose;
+pub mod klc;
+pub(crate) mod keysym;
+pub mod xkb;
+
+/// Comma separated layout names (files in [`LAYOUT_DIRECTORY`]) or paths to layout files.
+pub const ENV_LAYOUTS: &str = "DIAKRITIKA_LAYOUTS";
+pub const LAYOUT_DIRECTORY: &str = "layouts";
+
+/// Modifier combination a layout maps separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ShiftLevel {
+    Base = 0,
+    Shift = 1,
+    AltGr = 2,
+    ShiftAltGr = 3,
+}
+
+impl ShiftLevel {
+    pub const ALL: [ShiftLevel; 4] = [
+        ShiftLevel::Base,
+        ShiftLevel::Shift,
+        ShiftLevel::AltGr,
+        ShiftLevel::ShiftAltGr,
+    ];
+
+    pub fn from_index(index: usize) -> Option<Self> {
+        Self::ALL.get(index).copied()
+    }
+}
+
+/// What every key of a keyboard layout types on every shift level.
+///
+/// Lets `Key::Character` bindings be resolved without asking the OS, so they behave the same on
+/// every platform and in CI.
+#[derive(Debug, Clone, Default)]
+pub struct KeyboardLayout {
+    pub name: String,
+    keys: BTreeMap<KeyCode, [Option<String>; 4]>,
+}
+
+impl KeyboardLayout {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            keys: BTreeMap::new(),
+        }
+    }
+
+    pub fn set(&mut self, key: KeyCode, level: ShiftLevel, output: String) {
+        self.keys.entry(key).or_default()[level as usize] = Some(output);
+    }
+
+    pub fn output(&self, key: KeyCode, level: ShiftLevel) -> Option<&str> {
+        self.keys
+            .get(&key)
+            .and_then(|levels| levels[level as usize].as_deref())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Parses the `.layout` format:
+    ///
+    /// ```text
+    /// # comment
+    /// name ru
+    /// q й Й
+    /// oem_1 ж Ж
+    /// ```
+    ///
+    /// Every key line is a key name (see [`KeyCode::from_name`]) followed by up to four outputs,
+    /// one per [`ShiftLevel`]. `-` leaves a level empty and `U+XXXX` stands for characters that
+    /// can't be written literally, like space.
+    pub fn parse(text: &str, default_name: &str) -> anyhow::Result<Self> {
+        let mut layout = Self::new(default_name);
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let head = parts.next().expect("Non-empty line without parts");
+            if head == "name" {
+                layout.name = parts.collect::<Vec<_>>().join(" ");
+                continue;
+            }
+            let key = KeyCode::from_name(head)
+                .with_context(|| format!("line {}: unknown key {head}", number + 1))?;
+            for (index, output) in parts.enumerate() {
+                let level = ShiftLevel::from_index(index)
+                    .ok_or_else(|| anyhow!("line {}: more than 4 levels", number + 1))?;
+                if let Some(output) = Self::parse_output(output)
+                    .with_context(|| format!("line {}", number + 1))?
+                {
+                    layout.set(key, level, output);
+                }
+            }
+        }
+        Ok(layout)
+    }
+
+    fn parse_output(output: &str) -> anyhow::Result<Option<String>> {
+        if output == "-" {
+            Ok(None)
+        } else if let Some(hex) = output.strip_prefix("U+") {
+            let code = u32::from_str_radix(hex, 16).with_context(|| format!("bad code point {output}"))?;
+            let char = char::from_u32(code).ok_or_else(|| anyhow!("bad code point {output}"))?;
+            Ok(Some(char.to_string()))
+        } else {
+            Ok(Some(output.to_owned()))
+        }
+    }
+
+    /// What `translator` says the keys type without modifiers.
+    pub fn from_translator(name: &str, translator: &dyn KeyTranslator) -> Self {
+        let mut layout = Self::new(name);
+        for key in KeyCode::physical() {
+            if let Some(output) = translator.to_unicode(key, true) {
+                layout.set(key, ShiftLevel::Base, output);
+            }
+        }
+        layout
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("Can't read {:?}", path))?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self::parse(&text, &name).with_context(|| format!("Can't parse {:?}", path))
+    }
+}
+
+impl Dump for KeyboardLayout {
+    fn dump(&self) -> String {
+        self.keys
+            .iter()
+            .map(|(key, levels)| {
+                let outputs = levels
+                    .iter()
+                    .map(|output| output.as_deref().unwrap_or("-"))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                format!("{} {}", key.name(), outputs)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Layouts the user types with.
+///
+/// Keys match the characters of the active layout, which the backends follow as the user switches
+/// layouts. Without an active one a key matches a character if any of the layouts puts it on it.
+#[derive(Default)]
+pub struct KeyboardLayouts {
+    layouts: Vec<KeyboardLayout>,
+    active: Option<usize>,
+}
+
+impl KeyboardLayouts {
+    pub fn add(&mut self, layout: KeyboardLayout) {
+        log::info!("Loaded keyboard layout {} with {} keys", layout.name, layout.keys.len());
+        self.layouts.push(layout);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layouts.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.layouts.len()
+    }
+
+    pub fn set_active(&mut self, index: Option<usize>) {
+        let active = index.filter(|index| *index < self.layouts.len());
+        if active != self.active {
+            let name = active.map_or("all", |index| self.layouts[index].name.as_str());
+            log::debug!("Active keyboard layout: {}", name);
+        }
+        self.active = active;
+    }
+
+    /// Activates the layout that agrees most with the current layout of the OS, `translate` is
+    /// what a key types in it without modifiers. Returns the index of the layout.
+    #[cfg(any(windows, feature = "x11", test))]
+    pub fn activate_matching(&mut self, translate: impl Fn(KeyCode) -> Option<String>) -> Option<usize> {
+        if self.layouts.is_empty() {
+            return None;
+        }
+        let typed = KeyCode::physical()
+            .filter_map(|key| Some((key, translate(key)?)))
+            .collect::<Vec<_>>();
+        let best = self
+            .layouts
+            .iter()
+            .map(|layout| {
+                typed
+                    .iter()
+                    .filter(|(key, output)| layout.output(*key, ShiftLevel::Base) == Some(output))
+                    .count()
+            })
+            .enumerate()
+            // The first one of equally good layouts
+            .max_by_key(|(index, matching)| (*matching, std::cmp::Reverse(*index)))
+            .filter(|(_, matching)| *matching > 0)
+            .map(|(index, _)| index);
+        self.set_active(best);
+        best
+    }
+
+    pub fn active(&self) -> impl Iterator<Item = &KeyboardLayout> {
+        self.layouts
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| self.active.is_none_or(|active| active == *index))
+            .map(|(_, layout)| layout)
+    }
+
+    /// Everything `key` types on `level` in the active layouts.
+    pub fn outputs(&self, key: KeyCode, level: ShiftLevel) -> Vec<String> {
+        let mut outputs = Vec::new();
+        for layout in self.active() {
+            if let Some(output) = layout.output(key, level) {
+                if !outputs.iter().any(|known| known == output) {
+                    outputs.push(output.to_owned());
+                }
+            }
+        }
+        outputs
+    }
+
+    /// Loads layouts by name from `directory`, from `.layout`, `.klc` and `.xkb` files, from XKB symbols
+    /// files and from the XKB database with `xkb:` and a symbols include like `xkb:us+ru:2`.
+    pub fn load(names: &[&str], directory: &Path) -> anyhow::Result<Self> {
+        let mut layouts = Self::default();
+        for name in names {
+            let loaded = if let Some(spec) = name.strip_prefix("xkb:") {
+                xkb::XkbImporter::from_env().import_symbols(spec)?
+            } else if !name.contains(['/', '\\', '.']) {
+                vec![KeyboardLayout::load(&directory.join(format!("{name}.layout")))?]
+            } else {
+                let path = Path::new(name);
+                match path.extension().and_then(|extension| extension.to_str()) {
+                    Some("layout") => vec![KeyboardLayout::load(path)?],
+                    Some("xkb") => xkb::XkbImporter::from_env().import_keymap_file(path)?,
+                    Some("klc") => vec![klc::load(path)?],
+                    _ => xkb::XkbImporter::from_env().import_symbols_file(path, None)?,
+                }
+            };
+            loaded.into_iter().for_each(|layout| layouts.add(layout));
+        }
+        Ok(layouts)
+    }
+}
+
+/// Loads the layouts listed in [`ENV_LAYOUTS`] into [`KEYBOARD_LAYOUTS`]. Without it the XKB
+/// layouts of the session are used on Linux.
+pub fn load_from_env() -> anyhow::Result<()> {
+    let Ok(names) = std::env::var(ENV_LAYOUTS) else {
+        load_system_layouts();
+        return Ok(());
+    };
+    let names = names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<_>>();
+    *KEYBOARD_LAYOUTS.write() = KeyboardLayouts::load(&names, Path::new(LAYOUT_DIRECTORY))?;
+    // Like the groups of XKB, the first one is active until the user switches
+    KEYBOARD_LAYOUTS.write().set_active(Some(0));
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn load_system_layouts() {
+    match xkb::system_layouts() {
+        Ok(loaded) => {
+            let mut layouts = KEYBOARD_LAYOUTS.write();
+            loaded.into_iter().for_each(|layout| layouts.add(layout));
+            // Every group is loaded, but only the current one types
+            layouts.set_active(Some(0));
+        }
+        Err(e) => log::warn!("Can't load the XKB layouts, characters are resolved by the OS: {:?}", e),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn load_system_layouts() {
+    log::info!("{} is not set, characters are resolved by the OS", ENV_LAYOUTS);
+}
+
+/// Resolves characters without the input backend from now on, for `check`. When no layouts were
+/// loaded, the translator of the platform fills one in.
+pub fn load_offline() {
+    let mut layouts = KEYBOARD_LAYOUTS.write();
+    if layouts.is_empty() {
+        layouts.add(KeyboardLayout::from_translator("system", offline_translator()));
+    }
+}
+
+/// Characters `key` types without modifiers. Asks the OS when no layouts were loaded.
+pub fn characters_of(key: KeyCode) -> Vec<String> {
+    let layouts = KEYBOARD_LAYOUTS.read();
+    if layouts.is_empty() {
+        key.to_unicode_localized().into_iter().collect()
+    } else {
+        layouts.outputs(key, ShiftLevel::Base)
+    }
+}
+
+/// How many layouts keys can type characters in, one when the OS is asked.
+pub fn layout_count() -> usize {
+    KEYBOARD_LAYOUTS.read().len().max(1)
+}
+
+/// What `key` types without modifiers in the layout at `index`, whether it's active or not.
+pub fn character_in(index: usize, key: KeyCode) -> Option<String> {
+    let layouts = KEYBOARD_LAYOUTS.read();
+    if layouts.is_empty() {
+        key.to_unicode_localized()
+    } else {
+        let layout = layouts.layouts.get(index)?;
+        layout.output(key, ShiftLevel::Base).map(str::to_owned)
+    }
+}
+
+/// Has characters resolve in the US and the Russian layout, for the tests. Loaded once for all of
+/// them.
+#[cfg(test)]
+pub(crate) fn load_test_layouts() {
+    static LOADED: std::sync::Once = std::sync::Once::new();
+    LOADED.call_once(|| {
+        let mut layouts = KEYBOARD_LAYOUTS.write();
+        for text in [include_str!("../../layouts/us.layout"), include_str!("../../layouts/ru.layout")] {
+            layouts.add(KeyboardLayout::parse(text, "test").unwrap());
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn us_and_ru() -> KeyboardLayouts {
+        let mut layouts = KeyboardLayouts::default();
+        for text in [include_str!("../../layouts/us.layout"), include_str!("../../layouts/ru.layout")] {
+            layouts.add(KeyboardLayout::parse(text, "test").unwrap());
+        }
+        layouts
+    }
+
+    #[test]
+    fn parses_levels_empty_levels_and_code_points() {
+        let text = "# comment\nname test layout\n\nspace U+0020\noem_1 ; : - ж\n";
+        let layout = KeyboardLayout::parse(text, "default").unwrap();
+        assert_eq!(layout.name, "test layout");
+        assert_eq!(layout.output(KeyCode::Space, ShiftLevel::Base), Some(" "));
+        assert_eq!(layout.output(KeyCode::Semicolon, ShiftLevel::Shift), Some(":"));
+        assert_eq!(layout.output(KeyCode::Semicolon, ShiftLevel::AltGr), None);
+        assert_eq!(layout.output(KeyCode::Semicolon, ShiftLevel::ShiftAltGr), Some("ж"));
+        assert_eq!(KeyboardLayout::parse("", "default").unwrap().name, "default");
+    }
+
+    #[test]
+    fn broken_lines_fail_with_their_number() {
+        for text in ["q й\nnokey x\n", "q й\nq 1 2 3 4 5\n", "\nq U+D800\n"] {
+            let error = KeyboardLayout::parse(text, "test").unwrap_err();
+            assert!(format!("{:#}", error).starts_with("line 2"), "{:#}", error);
+        }
+    }
+
+    #[test]
+    fn shift_levels_of_a_key() {
+        let layouts = us_and_ru();
+        assert_eq!(layouts.outputs(KeyCode::KeyS, ShiftLevel::Base), vec!["s", "ы"]);
+        assert_eq!(layouts.outputs(KeyCode::KeyS, ShiftLevel::Shift), vec!["S", "Ы"]);
+        assert!(layouts.outputs(KeyCode::KeyS, ShiftLevel::AltGr).is_empty());
+    }
+
+    #[test]
+    fn characters_of_a_key_in_every_layout() {
+        load_test_layouts();
+        assert_eq!(characters_of(KeyCode::KeyS), vec!["s", "ы"]);
+        assert_eq!(characters_of(KeyCode::Digit1), vec!["1"]);
+        assert_eq!(character_in(1, KeyCode::KeyS).as_deref(), Some("ы"));
+    }
+
+    #[test]
+    fn activates_the_layout_matching_the_os() {
+        let mut layouts = us_and_ru();
+        let ru = KeyboardLayout::parse(include_str!("../../layouts/ru.layout"), "ru").unwrap();
+        let typed_in_ru = |key| ru.output(key, ShiftLevel::Base).map(str::to_owned);
+        assert_eq!(layouts.activate_matching(typed_in_ru), Some(1));
+        assert_eq!(layouts.outputs(KeyCode::KeyS, ShiftLevel::Base), vec!["ы"]);
+
+        // Digits are the same in both, the first one wins
+        let digits = |key: KeyCode| (key == KeyCode::Digit1).then(|| "1".to_owned());
+        assert_eq!(layouts.activate_matching(digits), Some(0));
+
+        assert_eq!(layouts.activate_matching(|_| None), None);
+        assert_eq!(layouts.outputs(KeyCode::KeyS, ShiftLevel::Base), vec!["s", "ы"]);
+    }
+}
@@ -0,0 +1,951 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+#[cfg(target_os = "linux")]
+use std::process::Command;
+
+use anyhow::{anyhow, bail, Context};
+
+use crate::keycode::KeyCode;
+use crate::layout::keysym::{keysym_from_name, keysym_to_char};
+use crate::layout::{KeyboardLayout, ShiftLevel};
+
+/// Directory with the `keycodes` and `symbols` databases, same variable as libxkbcommon uses.
+pub const ENV_XKB_ROOT: &str = "XKB_CONFIG_ROOT";
+pub const DEFAULT_XKB_ROOT: &str = "/usr/share/X11/xkb";
+/// Keycodes the `evdev` rules pick for every model.
+const DEFAULT_KEYCODES: &str = "evdev+aliases(qwerty)";
+/// X11 keycodes are evdev codes shifted by 8.
+const KEYCODE_OFFSET: u32 = 8;
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    KeyName(String),
+    Punct(char),
+}
+
+/// Token with the line it starts on.
+type Spanned = (Token, usize);
+
+fn tokenize(text: &str) -> anyhow::Result<Vec<Spanned>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while let Some(char) = chars.next() {
+        match char {
+            '\n' => line += 1,
+            char if char.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.next_if(|next| *next != '\n').is_some() {}
+            }
+            '#' => while chars.next_if(|next| *next != '\n').is_some() {},
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if previous == '*' => break,
+                        Some(next) => {
+                            if next == '\n' {
+                                line += 1;
+                            }
+                            previous = next;
+                        }
+                        None => bail!("line {}: unterminated comment", line),
+                    }
+                }
+            }
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => string.extend(chars.next()),
+                        Some(next) => string.push(next),
+                        None => bail!("line {}: unterminated string", line),
+                    }
+                }
+                tokens.push((Token::Str(string), line));
+            }
+            '<' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('>') => break,
+                        Some(next) if !next.is_whitespace() => name.push(next),
+                        _ => bail!("line {}: unterminated key name", line),
+                    }
+                }
+                tokens.push((Token::KeyName(name), line));
+            }
+            char if char.is_alphanumeric() || char == '_' => {
+                let mut ident = String::from(char);
+                while let Some(next) =
+                    chars.next_if(|next| next.is_alphanumeric() || *next == '_' || *next == '.')
+                {
+                    ident.push(next);
+                }
+                tokens.push((Token::Ident(ident), line));
+            }
+            char => tokens.push((Token::Punct(char), line)),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Spanned],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(tokens: &'a [Spanned]) -> Self {
+        Self {
+            tokens,
+            position: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&'a Token> {
+        self.tokens.get(self.position + offset).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.peek();
+        self.position += 1;
+        token
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.position.min(self.tokens.len().saturating_sub(1)))
+            .map_or(0, |(_, line)| *line)
+    }
+
+    fn error(&self, expected: &str) -> anyhow::Error {
+        anyhow!("line {}: expected {}, got {:?}", self.line(), expected, self.peek())
+    }
+
+    fn eat_punct(&mut self, punct: char) -> bool {
+        if self.peek() == Some(&Token::Punct(punct)) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_punct(&mut self, punct: char) -> anyhow::Result<()> {
+        if self.eat_punct(punct) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("'{}'", punct)))
+        }
+    }
+
+    fn expect_str(&mut self) -> anyhow::Result<String> {
+        match self.peek() {
+            Some(Token::Str(string)) => {
+                self.position += 1;
+                Ok(string.clone())
+            }
+            _ => Err(self.error("a string")),
+        }
+    }
+
+    fn expect_key_name(&mut self) -> anyhow::Result<String> {
+        match self.peek() {
+            Some(Token::KeyName(name)) => {
+                self.position += 1;
+                Ok(name.clone())
+            }
+            _ => Err(self.error("a key name")),
+        }
+    }
+
+    fn expect_number(&mut self) -> anyhow::Result<u32> {
+        match self.peek() {
+            Some(Token::Ident(number)) => {
+                let parsed = match number.strip_prefix("0x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => number.parse().ok(),
+                };
+                let parsed = parsed.ok_or_else(|| self.error("a number"))?;
+                self.position += 1;
+                Ok(parsed)
+            }
+            _ => Err(self.error("a number")),
+        }
+    }
+
+    /// `[Group2]` as a zero based index, 0 when there is no index at all.
+    fn group_index(&mut self) -> anyhow::Result<usize> {
+        if !self.eat_punct('[') {
+            return Ok(0);
+        }
+        let group = match self.next() {
+            Some(Token::Ident(group)) => group
+                .trim_start_matches(|char: char| char.is_alphabetic())
+                .parse::<usize>()
+                .ok()
+                .filter(|group| *group > 0),
+            _ => None,
+        };
+        let group = group.ok_or_else(|| anyhow!("line {}: bad group index", self.line()))?;
+        self.expect_punct(']')?;
+        Ok(group - 1)
+    }
+
+    /// `[ a, A, aacute ]`, with `None` for levels without a character keysym.
+    fn keysym_list(&mut self) -> anyhow::Result<Vec<Option<u32>>> {
+        self.expect_punct('[')?;
+        let mut keysyms = Vec::new();
+        loop {
+            match self.next() {
+                Some(Token::Punct(']')) => break,
+                Some(Token::Punct(',')) => {}
+                Some(Token::Ident(name)) => keysyms.push(keysym_from_name(name)),
+                // Several keysyms on one level, only the first one is typed by itself
+                Some(Token::Punct('{')) => {
+                    let mut first = None;
+                    loop {
+                        match self.next() {
+                            Some(Token::Punct('}')) => break,
+                            Some(Token::Ident(name)) if first.is_none() => {
+                                first = Some(keysym_from_name(name))
+                            }
+                            Some(_) => {}
+                            None => bail!("line {}: unterminated keysym list", self.line()),
+                        }
+                    }
+                    keysyms.push(first.flatten());
+                }
+                _ => return Err(self.error("a keysym")),
+            }
+        }
+        Ok(keysyms)
+    }
+
+    /// Skips tokens up to the end of the current statement.
+    fn skip_statement(&mut self) {
+        let mut depth = 0;
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Punct('{' | '[' | '(') => depth += 1,
+                Token::Punct('}' | ']' | ')') if depth == 0 => return,
+                Token::Punct('}' | ']' | ')') => depth -= 1,
+                Token::Punct(';') if depth == 0 => {
+                    self.position += 1;
+                    return;
+                }
+                _ => {}
+            }
+            self.position += 1;
+        }
+    }
+
+    /// Skips tokens up to the next `,` or `}` of the current block.
+    fn skip_entry(&mut self) {
+        let mut depth = 0;
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Punct(',' | '}') if depth == 0 => return,
+                Token::Punct('{' | '[' | '(') => depth += 1,
+                Token::Punct('}' | ']' | ')') => depth -= 1,
+                _ => {}
+            }
+            self.position += 1;
+        }
+    }
+}
+
+/// `xkb_symbols "name" { ... };` and its siblings.
+struct Section {
+    flags: Vec<String>,
+    kind: String,
+    name: Option<String>,
+    body: Vec<Spanned>,
+}
+
+fn sections(tokens: &[Spanned]) -> anyhow::Result<Vec<Section>> {
+    let mut parser = Parser::new(tokens);
+    let mut sections = Vec::new();
+    let mut flags = Vec::new();
+    while let Some(token) = parser.next() {
+        match token {
+            Token::Ident(kind) if kind.starts_with("xkb_") => {
+                let name = match parser.peek() {
+                    Some(Token::Str(name)) => {
+                        parser.next();
+                        Some(name.clone())
+                    }
+                    _ => None,
+                };
+                parser.expect_punct('{')?;
+                let start = parser.position;
+                let mut depth = 1;
+                while depth > 0 {
+                    match parser.next() {
+                        Some(Token::Punct('{')) => depth += 1,
+                        Some(Token::Punct('}')) => depth -= 1,
+                        Some(_) => {}
+                        None => bail!("Section {} is not terminated", kind),
+                    }
+                }
+                sections.push(Section {
+                    flags: std::mem::take(&mut flags),
+                    kind: kind.clone(),
+                    name,
+                    body: tokens[start..parser.position - 1].to_vec(),
+                });
+                parser.eat_punct(';');
+            }
+            Token::Ident(flag) => flags.push(flag.clone()),
+            Token::Punct(';') => {}
+            _ => bail!("line {}: unexpected {:?}", parser.line(), token),
+        }
+    }
+    Ok(sections)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Merge {
+    Override,
+    Augment,
+}
+
+impl Merge {
+    fn from_keyword(keyword: &str) -> Option<Self> {
+        match keyword {
+            "include" | "override" | "replace" => Some(Merge::Override),
+            "augment" => Some(Merge::Augment),
+            _ => None,
+        }
+    }
+}
+
+/// One part of an include like `ru(winkeys):2`.
+struct Include {
+    merge: Merge,
+    file: String,
+    section: Option<String>,
+    group: Option<usize>,
+}
+
+fn parse_include(spec: &str, merge: Merge) -> Vec<Include> {
+    let mut includes = Vec::new();
+    let mut merge = merge;
+    let mut rest = spec;
+    while !rest.is_empty() {
+        let end = rest.find(['+', '|']).unwrap_or(rest.len());
+        let (component, group) = match rest[..end].split_once(':') {
+            Some((component, group)) => (component, group.parse::<usize>().ok()),
+            None => (&rest[..end], None),
+        };
+        let (file, section) = match component.split_once('(') {
+            Some((file, section)) => (file, Some(section.trim_end_matches(')').to_owned())),
+            None => (component, None),
+        };
+        if !file.is_empty() {
+            includes.push(Include {
+                merge,
+                file: file.to_owned(),
+                section,
+                group: group.filter(|group| *group > 0).map(|group| group - 1),
+            });
+        }
+        merge = if rest[end..].starts_with('|') {
+            Merge::Augment
+        } else {
+            Merge::Override
+        };
+        rest = rest.get(end + 1..).unwrap_or_default();
+    }
+    includes
+}
+
+/// Content of an XKB section kind that can be included from the XKB database.
+trait Component: Default {
+    const DIRECTORY: &'static str;
+    const KIND: &'static str;
+
+    fn merge(&mut self, other: Self, merge: Merge, group: Option<usize>);
+
+    /// Parses one statement that isn't an include.
+    fn statement(&mut self, parser: &mut Parser) -> anyhow::Result<()>;
+}
+
+#[derive(Default)]
+struct Keycodes {
+    codes: BTreeMap<String, u32>,
+    aliases: BTreeMap<String, String>,
+}
+
+impl Keycodes {
+    fn key_code(&self, name: &str) -> Option<KeyCode> {
+        let name = self.aliases.get(name).map_or(name, String::as_str);
+        self.codes
+            .get(name)
+            .and_then(|code| code.checked_sub(KEYCODE_OFFSET))
+            .and_then(|code| u16::try_from(code).ok())
+            .and_then(KeyCode::from_evdev)
+    }
+}
+
+impl Component for Keycodes {
+    const DIRECTORY: &'static str = "keycodes";
+    const KIND: &'static str = "xkb_keycodes";
+
+    fn merge(&mut self, other: Self, merge: Merge, _group: Option<usize>) {
+        for (name, code) in other.codes {
+            if merge == Merge::Override || !self.codes.contains_key(&name) {
+                self.codes.insert(name, code);
+            }
+        }
+        for (alias, name) in other.aliases {
+            if merge == Merge::Override || !self.aliases.contains_key(&alias) {
+                self.aliases.insert(alias, name);
+            }
+        }
+    }
+
+    fn statement(&mut self, parser: &mut Parser) -> anyhow::Result<()> {
+        match parser.peek() {
+            Some(Token::KeyName(_)) => {
+                let name = parser.expect_key_name()?;
+                parser.expect_punct('=')?;
+                let code = parser.expect_number()?;
+                self.codes.insert(name, code);
+                parser.eat_punct(';');
+            }
+            Some(Token::Ident(word)) if word == "alias" => {
+                parser.next();
+                let alias = parser.expect_key_name()?;
+                parser.expect_punct('=')?;
+                let name = parser.expect_key_name()?;
+                self.aliases.insert(alias, name);
+                parser.eat_punct(';');
+            }
+            Some(Token::Punct(';')) => {
+                parser.next();
+            }
+            _ => parser.skip_statement(),
+        }
+        Ok(())
+    }
+}
+
+/// Keysyms by key name, group and level.
+#[derive(Default)]
+struct Symbols {
+    names: BTreeMap<usize, String>,
+    keys: BTreeMap<String, BTreeMap<usize, Vec<Option<u32>>>>,
+}
+
+impl Symbols {
+    fn set(&mut self, key: &str, group: usize, keysyms: Vec<Option<u32>>, merge: Merge) {
+        let levels = self
+            .keys
+            .entry(key.to_owned())
+            .or_default()
+            .entry(group)
+            .or_default();
+        if levels.len() < keysyms.len() {
+            levels.resize(keysyms.len(), None);
+        }
+        for (level, keysym) in keysyms.into_iter().enumerate() {
+            if keysym.is_some() && (merge == Merge::Override || levels[level].is_none()) {
+                levels[level] = keysym;
+            }
+        }
+    }
+
+    fn key(&mut self, parser: &mut Parser, merge: Merge) -> anyhow::Result<()> {
+        let name = parser.expect_key_name()?;
+        parser.expect_punct('{')?;
+        let mut next_group = 0;
+        loop {
+            match parser.peek() {
+                Some(Token::Punct('}')) => {
+                    parser.next();
+                    break;
+                }
+                Some(Token::Punct(',')) => {
+                    parser.next();
+                }
+                Some(Token::Punct('[')) => {
+                    let keysyms = parser.keysym_list()?;
+                    self.set(&name, next_group, keysyms, merge);
+                    next_group += 1;
+                }
+                Some(Token::Ident(word)) if word.eq_ignore_ascii_case("symbols") => {
+                    parser.next();
+                    let group = parser.group_index()?;
+                    parser.expect_punct('=')?;
+                    let keysyms = parser.keysym_list()?;
+                    self.set(&name, group, keysyms, merge);
+                    next_group = group + 1;
+                }
+                Some(_) => parser.skip_entry(),
+                None => bail!("Key <{}> is not terminated", name),
+            }
+        }
+        parser.eat_punct(';');
+        Ok(())
+    }
+
+    /// One layout per group. Keys with fewer groups wrap around, the way XKB does it.
+    fn layouts(&self, keycodes: &Keycodes, default_name: &str) -> Vec<KeyboardLayout> {
+        let group_count = self
+            .keys
+            .values()
+            .filter_map(|groups| groups.keys().max())
+            .max()
+            .map_or(0, |group| group + 1);
+        (0..group_count)
+            .map(|group| {
+                let name = self
+                    .names
+                    .get(&group)
+                    .cloned()
+                    .unwrap_or_else(|| format!("{} {}", default_name, group + 1));
+                let mut layout = KeyboardLayout::new(&name);
+                for (key_name, groups) in &self.keys {
+                    let Some(key) = keycodes.key_code(key_name) else {
+                        log::trace!(target: "xkb", "No key code for <{}>", key_name);
+                        continue;
+                    };
+                    let key_groups = groups.keys().max().map_or(1, |group| group + 1);
+                    let Some(levels) = groups.get(&(group % key_groups)) else {
+                        continue;
+                    };
+                    for (index, keysym) in levels.iter().enumerate() {
+                        let level = ShiftLevel::from_index(index);
+                        let char = keysym.and_then(keysym_to_char);
+                        if let (Some(level), Some(char)) = (level, char) {
+                            layout.set(key, level, char.to_string());
+                        }
+                    }
+                }
+                layout
+            })
+            .collect()
+    }
+}
+
+impl Component for Symbols {
+    const DIRECTORY: &'static str = "symbols";
+    const KIND: &'static str = "xkb_symbols";
+
+    fn merge(&mut self, other: Self, merge: Merge, group: Option<usize>) {
+        let target = |source: usize| match group {
+            Some(group) if source == 0 => group,
+            _ => source,
+        };
+        for (source, name) in other.names {
+            if merge == Merge::Override || !self.names.contains_key(&target(source)) {
+                self.names.insert(target(source), name);
+            }
+        }
+        for (key, groups) in other.keys {
+            for (source, keysyms) in groups {
+                self.set(&key, target(source), keysyms, merge);
+            }
+        }
+    }
+
+    fn statement(&mut self, parser: &mut Parser) -> anyhow::Result<()> {
+        let mut merge = Merge::Override;
+        if let Some(Token::Ident(word)) = parser.peek() {
+            if let (Some(keyword), Some(Token::Ident(_))) = (Merge::from_keyword(word), parser.peek_at(1)) {
+                merge = keyword;
+                parser.next();
+            }
+        }
+        match parser.peek() {
+            Some(Token::Ident(word)) if word == "key" => {
+                parser.next();
+                self.key(parser, merge)?;
+            }
+            Some(Token::Ident(word)) if word == "name" => {
+                parser.next();
+                let group = parser.group_index()?;
+                parser.expect_punct('=')?;
+                let name = parser.expect_str()?;
+                self.names.insert(group, name);
+                parser.eat_punct(';');
+            }
+            Some(Token::Punct(';')) => {
+                parser.next();
+            }
+            _ => parser.skip_statement(),
+        }
+        Ok(())
+    }
+}
+
+/// Reads layouts from the XKB database (`/usr/share/X11/xkb`) and from keymaps printed by
+/// `xkbcomp` or `setxkbmap -print`.
+///
+/// Only what is needed to know which characters the keys type is understood: key codes, key
+/// symbols, group names and includes. Types, actions and modifier maps are skipped.
+pub struct XkbImporter {
+    root: PathBuf,
+    depth: usize,
+}
+
+impl XkbImporter {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_owned(),
+            depth: 0,
+        }
+    }
+
+    /// Uses [`ENV_XKB_ROOT`] or [`DEFAULT_XKB_ROOT`].
+    pub fn from_env() -> Self {
+        Self::new(Path::new(
+            &env::var(ENV_XKB_ROOT).unwrap_or_else(|_| DEFAULT_XKB_ROOT.to_owned()),
+        ))
+    }
+
+    /// Layouts of a symbols include like `us+ru(winkeys):2`, one per group.
+    pub fn import_symbols(&mut self, spec: &str) -> anyhow::Result<Vec<KeyboardLayout>> {
+        let keycodes = self.load::<Keycodes>(DEFAULT_KEYCODES, Merge::Override)?;
+        let symbols = self.load::<Symbols>(spec, Merge::Override)?;
+        Ok(symbols.layouts(&keycodes, spec))
+    }
+
+    /// Layouts of a standalone symbols file. Its includes are looked up in the XKB database.
+    pub fn import_symbols_file(&mut self, path: &Path, section: Option<&str>) -> anyhow::Result<Vec<KeyboardLayout>> {
+        let text = fs::read_to_string(path).with_context(|| format!("Can't read {:?}", path))?;
+        let sections = sections(&tokenize(&text)?)?;
+        let section = Self::pick_section(sections, Symbols::KIND, section)
+            .with_context(|| format!("No symbols in {:?}", path))?;
+        let keycodes = self.load::<Keycodes>(DEFAULT_KEYCODES, Merge::Override)?;
+        let symbols = self.parse_body::<Symbols>(&section.body)?;
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        Ok(symbols.layouts(&keycodes, &name))
+    }
+
+    /// Layouts of a keymap, one per group. Keycodes fall back to the XKB database when the keymap
+    /// has none.
+    pub fn import_keymap(&mut self, text: &str) -> anyhow::Result<Vec<KeyboardLayout>> {
+        let mut sections = sections(&tokenize(text)?)?;
+        if let Some(index) = sections.iter().position(|section| section.kind == "xkb_keymap") {
+            sections = self::sections(&sections.swap_remove(index).body)?;
+        }
+        let mut keycodes = match sections.iter().find(|section| section.kind == Keycodes::KIND) {
+            Some(section) => self.parse_body::<Keycodes>(&section.body)?,
+            None => Keycodes::default(),
+        };
+        if keycodes.codes.is_empty() {
+            keycodes = self.load::<Keycodes>(DEFAULT_KEYCODES, Merge::Override)?;
+        }
+        let section = sections
+            .iter()
+            .find(|section| section.kind == Symbols::KIND)
+            .ok_or_else(|| anyhow!("The keymap has no {} section", Symbols::KIND))?;
+        let symbols = self.parse_body::<Symbols>(&section.body)?;
+        Ok(symbols.layouts(&keycodes, section.name.as_deref().unwrap_or("xkb")))
+    }
+
+    pub fn import_keymap_file(&mut self, path: &Path) -> anyhow::Result<Vec<KeyboardLayout>> {
+        let text = fs::read_to_string(path).with_context(|| format!("Can't read {:?}", path))?;
+        self.import_keymap(&text).with_context(|| format!("Can't parse {:?}", path))
+    }
+
+    /// The named section, or the one flagged `default`, or the first one.
+    fn pick_section(sections: Vec<Section>, kind: &str, name: Option<&str>) -> Option<Section> {
+        let mut sections = sections
+            .into_iter()
+            .filter(|section| section.kind == kind)
+            .collect::<Vec<_>>();
+        let index = match name {
+            Some(name) => sections
+                .iter()
+                .position(|section| section.name.as_deref() == Some(name))?,
+            None => sections
+                .iter()
+                .position(|section| section.flags.iter().any(|flag| flag == "default"))
+                .unwrap_or(0),
+        };
+        (index < sections.len()).then(|| sections.swap_remove(index))
+    }
+
+    fn load<T: Component>(&mut self, spec: &str, merge: Merge) -> anyhow::Result<T> {
+        if self.depth >= MAX_INCLUDE_DEPTH {
+            bail!("Includes are nested too deep at {:?}", spec);
+        }
+        self.depth += 1;
+        let result = parse_include(spec, merge)
+            .into_iter()
+            .try_fold(T::default(), |mut component, include| {
+                let path = self.root.join(T::DIRECTORY).join(&include.file);
+                let text = fs::read_to_string(&path).with_context(|| format!("Can't read {:?}", path))?;
+                let sections = sections(&tokenize(&text)?).with_context(|| format!("Can't parse {:?}", path))?;
+                let section = Self::pick_section(sections, T::KIND, include.section.as_deref())
+                    .ok_or_else(|| anyhow!("No section {:?} in {:?}", include.section, path))?;
+                let included = self
+                    .parse_body::<T>(&section.body)
+                    .with_context(|| format!("Can't parse {:?}", path))?;
+                component.merge(included, include.merge, include.group);
+                Ok::<T, anyhow::Error>(component)
+            });
+        self.depth -= 1;
+        result
+    }
+
+    fn parse_body<T: Component>(&mut self, body: &[Spanned]) -> anyhow::Result<T> {
+        let mut parser = Parser::new(body);
+        let mut component = T::default();
+        while let Some(token) = parser.peek() {
+            match (token, parser.peek_at(1)) {
+                (Token::Ident(word), Some(Token::Str(spec))) if Merge::from_keyword(word).is_some() => {
+                    let merge = Merge::from_keyword(word).expect("Checked above");
+                    parser.position += 2;
+                    let included = self.load::<T>(spec, merge)?;
+                    component.merge(included, merge, None);
+                    parser.eat_punct(';');
+                }
+                _ => component.statement(&mut parser)?,
+            }
+        }
+        Ok(component)
+    }
+}
+
+/// Rules, model, layout, variant and options, the names XKB layouts are configured with.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Default)]
+pub struct Rmlvo {
+    pub rules: String,
+    pub model: String,
+    pub layout: String,
+    pub variant: String,
+    pub options: String,
+}
+
+#[cfg(target_os = "linux")]
+impl Rmlvo {
+    /// Parses the output of `setxkbmap -query` as well as `/etc/default/keyboard`.
+    pub fn parse(text: &str) -> Self {
+        let mut rmlvo = Self::default();
+        for line in text.lines() {
+            let (key, value) = match (line.find('='), line.find(':')) {
+                // XKBLAYOUT="us,ru"
+                (Some(equals), colon) if colon.is_none_or(|colon| equals < colon) => {
+                    let key = line[..equals].trim().trim_start_matches("XKB").to_lowercase();
+                    (key, line[equals + 1..].trim().trim_matches('"'))
+                }
+                // layout:     us,ru
+                (_, Some(colon)) => (line[..colon].trim().to_lowercase(), line[colon + 1..].trim()),
+                _ => continue,
+            };
+            let field = match key.as_str() {
+                "rules" => &mut rmlvo.rules,
+                "model" => &mut rmlvo.model,
+                "layout" => &mut rmlvo.layout,
+                "variant" => &mut rmlvo.variant,
+                "options" => &mut rmlvo.options,
+                _ => continue,
+            };
+            *field = value.to_owned();
+        }
+        rmlvo
+    }
+
+    /// The `XKB_DEFAULT_*` variables Wayland compositors use, if a layout is set.
+    pub fn from_env() -> Option<Self> {
+        let var = |name: &str| env::var(format!("XKB_DEFAULT_{}", name)).unwrap_or_default();
+        let rmlvo = Self {
+            rules: var("RULES"),
+            model: var("MODEL"),
+            layout: var("LAYOUT"),
+            variant: var("VARIANT"),
+            options: var("OPTIONS"),
+        };
+        (!rmlvo.layout.is_empty()).then_some(rmlvo)
+    }
+
+    /// Asks the X server with `setxkbmap -query`.
+    pub fn query() -> anyhow::Result<Self> {
+        let output = Command::new("setxkbmap")
+            .arg("-query")
+            .output()
+            .context("Can't run setxkbmap")?;
+        if !output.status.success() {
+            bail!("setxkbmap -query failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(Self::parse(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Layouts of the session: `XKB_DEFAULT_*`, the X server, then the system console setup.
+    pub fn system() -> anyhow::Result<Self> {
+        if let Some(rmlvo) = Self::from_env() {
+            return Ok(rmlvo);
+        }
+        let error = match Self::query() {
+            Ok(rmlvo) if !rmlvo.layout.is_empty() => return Ok(rmlvo),
+            Ok(_) => anyhow!("The X server reports no layout"),
+            Err(error) => error,
+        };
+        fs::read_to_string("/etc/default/keyboard")
+            .map(|text| Self::parse(&text))
+            .ok()
+            .filter(|rmlvo| !rmlvo.layout.is_empty())
+            .ok_or(error)
+    }
+
+    /// The layouts as a symbols include, e.g. `pc+us+ru(winkeys):2`.
+    ///
+    /// Each layout gets its own group the way the `evdev` rules do it. Models and options don't
+    /// change which characters the keys type, so they are left out.
+    pub fn symbols(&self) -> String {
+        let variants = self.variant.split(',').map(str::trim).collect::<Vec<_>>();
+        let layouts = self
+            .layout
+            .split(',')
+            .map(str::trim)
+            .enumerate()
+            .filter(|(_, layout)| !layout.is_empty())
+            .map(|(index, layout)| {
+                let mut component = layout.to_owned();
+                if let Some(variant) = variants.get(index).filter(|variant| !variant.is_empty()) {
+                    component.push_str(&format!("({})", variant));
+                }
+                if index > 0 {
+                    component.push_str(&format!(":{}", index + 1));
+                }
+                component
+            });
+        std::iter::once("pc".to_owned()).chain(layouts).collect::<Vec<_>>().join("+")
+    }
+}
+
+/// Layouts of the current session, see [`Rmlvo::system`].
+#[cfg(target_os = "linux")]
+pub fn system_layouts() -> anyhow::Result<Vec<KeyboardLayout>> {
+    let rmlvo = Rmlvo::system()?;
+    log::info!(target: "xkb", "Using XKB layouts {:?}", rmlvo);
+    XkbImporter::from_env().import_symbols(&rmlvo.symbols())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::ShiftLevel;
+
+    /// XKB database with the given files, in a directory of its own per test.
+    fn database(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = env::temp_dir().join(format!("xkb-{}-{}", test, std::process::id()));
+        for (path, text) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().expect("Files are in a directory")).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        root
+    }
+
+    const KEYCODES: [(&str, &str); 2] = [
+        (
+            "keycodes/evdev",
+            r#"default xkb_keycodes "evdev" { <AD01> = 24; <AC01> = 38; <AC02> = 39; };"#,
+        ),
+        ("keycodes/aliases", r#"xkb_keycodes "qwerty" { alias <LatQ> = <AD01>; };"#),
+    ];
+
+    const LATIN: (&str, &str) = (
+        "symbols/latin",
+        r#"
+        default xkb_symbols "basic" {
+            key <AC01> { [ a, A ] };
+            key <AC02> { [ s, S ] };
+        };
+        xkb_symbols "q" {
+            include "latin(basic)"
+            key <LatQ> { [ q, Q ] };
+        };
+        "#,
+    );
+
+    #[test]
+    fn includes_are_resolved() {
+        let cz = (
+            "symbols/cz",
+            r#"
+            default xkb_symbols "basic" {
+                include "latin(q)"
+                name[Group1] = "Czech";
+                key <AC02> { [ scaron, Scaron ] };
+            };
+            "#,
+        );
+        let root = database("includes", &[KEYCODES[0], KEYCODES[1], LATIN, cz]);
+        let layouts = XkbImporter::new(&root).import_symbols("cz").unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(layouts.len(), 1);
+        let czech = &layouts[0];
+        assert_eq!(czech.name, "Czech");
+        assert_eq!(czech.output(KeyCode::KeyA, ShiftLevel::Base), Some("a"));
+        assert_eq!(czech.output(KeyCode::KeyQ, ShiftLevel::Shift), Some("Q"));
+        assert_eq!(czech.output(KeyCode::KeyS, ShiftLevel::Base), Some("š"));
+        assert_eq!(czech.output(KeyCode::KeyS, ShiftLevel::Shift), Some("Š"));
+    }
+
+    #[test]
+    fn groups_and_augment_of_includes() {
+        let ru = (
+            "symbols/ru",
+            r#"
+            default xkb_symbols "basic" {
+                name[Group1] = "Russian";
+                key <AC01> { [ Cyrillic_ef, Cyrillic_EF ] };
+            };
+            "#,
+        );
+        let root = database("groups", &[KEYCODES[0], KEYCODES[1], LATIN, ru]);
+        let layouts = XkbImporter::new(&root).import_symbols("latin+ru:2").unwrap();
+        let augmented = XkbImporter::new(&root).import_symbols("latin|ru").unwrap();
+        let missing = XkbImporter::new(&root).import_symbols("latin(nothing)");
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(layouts.len(), 2);
+        assert_eq!(layouts[0].output(KeyCode::KeyA, ShiftLevel::Base), Some("a"));
+        assert_eq!(layouts[1].name, "Russian");
+        assert_eq!(layouts[1].output(KeyCode::KeyA, ShiftLevel::Base), Some("ф"));
+        // Keys with fewer groups wrap around
+        assert_eq!(layouts[1].output(KeyCode::KeyS, ShiftLevel::Base), Some("s"));
+
+        assert_eq!(augmented.len(), 1);
+        assert_eq!(augmented[0].output(KeyCode::KeyA, ShiftLevel::Base), Some("a"));
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn keymap_includes_the_database() {
+        let root = database("keymap", &[KEYCODES[0], KEYCODES[1], LATIN]);
+        let keymap = r#"
+            xkb_keymap {
+                xkb_keycodes { include "evdev+aliases(qwerty)" };
+                xkb_symbols "pc+latin" { include "latin(q)" key <AC01> { [ aacute ] }; };
+            };
+        "#;
+        let layouts = XkbImporter::new(&root).import_keymap(keymap).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(layouts.len(), 1);
+        assert_eq!(layouts[0].output(KeyCode::KeyA, ShiftLevel::Base), Some("á"));
+        assert_eq!(layouts[0].output(KeyCode::KeyA, ShiftLevel::Shift), Some("A"));
+        assert_eq!(layouts[0].output(KeyCode::KeyQ, ShiftLevel::Base), Some("q"));
+    }
+}
@@ -0,0 +1,26 @@
+//! The daemon and the hotkey manager it runs, for tools that embed them. The managers are
+//! reached through [`HOTKEY_MANAGER_INSTANCE`] and [`KEY_MANAGER_INSTANCE`].
+
+mod backend;
+mod daemon;
+mod keybindings;
+mod keycode;
+mod layout;
+#[cfg(target_os = "linux")]
+mod linux;
+mod r#static;
+mod r#type;
+#[cfg(windows)]
+mod win;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod util;
+
+pub use crate::daemon::run;
+pub use crate::keycode::KeyCode;
+pub use crate::r#static::{HOTKEY_MANAGER_INSTANCE, KEY_MANAGER_INSTANCE};
+pub use crate::r#type::hotkeymanager::{
+    BindingId, BindingKeys, HotkeyBinding, HotkeyManager, Key, KeyBinding, KeySequence, PressedKeys, RemovedBinding,
+    TriggeredHotkey,
+};
+pub use crate::r#type::keyboardhook::KeyManager;
+pub use crate::r#type::sticky::Latch;
@@ -0,0 +1,484 @@
+use std::collections::HashSet;
+use std::ffi::{c_ulong, CString};
+use std::fmt::{Debug, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem::size_of;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+use libc::input_event;
+
+use crate::backend::CaptureBackend;
+use crate::keycode::KeyCode;
+use crate::linux::group::GroupSwitch;
+use crate::linux::uinput::{UINPUT_PRODUCT, UINPUT_VENDOR};
+use crate::r#static::KEY_MANAGER_INSTANCE;
+
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_MSC: u16 = 0x04;
+pub const KEY_A: u16 = 30;
+pub const KEY_Z: u16 = 44;
+const KEY_MAX: usize = 0x2ff;
+
+const IOC_WRITE: c_ulong = 1;
+const IOC_READ: c_ulong = 2;
+
+/// `_IOC` from `asm-generic/ioctl.h`
+pub(crate) const fn ioc(dir: c_ulong, ty: u8, nr: u8, size: usize) -> c_ulong {
+    (dir << 30) | ((size as c_ulong) << 16) | ((ty as c_ulong) << 8) | nr as c_ulong
+}
+
+const fn eviocgbit(ev: u16, len: usize) -> c_ulong {
+    ioc(IOC_READ, b'E', 0x20 + ev as u8, len)
+}
+
+const fn eviocgname(len: usize) -> c_ulong {
+    ioc(IOC_READ, b'E', 0x06, len)
+}
+
+const fn eviocgkey(len: usize) -> c_ulong {
+    ioc(IOC_READ, b'E', 0x18, len)
+}
+
+const EVIOCGID: c_ulong = ioc(IOC_READ, b'E', 0x02, size_of::<libc::input_id>());
+const EVIOCGRAB: c_ulong = ioc(IOC_WRITE, b'E', 0x90, size_of::<libc::c_int>());
+
+const INPUT_DIRECTORY: &str = "/dev/input";
+
+fn is_event_node(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with("event"))
+}
+
+/// Receives events of grabbed devices that were not swallowed by a hotkey, so they still reach
+/// the rest of the system.
+pub trait EventSink: Send + Sync {
+    fn forward(&self, events: &[input_event]);
+}
+
+/// `input_event` with a readable `Debug`, passed to the key manager for logging.
+pub struct EvdevEvent<'a>(pub &'a str, pub input_event);
+
+impl Debug for EvdevEvent<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "input_event[{}] type: {}, code: {}, value: {}, time: {}.{:06}",
+            self.0,
+            self.1.type_,
+            self.1.code,
+            self.1.value,
+            self.1.time.tv_sec,
+            self.1.time.tv_usec
+        )
+    }
+}
+
+struct EvdevDevice {
+    file: File,
+    path: PathBuf,
+    name: String,
+    /// Key codes that are down, released for it when the device goes away.
+    held: HashSet<u16>,
+}
+
+fn test_bit(bits: &[u8], bit: usize) -> bool {
+    bits.get(bit / 8).is_some_and(|byte| byte & (1 << (bit % 8)) != 0)
+}
+
+impl EvdevDevice {
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+            .open(path)?;
+        let mut name = [0u8; 256];
+        let len = unsafe { libc::ioctl(file.as_raw_fd(), eviocgname(name.len()) as _, name.as_mut_ptr()) };
+        let name = if len > 0 {
+            String::from_utf8_lossy(&name[..len as usize])
+                .trim_end_matches('\0')
+                .to_owned()
+        } else {
+            "unknown".to_owned()
+        };
+        Ok(Self {
+            file,
+            path: path.to_owned(),
+            name,
+            held: HashSet::new(),
+        })
+    }
+
+    /// Anything that reports the letter keys is treated as a keyboard.
+    fn is_keyboard(&self) -> bool {
+        let mut bits = [0u8; KEY_MAX / 8 + 1];
+        let res = unsafe {
+            libc::ioctl(self.file.as_raw_fd(), eviocgbit(EV_KEY, bits.len()) as _, bits.as_mut_ptr())
+        };
+        res >= 0 && (KEY_A..=KEY_Z).all(|key| test_bit(&bits, key as usize))
+    }
+
+    /// Our own uinput keyboard. Reading it would feed injected keys back into the hotkey engine.
+    fn is_virtual_keyboard(&self) -> bool {
+        let mut id = libc::input_id {
+            bustype: 0,
+            vendor: 0,
+            product: 0,
+            version: 0,
+        };
+        let res = unsafe { libc::ioctl(self.file.as_raw_fd(), EVIOCGID as _, &mut id as *mut libc::input_id) };
+        res >= 0 && id.vendor == UINPUT_VENDOR && id.product == UINPUT_PRODUCT
+    }
+
+    fn has_pressed_keys(&self) -> bool {
+        let mut bits = [0u8; KEY_MAX / 8 + 1];
+        let res = unsafe {
+            libc::ioctl(self.file.as_raw_fd(), eviocgkey(bits.len()) as _, bits.as_mut_ptr())
+        };
+        res >= 0 && bits.iter().any(|byte| *byte != 0)
+    }
+
+    fn grab(&self, grab: bool) -> io::Result<()> {
+        let res = unsafe { libc::ioctl(self.file.as_raw_fd(), EVIOCGRAB as _, grab as libc::c_int) };
+        if res < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Reads whatever is available without blocking.
+    fn read_events(&self, buffer: &mut [input_event]) -> io::Result<usize> {
+        let res = unsafe {
+            libc::read(
+                self.file.as_raw_fd(),
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                std::mem::size_of_val(buffer),
+            )
+        };
+        if res < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::WouldBlock {
+                Ok(0)
+            } else {
+                Err(error)
+            }
+        } else {
+            Ok(res as usize / size_of::<input_event>())
+        }
+    }
+}
+
+/// Tells which nodes of `/dev/input` were created or got new permissions, with inotify.
+struct Hotplug(File);
+
+impl Hotplug {
+    fn new() -> io::Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        let directory = CString::new(INPUT_DIRECTORY).expect("No NUL in the path");
+        if unsafe { libc::inotify_add_watch(fd, directory.as_ptr(), libc::IN_CREATE | libc::IN_ATTRIB) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self(file))
+    }
+
+    /// The `event*` nodes that changed since the last call.
+    fn changed(&self) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        let mut buffer = [0u8; 4096];
+        loop {
+            let read = unsafe { libc::read(self.0.as_raw_fd(), buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+            if read <= 0 {
+                return paths;
+            }
+            let mut offset = 0;
+            while offset + size_of::<libc::inotify_event>() <= read as usize {
+                let event = unsafe { std::ptr::read_unaligned(buffer.as_ptr().add(offset) as *const libc::inotify_event) };
+                let start = offset + size_of::<libc::inotify_event>();
+                let name = &buffer[start..start + event.len as usize];
+                let name = name.split(|byte| *byte == 0).next().unwrap_or_default();
+                let path = Path::new(INPUT_DIRECTORY).join(String::from_utf8_lossy(name).as_ref());
+                if is_event_node(&path) && !paths.contains(&path) {
+                    paths.push(path);
+                }
+                offset = start + event.len as usize;
+            }
+        }
+    }
+}
+
+/// Reads keyboards from `/dev/input/event*`.
+///
+/// Without `grab` other programs still see every key, so hotkeys can't be swallowed. With `grab`
+/// the devices are taken exclusively and every event that is not swallowed is handed to the
+/// passthrough sink.
+pub struct EvdevCapture {
+    devices: Vec<PathBuf>,
+    grab: bool,
+    passthrough: Option<Arc<dyn EventSink>>,
+}
+
+impl EvdevCapture {
+    /// `devices` may be empty, then every keyboard found in `/dev/input` is used.
+    pub fn new(devices: Vec<PathBuf>, grab: bool) -> Self {
+        Self {
+            devices,
+            grab,
+            passthrough: None,
+        }
+    }
+
+    pub fn with_passthrough(mut self, sink: Arc<dyn EventSink>) -> Self {
+        self.passthrough = Some(sink);
+        self
+    }
+
+    /// The device at `path` if it's a keyboard other than our own.
+    fn open_keyboard(path: &Path) -> io::Result<Option<EvdevDevice>> {
+        let device = EvdevDevice::open(path)?;
+        if device.is_virtual_keyboard() {
+            log::trace!(target: "evdev", "Skipping {:?} ({}): our own virtual keyboard", device.path, device.name);
+            Ok(None)
+        } else if device.is_keyboard() {
+            Ok(Some(device))
+        } else {
+            log::trace!(target: "evdev", "Skipping {:?} ({}): not a keyboard", device.path, device.name);
+            Ok(None)
+        }
+    }
+
+    fn open_devices(&self) -> anyhow::Result<Vec<EvdevDevice>> {
+        let paths = if self.devices.is_empty() {
+            let mut paths = std::fs::read_dir(INPUT_DIRECTORY)
+                .context("Can't list /dev/input")?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| is_event_node(path))
+                .collect::<Vec<_>>();
+            paths.sort();
+            paths
+        } else {
+            self.devices.clone()
+        };
+
+        let devices = paths
+            .iter()
+            .filter_map(|path| {
+                Self::open_keyboard(path)
+                    .map_err(|e| log::warn!(target: "evdev", "Can't open {:?}: {}", path, e))
+                    .ok()
+                    .flatten()
+            })
+            .collect::<Vec<_>>();
+
+        if devices.is_empty() && !self.devices.is_empty() {
+            Err(anyhow!(
+                "No readable keyboards in /dev/input. Is the user in the `input` group?"
+            ))
+        } else {
+            Ok(devices)
+        }
+    }
+
+    fn listen(device: &EvdevDevice, grab: bool) -> anyhow::Result<()> {
+        if grab {
+            device
+                .grab(true)
+                .with_context(|| format!("Can't grab {:?}", device.path))?;
+        }
+        log::info!(target: "evdev", "Listening to {:?} ({}){}", device.path, device.name, if grab { " exclusively" } else { "" });
+        Ok(())
+    }
+
+    /// Lets go of the keys that were down on a device that went away, so they don't stay pressed
+    /// in the hotkey engine and on the virtual keyboard.
+    fn release_held(&self, device: &EvdevDevice, passthrough: Option<&dyn EventSink>) {
+        let time = libc::timeval { tv_sec: 0, tv_usec: 0 };
+        for &code in &device.held {
+            let release = input_event { time, type_: EV_KEY, code, value: 0 };
+            if !self.handle_key(device, release) {
+                if let Some(sink) = passthrough {
+                    sink.forward(&[release, input_event { time, type_: EV_SYN, code: 0, value: 0 }]);
+                }
+            }
+        }
+    }
+
+    /// Grabbing a device while a key is down leaves that key stuck for everyone else, so wait
+    /// for the keys used to start us (usually Enter) to be released.
+    fn wait_for_release(devices: &[EvdevDevice]) {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while devices.iter().any(|device| device.has_pressed_keys()) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    fn handle_key(&self, device: &EvdevDevice, event: input_event) -> bool {
+        let Some(key) = KeyCode::from_evdev(event.code) else {
+            log::trace!(target: "evdev", "Unmapped key code {}", event.code);
+            return false;
+        };
+        let raw = EvdevEvent(&device.name, event);
+        match event.value {
+            // 1 is a press, 2 is autorepeat which the key manager treats as another keydown
+            1 | 2 => KEY_MANAGER_INSTANCE.write().keydown(key, false, raw),
+            0 => KEY_MANAGER_INSTANCE.write().keyup(key, false, raw),
+            _ => false,
+        }
+    }
+}
+
+impl CaptureBackend for EvdevCapture {
+    fn can_swallow(&self) -> bool {
+        self.grab && self.passthrough.is_some()
+    }
+
+    fn run(&self) -> anyhow::Result<()> {
+        let passthrough = match (&self.passthrough, self.grab) {
+            (Some(sink), true) => Some(sink.clone()),
+            (None, true) => {
+                return Err(anyhow!(
+                    "Grabbing keyboards needs an injection backend to pass unhandled keys through"
+                ))
+            }
+            _ => None,
+        };
+        let mut devices = self.open_devices()?;
+        // Only when listening to every keyboard, a listed device that comes back is left alone
+        let hotplug = match self.devices.is_empty() {
+            true => Hotplug::new()
+                .map_err(|e| log::warn!(target: "evdev", "Can't watch /dev/input, keyboards plugged in later are not used: {}", e))
+                .ok(),
+            false => None,
+        };
+        if devices.is_empty() && hotplug.is_none() {
+            return Err(anyhow!("No readable keyboards in /dev/input. Is the user in the `input` group?"));
+        }
+        if devices.is_empty() {
+            log::warn!(target: "evdev", "No readable keyboards in /dev/input yet. Is the user in the `input` group?");
+        }
+
+        if passthrough.is_some() {
+            Self::wait_for_release(&devices);
+        }
+        for device in &devices {
+            Self::listen(device, passthrough.is_some())?;
+        }
+        if passthrough.is_none() {
+            log::warn!(target: "evdev", "Keyboards are not grabbed, keys of triggered hotkeys will leak to applications");
+        }
+
+        let mut group = GroupSwitch::from_system();
+        let mut buffer = [input_event {
+            time: libc::timeval { tv_sec: 0, tv_usec: 0 },
+            type_: 0,
+            code: 0,
+            value: 0,
+        }; 64];
+
+        loop {
+            let mut fds = devices
+                .iter()
+                .map(|device| device.file.as_raw_fd())
+                .chain(hotplug.as_ref().map(|hotplug| hotplug.0.as_raw_fd()))
+                .map(|fd| libc::pollfd {
+                    fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                })
+                .collect::<Vec<_>>();
+            let res = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, -1) };
+            if res < 0 {
+                let error = io::Error::last_os_error();
+                if error.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(error).context("poll on input devices failed");
+            }
+
+            let mut gone = Vec::new();
+            for (index, (device, fd)) in devices.iter_mut().zip(fds.iter()).enumerate() {
+                if fd.revents & (libc::POLLERR | libc::POLLHUP | libc::POLLNVAL) != 0 {
+                    gone.push(index);
+                    continue;
+                }
+                if fd.revents & libc::POLLIN == 0 {
+                    continue;
+                }
+                let count = match device.read_events(&mut buffer) {
+                    Ok(count) => count,
+                    Err(e) => {
+                        log::debug!(target: "evdev", "Can't read {:?}: {}", device.path, e);
+                        gone.push(index);
+                        continue;
+                    }
+                };
+
+                let mut forward = Vec::with_capacity(count);
+                for &event in &buffer[..count] {
+                    if let (EV_KEY, Some(key)) = (event.type_, KeyCode::from_evdev(event.code)) {
+                        if event.value != 2 {
+                            group.key(key, event.value == 1);
+                        }
+                    }
+                    if event.type_ == EV_KEY {
+                        match event.value {
+                            0 => device.held.remove(&event.code),
+                            _ => device.held.insert(event.code),
+                        };
+                    }
+                    let swallowed = event.type_ == EV_KEY && self.handle_key(device, event);
+                    if !swallowed {
+                        forward.push(event);
+                    }
+                }
+                if let Some(sink) = &passthrough {
+                    // A report with nothing but EV_SYN and EV_MSC is left over from a swallowed key
+                    if forward.iter().any(|event| event.type_ != EV_SYN && event.type_ != EV_MSC) {
+                        sink.forward(&forward);
+                    }
+                }
+            }
+
+            // From the back, so the indexes stay valid
+            for index in gone.into_iter().rev() {
+                let device = devices.remove(index);
+                log::warn!(target: "evdev", "{:?} ({}) went away, listening to {} keyboards", device.path, device.name, devices.len());
+                for key in device.held.iter().filter_map(|code| KeyCode::from_evdev(*code)) {
+                    group.key(key, false);
+                }
+                self.release_held(&device, passthrough.as_deref());
+            }
+            if let Some(hotplug) = &hotplug {
+                if fds.last().is_some_and(|fd| fd.revents & libc::POLLIN != 0) {
+                    for path in hotplug.changed() {
+                        if devices.iter().any(|device| device.path == path) {
+                            continue;
+                        }
+                        // udev may not have given it the `input` group yet, it's tried again then
+                        let Ok(Some(device)) = Self::open_keyboard(&path) else {
+                            continue;
+                        };
+                        match Self::listen(&device, passthrough.is_some()) {
+                            Ok(()) => devices.push(device),
+                            Err(e) => log::warn!(target: "evdev", "{:?}", e),
+                        }
+                    }
+                }
+            } else if devices.is_empty() {
+                return Err(anyhow!("Every keyboard went away"));
+            }
+        }
+    }
+}
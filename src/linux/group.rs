@@ -0,0 +1,94 @@
+use std::collections::HashSet;
+
+use crate::keycode::KeyCode;
+use crate::layout::xkb::Rmlvo;
+use crate::r#static::KEYBOARD_LAYOUTS;
+
+/// Keys of the `grp:` options that switch to the next group, generic modifiers stand for both
+/// sides.
+const TOGGLES: [(&str, &[KeyCode]); 16] = [
+    ("toggle", &[KeyCode::AltRight]),
+    ("lalt_toggle", &[KeyCode::AltLeft]),
+    ("caps_toggle", &[KeyCode::CapsLock]),
+    ("menu_toggle", &[KeyCode::ContextMenu]),
+    ("lwin_toggle", &[KeyCode::MetaLeft]),
+    ("rwin_toggle", &[KeyCode::MetaRight]),
+    ("lctrl_toggle", &[KeyCode::ControlLeft]),
+    ("rctrl_toggle", &[KeyCode::ControlRight]),
+    ("shifts_toggle", &[KeyCode::ShiftLeft, KeyCode::ShiftRight]),
+    ("alt_shift_toggle", &[KeyCode::Alt, KeyCode::Shift]),
+    ("ctrl_shift_toggle", &[KeyCode::Control, KeyCode::Shift]),
+    ("ctrl_alt_toggle", &[KeyCode::Control, KeyCode::Alt]),
+    ("alt_caps_toggle", &[KeyCode::Alt, KeyCode::CapsLock]),
+    ("alt_space_toggle", &[KeyCode::Alt, KeyCode::Space]),
+    ("win_space_toggle", &[KeyCode::Meta, KeyCode::Space]),
+    ("ctrl_space_toggle", &[KeyCode::Control, KeyCode::Space]),
+];
+
+fn is_key(expected: KeyCode, code: KeyCode) -> bool {
+    expected == code || expected.sides().is_some_and(|(left, right)| code == left || code == right)
+}
+
+/// Follows the XKB group for backends that only see raw keys, by watching for the keys of the
+/// `grp:` option that switch it. Switching done by the desktop, like GNOME's `super+space`, isn't
+/// seen.
+pub(crate) struct GroupSwitch {
+    toggles: Vec<&'static [KeyCode]>,
+    pressed: HashSet<KeyCode>,
+    group: usize,
+}
+
+impl GroupSwitch {
+    pub fn new(options: &str) -> Self {
+        let toggles = options
+            .split(',')
+            .filter_map(|option| option.trim().strip_prefix("grp:"))
+            .filter_map(|name| match TOGGLES.iter().find(|(known, _)| *known == name) {
+                Some((_, keys)) => Some(*keys),
+                None => {
+                    log::warn!(target: "evdev", "Layout switch grp:{} is not followed, bindings use the first layout", name);
+                    None
+                }
+            })
+            .collect();
+        Self {
+            toggles,
+            pressed: HashSet::new(),
+            group: 0,
+        }
+    }
+
+    /// The switch of the session, see [`Rmlvo::system`].
+    pub fn from_system() -> Self {
+        match Rmlvo::system() {
+            Ok(rmlvo) => Self::new(&rmlvo.options),
+            Err(e) => {
+                log::debug!(target: "evdev", "No layout switch to follow: {:?}", e);
+                Self::new("")
+            }
+        }
+    }
+
+    /// Sees a key go down or up and activates the next layout when it completes a switch.
+    pub fn key(&mut self, key: KeyCode, down: bool) {
+        if !down {
+            self.pressed.remove(&key);
+            return;
+        }
+        if !self.pressed.insert(key) {
+            return;
+        }
+        let switched = self.toggles.iter().any(|toggle| {
+            toggle.iter().any(|expected| is_key(*expected, key))
+                && toggle
+                    .iter()
+                    .all(|expected| self.pressed.iter().any(|pressed| is_key(*expected, *pressed)))
+                && self.pressed.len() == toggle.len()
+        });
+        if switched {
+            let mut layouts = KEYBOARD_LAYOUTS.write();
+            self.group = (self.group + 1) % layouts.len().max(1);
+            layouts.set_active(Some(self.group));
+        }
+    }
+}
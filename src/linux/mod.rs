@@ -0,0 +1,113 @@
+pub(crate) mod evdev;
+pub(crate) mod group;
+pub(crate) mod uinput;
+#[cfg(feature = "x11")]
+pub(crate) mod x11;
+
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::backend::null::NullBackend;
+use crate::backend::{CaptureBackend, InjectionBackend, InputBackend, KeyTranslator};
+use crate::keycode::KeyCode;
+use crate::linux::evdev::EvdevCapture;
+use crate::linux::uinput::{UinputDevice, UnicodeMethod};
+
+/// `evdev` (default) or `x11`.
+pub const ENV_BACKEND: &str = "DIAKRITIKA_BACKEND";
+/// Comma separated list of `/dev/input/event*` devices to read. Every keyboard if unset.
+pub const ENV_EVDEV_DEVICES: &str = "DIAKRITIKA_EVDEV_DEVICES";
+/// Set to `1` to grab the keyboards exclusively.
+pub const ENV_EVDEV_GRAB: &str = "DIAKRITIKA_EVDEV_GRAB";
+/// `ctrl-shift-u` (default) or `keysym`, see [`UnicodeMethod`].
+pub const ENV_UNICODE_METHOD: &str = "DIAKRITIKA_UNICODE_METHOD";
+
+/// Keyboards are read from evdev and keys are typed through a uinput virtual keyboard, which
+/// works the same under X11, Wayland and the console.
+pub struct LinuxBackend {
+    capture: EvdevCapture,
+    injection: Option<Arc<UinputDevice>>,
+}
+
+fn env_flag(name: &str) -> bool {
+    matches!(env::var(name).as_deref(), Ok("1") | Ok("true") | Ok("yes"))
+}
+
+/// Picks the backend named in `DIAKRITIKA_BACKEND`.
+pub fn backend_from_env() -> Box<dyn InputBackend> {
+    match env::var(ENV_BACKEND).as_deref() {
+        #[cfg(feature = "x11")]
+        Ok("x11") => match x11::X11Backend::new() {
+            Ok(backend) => return Box::new(backend),
+            Err(e) => log::error!("Can't use the x11 backend, falling back to evdev: {:?}", e),
+        },
+        Ok("evdev") | Err(_) => {}
+        Ok(other) => log::error!("Unknown backend {other}, falling back to evdev"),
+    }
+    Box::new(LinuxBackend::from_env())
+}
+
+impl LinuxBackend {
+    pub fn from_env() -> Self {
+        let devices = env::var(ENV_EVDEV_DEVICES)
+            .map(|list| {
+                list.split(',')
+                    .map(str::trim)
+                    .filter(|path| !path.is_empty())
+                    .map(PathBuf::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let unicode_method = env::var(ENV_UNICODE_METHOD)
+            .ok()
+            .and_then(|method| {
+                method
+                    .parse()
+                    .map_err(|e| log::error!("{ENV_UNICODE_METHOD}: {:?}", e))
+                    .ok()
+            })
+            .unwrap_or(UnicodeMethod::CtrlShiftU);
+
+        let injection = match UinputDevice::create(unicode_method) {
+            Ok(device) => Some(Arc::new(device)),
+            Err(e) => {
+                log::error!("Can't create the virtual keyboard, nothing will be typed: {:?}", e);
+                None
+            }
+        };
+        let mut capture = EvdevCapture::new(devices, env_flag(ENV_EVDEV_GRAB));
+        if let Some(device) = &injection {
+            capture = capture.with_passthrough(device.clone());
+        }
+
+        Self { capture, injection }
+    }
+}
+
+impl InputBackend for LinuxBackend {
+    fn name(&self) -> &'static str {
+        "evdev"
+    }
+
+    fn capture(&self) -> &dyn CaptureBackend {
+        &self.capture
+    }
+
+    fn injection(&self) -> &dyn InjectionBackend {
+        match &self.injection {
+            Some(device) => device.as_ref(),
+            None => &NullBackend,
+        }
+    }
+
+    fn translator(&self) -> &dyn KeyTranslator {
+        self
+    }
+}
+
+impl KeyTranslator for LinuxBackend {
+    fn to_unicode(&self, key: KeyCode, localized: bool) -> Option<String> {
+        NullBackend.to_unicode(key, localized)
+    }
+}
@@ -0,0 +1,302 @@
+use std::ffi::c_ulong;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::mem::size_of;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::process::Command;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use libc::{input_event, input_id, uinput_setup};
+use parking_lot::Mutex;
+
+use crate::backend::{InjectionBackend, KeyAction, KeyStroke, KeyType};
+use crate::keycode::KeyCode;
+use crate::linux::evdev::{ioc, EventSink, EV_KEY, EV_SYN};
+
+/// Vendor id of the virtual keyboard. The capture side ignores devices with this id, the same way
+/// the Windows hook ignores keystrokes carrying `KEYSTROKE_MARKER`.
+pub const UINPUT_VENDOR: u16 = 0x0666;
+pub const UINPUT_PRODUCT: u16 = 0x0001;
+pub const UINPUT_NAME: &str = "diakritika virtual keyboard";
+
+const BUS_VIRTUAL: u16 = 0x06;
+const SYN_REPORT: u16 = 0;
+/// Highest key code the virtual keyboard advertises.
+const KEY_MAX_USED: u16 = 0xff;
+/// X11 keycodes are evdev codes shifted by 8.
+const X11_KEYCODE_OFFSET: u16 = 8;
+/// Time applications get to read the new mapping of the spare keycode before it's pressed, and
+/// the release to arrive before the mapping is restored.
+const MAPPING_DELAY: Duration = Duration::from_millis(20);
+
+const IOC_NONE: c_ulong = 0;
+const IOC_WRITE: c_ulong = 1;
+const UI_DEV_CREATE: c_ulong = ioc(IOC_NONE, b'U', 1, 0);
+const UI_DEV_DESTROY: c_ulong = ioc(IOC_NONE, b'U', 2, 0);
+const UI_DEV_SETUP: c_ulong = ioc(IOC_WRITE, b'U', 3, size_of::<uinput_setup>());
+const UI_SET_EVBIT: c_ulong = ioc(IOC_WRITE, b'U', 100, size_of::<libc::c_int>());
+const UI_SET_KEYBIT: c_ulong = ioc(IOC_WRITE, b'U', 101, size_of::<libc::c_int>());
+
+/// How characters that are not on the keyboard get typed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnicodeMethod {
+    /// Ctrl+Shift+U, the hex code and Space. Understood by GTK and IBus.
+    CtrlShiftU,
+    /// Put the character's keysym on a keycode without keysyms with `xmodmap`, press it and
+    /// take the keysym off again. Needs an X server (or XWayland for X clients) and runs `xmodmap`
+    /// twice per character, which is slow. Applications that don't read the new mapping within
+    /// [`MAPPING_DELAY`] get the previous character, and nothing can be typed when every keycode
+    /// has keysyms.
+    Keysym,
+}
+
+impl FromStr for UnicodeMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ctrl-shift-u" | "ctrlshiftu" => Ok(UnicodeMethod::CtrlShiftU),
+            "keysym" => Ok(UnicodeMethod::Keysym),
+            other => Err(anyhow!("Unknown unicode method {other}")),
+        }
+    }
+}
+
+fn ioctl(file: &File, request: c_ulong, arg: libc::c_ulong) -> io::Result<()> {
+    if unsafe { libc::ioctl(file.as_raw_fd(), request as _, arg) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Virtual keyboard created through `/dev/uinput`.
+pub struct UinputDevice {
+    file: Mutex<File>,
+    unicode_method: UnicodeMethod,
+    /// X11 keycode without keysyms that [`UnicodeMethod::Keysym`] puts characters on.
+    spare_keycode: Option<u16>,
+}
+
+impl UinputDevice {
+    pub fn create(unicode_method: UnicodeMethod) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+            .open("/dev/uinput")
+            .context("Can't open /dev/uinput")?;
+
+        ioctl(&file, UI_SET_EVBIT, EV_KEY as _).context("UI_SET_EVBIT failed")?;
+        for key in 1..=KEY_MAX_USED {
+            ioctl(&file, UI_SET_KEYBIT, key as _).context("UI_SET_KEYBIT failed")?;
+        }
+
+        let mut setup = uinput_setup {
+            id: input_id {
+                bustype: BUS_VIRTUAL,
+                vendor: UINPUT_VENDOR,
+                product: UINPUT_PRODUCT,
+                version: 1,
+            },
+            name: [0; libc::UINPUT_MAX_NAME_SIZE],
+            ff_effects_max: 0,
+        };
+        for (dst, src) in setup.name.iter_mut().zip(UINPUT_NAME.bytes()) {
+            *dst = src as libc::c_char;
+        }
+        ioctl(&file, UI_DEV_SETUP, &setup as *const _ as _).context("UI_DEV_SETUP failed")?;
+        ioctl(&file, UI_DEV_CREATE, 0).context("UI_DEV_CREATE failed")?;
+        // Give udev and the display server a moment to pick the new device up, otherwise the
+        // first keystrokes get lost.
+        thread::sleep(Duration::from_millis(200));
+        log::info!(target: "uinput", "Created virtual keyboard, unicode method: {:?}", unicode_method);
+
+        let spare_keycode = match unicode_method {
+            UnicodeMethod::CtrlShiftU => None,
+            UnicodeMethod::Keysym => match Self::find_spare_keycode() {
+                Ok(Some(keycode)) => {
+                    log::info!(target: "uinput", "Using keycode {} for unicode output", keycode);
+                    Some(keycode)
+                }
+                Ok(None) => {
+                    log::warn!(target: "uinput", "All keycodes are taken, only characters of the layout can be typed");
+                    None
+                }
+                Err(e) => {
+                    log::error!(target: "uinput", "Can't read the keyboard mapping, only characters of the layout can be typed: {:?}", e);
+                    None
+                }
+            },
+        };
+
+        Ok(Self {
+            file: Mutex::new(file),
+            unicode_method,
+            spare_keycode,
+        })
+    }
+
+    fn write_events(&self, events: &[input_event]) {
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                events.as_ptr() as *const u8,
+                std::mem::size_of_val(events),
+            )
+        };
+        if let Err(e) = self.file.lock().write_all(bytes) {
+            log::error!(target: "uinput", "Failed to write {} events: {}", events.len(), e);
+        }
+    }
+
+    fn event(type_: u16, code: u16, value: i32) -> input_event {
+        input_event {
+            time: libc::timeval { tv_sec: 0, tv_usec: 0 },
+            type_,
+            code,
+            value,
+        }
+    }
+
+    /// Key event followed by a `SYN_REPORT`.
+    fn key(code: u16, action: KeyAction) -> [input_event; 2] {
+        [
+            Self::event(EV_KEY, code, (action == KeyAction::Press) as i32),
+            Self::event(EV_SYN, SYN_REPORT, 0),
+        ]
+    }
+
+    fn tap(code: u16) -> Vec<input_event> {
+        let mut events = Vec::with_capacity(4);
+        events.extend(Self::key(code, KeyAction::Press));
+        events.extend(Self::key(code, KeyAction::Release));
+        events
+    }
+
+    fn hex_digit(digit: char) -> Option<u16> {
+        KeyCode::from_keysym(digit as u32).map(KeyCode::evdev)
+    }
+
+    fn ctrl_shift_u(char: u32) -> Vec<input_event> {
+        let ctrl = KeyCode::ControlLeft.evdev();
+        let shift = KeyCode::ShiftLeft.evdev();
+        let u = KeyCode::KeyU.evdev();
+        let mut events = Vec::new();
+        events.extend(Self::key(ctrl, KeyAction::Press));
+        events.extend(Self::key(shift, KeyAction::Press));
+        events.extend(Self::tap(u));
+        events.extend(Self::key(shift, KeyAction::Release));
+        events.extend(Self::key(ctrl, KeyAction::Release));
+        format!("{:x}", char)
+            .chars()
+            .filter_map(Self::hex_digit)
+            .for_each(|code| events.extend(Self::tap(code)));
+        events.extend(Self::tap(KeyCode::Space.evdev()));
+        events
+    }
+
+    fn xmodmap(args: &[&str]) -> anyhow::Result<String> {
+        let output = Command::new("xmodmap")
+            .args(args)
+            .output()
+            .context("Can't run xmodmap")?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else {
+            Err(anyhow!("xmodmap exited with {}", output.status))
+        }
+    }
+
+    /// The highest keycode the virtual keyboard can press that has no keysyms. Taking one of a
+    /// key would break that key.
+    fn find_spare_keycode() -> anyhow::Result<Option<u16>> {
+        let mapping = Self::xmodmap(&["-pke"])?;
+        let spare = mapping
+            .lines()
+            .filter_map(|line| {
+                let (keycode, keysyms) = line.strip_prefix("keycode")?.split_once('=')?;
+                let keycode = keycode.trim().parse::<u16>().ok()?;
+                keysyms.trim().is_empty().then_some(keycode)
+            })
+            .filter(|keycode| (1..=KEY_MAX_USED).contains(&keycode.wrapping_sub(X11_KEYCODE_OFFSET)))
+            .max();
+        Ok(spare)
+    }
+
+    /// xmodmap only exits once the X server applied the mapping, applications are notified of it
+    /// after that.
+    fn remap_spare_keycode(keycode: u16, char: u32) -> anyhow::Result<()> {
+        Self::xmodmap(&["-e", &format!("keycode {keycode} = U{char:04X} U{char:04X}")])?;
+        thread::sleep(MAPPING_DELAY);
+        Ok(())
+    }
+
+    fn restore_spare_keycode(keycode: u16) {
+        thread::sleep(MAPPING_DELAY);
+        if let Err(e) = Self::xmodmap(&["-e", &format!("keycode {keycode} =")]) {
+            log::error!(target: "uinput", "Can't restore keycode {}: {:?}", keycode, e);
+        }
+    }
+
+    fn unicode(&self, stroke: &KeyStroke) -> Vec<input_event> {
+        match (self.unicode_method, stroke.action) {
+            (UnicodeMethod::CtrlShiftU, KeyAction::Press) => Self::ctrl_shift_u(stroke.scancode),
+            // The whole sequence was typed on press already
+            (UnicodeMethod::CtrlShiftU, KeyAction::Release) => Vec::new(),
+            (UnicodeMethod::Keysym, action) => {
+                let Some(keycode) = self.spare_keycode else {
+                    if action == KeyAction::Press {
+                        log::warn!(target: "uinput", "No spare keycode to type {:?}", stroke);
+                    }
+                    return Vec::new();
+                };
+                if action == KeyAction::Press {
+                    if let Err(e) = Self::remap_spare_keycode(keycode, stroke.scancode) {
+                        log::error!(target: "uinput", "Can't remap keycode {} to U+{:04X}: {:?}", keycode, stroke.scancode, e);
+                        return Vec::new();
+                    }
+                }
+                Self::key(keycode - X11_KEYCODE_OFFSET, action).to_vec()
+            }
+        }
+    }
+}
+
+impl InjectionBackend for UinputDevice {
+    fn send_keystrokes(&self, strokes: &[KeyStroke]) {
+        for stroke in strokes {
+            log::trace!(target: "uinput", "{:?}", stroke);
+            let events = match stroke.key_type {
+                KeyType::Classic => match stroke.key.map(KeyCode::evdev).filter(|code| *code != 0) {
+                    Some(code) => Self::key(code, stroke.action).to_vec(),
+                    None => {
+                        log::warn!(target: "uinput", "No evdev code for {:?}", stroke);
+                        continue;
+                    }
+                },
+                KeyType::Unicode => self.unicode(stroke),
+            };
+            self.write_events(&events);
+            if let (KeyType::Unicode, KeyAction::Release, UnicodeMethod::Keysym, Some(keycode)) =
+                (stroke.key_type, stroke.action, self.unicode_method, self.spare_keycode)
+            {
+                Self::restore_spare_keycode(keycode);
+            }
+        }
+    }
+}
+
+impl EventSink for UinputDevice {
+    fn forward(&self, events: &[input_event]) {
+        self.write_events(events);
+    }
+}
+
+impl Drop for UinputDevice {
+    fn drop(&mut self) {
+        let _ = ioctl(&self.file.lock(), UI_DEV_DESTROY, 0);
+    }
+}
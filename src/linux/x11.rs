@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+use std::ffi::{c_int, c_uchar, c_uint, CStr};
+use std::fmt::{Debug, Formatter};
+use std::mem::MaybeUninit;
+use std::ptr::null;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use parking_lot::Mutex;
+use x11::xinput2::{
+    XIAllMasterDevices, XIAllDevices, XIEventMask, XIFreeDeviceInfo, XIQueryDevice,
+    XIQueryVersion, XIRawEvent, XISelectEvents, XI_RawKeyPress, XI_RawKeyRelease,
+};
+use x11::xlib::{
+    Display, GenericEvent, KeySym, XChangeKeyboardMapping, XCloseDisplay, XDefaultRootWindow,
+    XDisplayKeycodes, XEvent, XFlush, XFree, XFreeEventData, XGetEventData,
+    XGetKeyboardMapping, XInitThreads, XNextEvent, XOpenDisplay, XQueryExtension, XSync,
+    XkbGetState, XkbKeycodeToKeysym, XkbStateRec,
+};
+use x11::xtest::XTestFakeKeyEvent;
+
+use crate::backend::{
+    CaptureBackend, InjectionBackend, InputBackend, KeyAction, KeyStroke, KeyTranslator, KeyType,
+};
+use crate::keycode::KeyCode;
+use crate::layout::keysym::{char_to_keysym, keysym_to_char};
+use crate::r#static::{KEYBOARD_LAYOUTS, KEY_MANAGER_INSTANCE};
+
+/// X11 keycodes are evdev codes shifted by 8 on every server that uses the evdev/libinput driver.
+const X11_KEYCODE_OFFSET: u32 = 8;
+const XKB_USE_CORE_KBD: c_uint = 0x0100;
+/// Time applications get to read the character off the spare keycode before its keysyms are
+/// taken off again.
+const MAPPING_DELAY: Duration = Duration::from_millis(20);
+
+/// Owned Xlib connection.
+struct XDisplay(*mut Display);
+
+// Xlib is initialised with `XInitThreads` and every connection is only used behind a mutex or
+// from the capture thread alone.
+unsafe impl Send for XDisplay {}
+unsafe impl Sync for XDisplay {}
+
+impl XDisplay {
+    fn open() -> anyhow::Result<Self> {
+        let display = unsafe { XOpenDisplay(null()) };
+        if display.is_null() {
+            Err(anyhow!("Can't open X display. Is $DISPLAY set?"))
+        } else {
+            Ok(Self(display))
+        }
+    }
+}
+
+impl Drop for XDisplay {
+    fn drop(&mut self) {
+        unsafe { XCloseDisplay(self.0) };
+    }
+}
+
+fn from_x11_keycode(keycode: c_int) -> Option<KeyCode> {
+    (keycode as u32)
+        .checked_sub(X11_KEYCODE_OFFSET)
+        .and_then(|code| u16::try_from(code).ok())
+        .and_then(KeyCode::from_evdev)
+}
+
+fn to_x11_keycode(key: KeyCode) -> Option<u32> {
+    match key.evdev() {
+        0 => None,
+        code => Some(code as u32 + X11_KEYCODE_OFFSET),
+    }
+}
+
+/// The XKB group, the layout the user switched to.
+fn current_group(display: &XDisplay) -> c_uint {
+    let mut state: XkbStateRec = unsafe { MaybeUninit::zeroed().assume_init() };
+    unsafe { XkbGetState(display.0, XKB_USE_CORE_KBD, &mut state) };
+    state.group as c_uint
+}
+
+/// What `key` types without modifiers in `group`.
+fn group_char(display: &XDisplay, key: KeyCode, group: c_uint) -> Option<String> {
+    let keycode = to_x11_keycode(key)?;
+    let keysym = unsafe { XkbKeycodeToKeysym(display.0, keycode as _, group as _, 0) };
+    keysym_to_char(keysym as u32).map(String::from)
+}
+
+/// `XIRawEvent` with a readable `Debug`, passed to the key manager for logging.
+struct RawKeyEvent<'a>(&'a XIRawEvent);
+
+impl Debug for RawKeyEvent<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "XIRawEvent[{}] keycode: {}, device: {}, source: {}, time: {}",
+            if self.0.evtype == XI_RawKeyPress { "press" } else { "release" },
+            self.0.detail,
+            self.0.deviceid,
+            self.0.sourceid,
+            self.0.time
+        )
+    }
+}
+
+/// Listens to XInput2 raw key events of the root window.
+///
+/// Raw events are only observed, X11 does not let us swallow them, so keys of a triggered hotkey
+/// reach the focused application the same way they do with a non-grabbed evdev capture.
+pub struct X11Capture;
+
+impl X11Capture {
+    /// Ids of the XTEST devices, the source of everything [`X11Injection`] sends.
+    fn xtest_devices(display: &XDisplay) -> Vec<c_int> {
+        let mut count = 0;
+        let mut devices = Vec::new();
+        unsafe {
+            let info = XIQueryDevice(display.0, XIAllDevices, &mut count);
+            if info.is_null() {
+                return devices;
+            }
+            for device in std::slice::from_raw_parts(info, count as usize) {
+                let name = CStr::from_ptr(device.name).to_string_lossy();
+                if name.contains("XTEST") {
+                    devices.push(device.deviceid);
+                }
+            }
+            XIFreeDeviceInfo(info);
+        }
+        devices
+    }
+}
+
+impl CaptureBackend for X11Capture {
+    fn can_swallow(&self) -> bool {
+        false
+    }
+
+    fn run(&self) -> anyhow::Result<()> {
+        let display = XDisplay::open()?;
+        let mut opcode = 0;
+        let mut event = 0;
+        let mut error = 0;
+        let (mut major, mut minor) = (2, 0);
+        unsafe {
+            if XQueryExtension(display.0, c"XInputExtension".as_ptr(), &mut opcode, &mut event, &mut error) == 0 {
+                return Err(anyhow!("The X server has no XInput extension"));
+            }
+            if XIQueryVersion(display.0, &mut major, &mut minor) != 0 {
+                return Err(anyhow!("The X server does not support XInput 2"));
+            }
+
+            let mut mask = [0 as c_uchar; 4];
+            for event in [XI_RawKeyPress, XI_RawKeyRelease] {
+                mask[(event >> 3) as usize] |= 1 << (event & 7);
+            }
+            let mut event_mask = XIEventMask {
+                deviceid: XIAllMasterDevices,
+                mask_len: mask.len() as c_int,
+                mask: mask.as_mut_ptr(),
+            };
+            XISelectEvents(display.0, XDefaultRootWindow(display.0), &mut event_mask, 1);
+            XSync(display.0, 0);
+        }
+        let xtest = Self::xtest_devices(&display);
+        log::info!(target: "x11", "Listening to raw key events, XInput {}.{}, ignoring XTEST devices {:?}", major, minor, xtest);
+        // Layout matching each group, looked up the first time the user switches to it
+        let mut layouts: HashMap<c_uint, Option<usize>> = HashMap::new();
+        let mut group = None;
+
+        loop {
+            let mut event: XEvent = unsafe { MaybeUninit::zeroed().assume_init() };
+            unsafe { XNextEvent(display.0, &mut event) };
+
+            let mut cookie = unsafe { event.generic_event_cookie };
+            if cookie.type_ != GenericEvent || cookie.extension != opcode {
+                continue;
+            }
+            if unsafe { XGetEventData(display.0, &mut cookie) } == 0 {
+                continue;
+            }
+            let raw = unsafe { &*(cookie.data as *const XIRawEvent) };
+
+            if xtest.contains(&raw.sourceid) {
+                // We ignore the keystrokes that we produced ourselves
+                log::trace!(target: "x11", "key_ignored: {:?}", RawKeyEvent(raw));
+            } else if let Some(key) = from_x11_keycode(raw.detail) {
+                if cookie.evtype == XI_RawKeyPress {
+                    let current = current_group(&display);
+                    if group != Some(current) {
+                        group = Some(current);
+                        let layout = *layouts.entry(current).or_insert_with(|| {
+                            KEYBOARD_LAYOUTS.write().activate_matching(|key| group_char(&display, key, current))
+                        });
+                        KEYBOARD_LAYOUTS.write().set_active(layout);
+                    }
+                }
+                if cookie.evtype == XI_RawKeyPress {
+                    KEY_MANAGER_INSTANCE.write().keydown(key, false, RawKeyEvent(raw));
+                } else if cookie.evtype == XI_RawKeyRelease {
+                    KEY_MANAGER_INSTANCE.write().keyup(key, false, RawKeyEvent(raw));
+                }
+            } else {
+                log::trace!(target: "x11", "Unmapped keycode {}", raw.detail);
+            }
+
+            unsafe { XFreeEventData(display.0, &mut cookie) };
+        }
+    }
+}
+
+/// Keycode without keysyms that is remapped to whatever character has to be typed and put back
+/// after the release. The backend is never dropped, so nothing else would restore it.
+struct SpareKeycode {
+    keycode: c_int,
+    original: Vec<KeySym>,
+}
+
+/// Types keys with XTest. Characters without a key get a spare keycode remapped to their keysym,
+/// they can't be typed when all keycodes are taken.
+pub struct X11Injection {
+    display: Mutex<XDisplay>,
+    spare: Option<SpareKeycode>,
+}
+
+impl X11Injection {
+    pub fn new() -> anyhow::Result<Self> {
+        let display = XDisplay::open()?;
+        let spare = Self::find_spare_keycode(&display)?;
+        match &spare {
+            Some(spare) => log::info!(target: "x11", "Using keycode {} for unicode output", spare.keycode),
+            None => log::warn!(target: "x11", "All keycodes are taken, only characters of the layout can be typed"),
+        }
+        Ok(Self {
+            display: Mutex::new(display),
+            spare,
+        })
+    }
+
+    /// The highest keycode without any keysyms. Taking one of a key would break that key.
+    fn find_spare_keycode(display: &XDisplay) -> anyhow::Result<Option<SpareKeycode>> {
+        let (mut min, mut max) = (0, 0);
+        let mut per_keycode = 0;
+        unsafe {
+            XDisplayKeycodes(display.0, &mut min, &mut max);
+            let mapping = XGetKeyboardMapping(display.0, min as _, max - min + 1, &mut per_keycode);
+            if mapping.is_null() {
+                return Err(anyhow!("Can't read the keyboard mapping"));
+            }
+            let keysyms = std::slice::from_raw_parts(
+                mapping,
+                ((max - min + 1) * per_keycode) as usize,
+            );
+            let spare = (min..=max)
+                .rev()
+                .find(|keycode| {
+                    let start = ((keycode - min) * per_keycode) as usize;
+                    keysyms[start..start + per_keycode as usize]
+                        .iter()
+                        .all(|keysym| *keysym == 0)
+                })
+                .map(|keycode| {
+                    let start = ((keycode - min) * per_keycode) as usize;
+                    let original = keysyms[start..start + per_keycode as usize].to_vec();
+                    SpareKeycode { keycode, original }
+                });
+            XFree(mapping as *mut _);
+            Ok(spare)
+        }
+    }
+
+    fn remap_spare(display: &XDisplay, spare: &SpareKeycode, keysyms: &mut [KeySym]) {
+        unsafe {
+            XChangeKeyboardMapping(
+                display.0,
+                spare.keycode,
+                keysyms.len() as c_int,
+                keysyms.as_mut_ptr(),
+                1,
+            );
+            XSync(display.0, 0);
+        }
+    }
+
+    fn fake_key(display: &XDisplay, keycode: c_uint, action: KeyAction) {
+        unsafe { XTestFakeKeyEvent(display.0, keycode, (action == KeyAction::Press) as _, 0) };
+    }
+}
+
+impl InjectionBackend for X11Injection {
+    fn send_keystrokes(&self, strokes: &[KeyStroke]) {
+        let display = self.display.lock();
+        for stroke in strokes {
+            log::trace!(target: "x11", "{:?}", stroke);
+            match stroke.key_type {
+                KeyType::Classic => match stroke.key.and_then(to_x11_keycode) {
+                    Some(keycode) => Self::fake_key(&display, keycode, stroke.action),
+                    None => log::warn!(target: "x11", "No keycode for {:?}", stroke),
+                },
+                KeyType::Unicode => {
+                    let Some(spare) = &self.spare else {
+                        if stroke.action == KeyAction::Press {
+                            log::warn!(target: "x11", "No spare keycode to type {:?}", stroke);
+                        }
+                        continue;
+                    };
+                    if stroke.action == KeyAction::Press {
+                        let Some(char) = char::from_u32(stroke.scancode) else {
+                            continue;
+                        };
+                        let keysym = char_to_keysym(char) as KeySym;
+                        Self::remap_spare(&display, spare, &mut [keysym, keysym]);
+                    }
+                    Self::fake_key(&display, spare.keycode as c_uint, stroke.action);
+                    if stroke.action == KeyAction::Release {
+                        unsafe { XSync(display.0, 0) };
+                        thread::sleep(MAPPING_DELAY);
+                        Self::remap_spare(&display, spare, &mut spare.original.clone());
+                    }
+                }
+            }
+        }
+        unsafe { XFlush(display.0) };
+    }
+}
+
+impl KeyTranslator for X11Injection {
+    fn to_unicode(&self, key: KeyCode, localized: bool) -> Option<String> {
+        let display = self.display.lock();
+        let group = if localized { current_group(&display) } else { 0 };
+        group_char(&display, key, group)
+    }
+}
+
+/// Raw XInput2 events for capturing, XTest for injection. Works without access to `/dev/input`.
+pub struct X11Backend {
+    capture: X11Capture,
+    injection: X11Injection,
+}
+
+impl X11Backend {
+    pub fn new() -> anyhow::Result<Self> {
+        unsafe { XInitThreads() };
+        Ok(Self {
+            capture: X11Capture,
+            injection: X11Injection::new()?,
+        })
+    }
+}
+
+impl InputBackend for X11Backend {
+    fn name(&self) -> &'static str {
+        "x11"
+    }
+
+    fn capture(&self) -> &dyn CaptureBackend {
+        &self.capture
+    }
+
+    fn injection(&self) -> &dyn InjectionBackend {
+        &self.injection
+    }
+
+    fn translator(&self) -> &dyn KeyTranslator {
+        &self.injection
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keysyms_of(display: &XDisplay, keycode: c_int) -> Vec<KeySym> {
+        let mut per_keycode = 0;
+        unsafe {
+            let mapping = XGetKeyboardMapping(display.0, keycode as _, 1, &mut per_keycode);
+            let keysyms = std::slice::from_raw_parts(mapping, per_keycode as usize).to_vec();
+            XFree(mapping as *mut _);
+            keysyms
+        }
+    }
+
+    #[test]
+    #[ignore = "needs an X server, run with `xvfb-run cargo test --features x11 -- --ignored`"]
+    fn characters_are_typed_on_the_spare_keycode_and_taken_off_again() {
+        unsafe { XInitThreads() };
+        let injection = X11Injection::new().unwrap();
+        let spare = injection.spare.as_ref().expect("The server has a keycode without keysyms");
+        let display = XDisplay::open().unwrap();
+        assert_eq!(injection.to_unicode(KeyCode::KeyA, false).as_deref(), Some("a"));
+
+        injection.send_keystrokes(&[KeyStroke::unicode('ř', KeyAction::Press)]);
+        assert!(keysyms_of(&display, spare.keycode).contains(&(char_to_keysym('ř') as KeySym)));
+
+        injection.send_keystrokes(&[KeyStroke::unicode('ř', KeyAction::Release)]);
+        assert_eq!(keysyms_of(&display, spare.keycode), spare.original);
+    }
+}
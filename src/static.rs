@@ -1,22 +1,40 @@
+use crate::backend::InputBackend;
+use crate::layout::KeyboardLayouts;
+use crate::r#type::command::RunningCommands;
+use crate::r#type::dispatcher::Dispatcher;
 use crate::r#type::hotkeymanager::HotkeyManager;
 use crate::r#type::keyboardhook::{KeyManager, KeyboardHookMetadata};
-use crate::win::keyboard_vk::KNOWN_VIRTUAL_KEY::{VK_CONTROL, VK_LWIN, VK_MENU, VK_SHIFT};
 use indexmap::IndexSet;
 use once_cell::sync::Lazy;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use std::sync::Arc;
 
-pub const CONST_VK_SHIFT: u32 = VK_SHIFT as u32;
-pub const CONST_VK_MENU: u32 = VK_MENU as u32;
-pub const CONST_VK_CONTROL: u32 = VK_CONTROL as u32;
-pub const CONST_VK_WIN: u32 = VK_LWIN as u32;
+#[cfg(not(test))]
+pub static INPUT_BACKEND: Lazy<Box<dyn InputBackend>> = Lazy::new(crate::backend::platform_default);
+/// The tests never touch the keyboard.
+#[cfg(test)]
+pub static INPUT_BACKEND: Lazy<Box<dyn InputBackend>> = Lazy::new(|| Box::new(crate::backend::recorder::RecordingBackend));
 
-pub static KEY_MANAGER_INSTANCE: Lazy<RwLock<KeyManager>> =
-    Lazy::new(|| RwLock::new(KeyManager::with_storage(IndexSet::with_capacity(20))));
+/// Layouts `Key::Character` bindings are resolved with. Empty means asking the OS.
+pub static KEYBOARD_LAYOUTS: Lazy<RwLock<KeyboardLayouts>> =
+    Lazy::new(|| RwLock::new(KeyboardLayouts::default()));
+
+/// Runs the actions of the magic bindings, one after another.
+pub static ACTION_DISPATCHER: Lazy<Dispatcher> = Lazy::new(Dispatcher::from_env);
+
+/// Programs started by `command` actions, they run on threads of their own.
+pub static RUNNING_COMMANDS: Lazy<Mutex<RunningCommands>> = Lazy::new(Default::default);
+
+pub static KEY_MANAGER_INSTANCE: Lazy<RwLock<KeyManager>> = Lazy::new(|| {
+    let mut key_manager = KeyManager::with_storage(IndexSet::with_capacity(20));
+    key_manager.spawn_tap_hold_timer();
+    RwLock::new(key_manager)
+});
 
 pub static HOTKEY_MANAGER_INSTANCE: Lazy<Arc<parking_lot::Mutex<HotkeyManager>>> =
     Lazy::new(|| {
         let hotkey_manager = Arc::new(parking_lot::Mutex::new(HotkeyManager::new()));
+        HotkeyManager::spawn_withhold_timer(&hotkey_manager);
 
         KEY_MANAGER_INSTANCE.write().add_hook(
             |metadata, hotkey_manager| {
@@ -37,4 +55,5 @@ pub static HOTKEY_MANAGER_INSTANCE: Lazy<Arc<parking_lot::Mutex<HotkeyManager>>>
 /// This leads to bad hotkey interpretation in keystroke processor and in general to some weird side
 /// effects because of the way how we treat keybindings with alt pressed.
 ///
+#[cfg(windows)]
 pub const SCANCODE_BLACKLIST: [u32;2] = [0x21d, 0xE038];
\ No newline at end of file
@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::backend::type_text;
+use crate::keybindings::action::CommandAction;
+use crate::r#static::{ACTION_DISPATCHER, RUNNING_COMMANDS};
+use crate::r#type::dispatcher::Phase;
+use crate::r#type::hotkeymanager::{Callback, Key, TriggeredHotkey};
+
+/// Keys of the binding that started the command with `pass_keys`, like `ralt+h`.
+pub const ENV_BINDING: &str = "DIAKRITIKA_BINDING";
+/// Keys that were down when the command started with `pass_keys`, like `ralt+h`.
+pub const ENV_KEYS: &str = "DIAKRITIKA_KEYS";
+/// How often a running command is checked for its exit and its timeout.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Commands started by bindings that didn't exit yet.
+#[derive(Default)]
+pub struct RunningCommands {
+    /// How many times each command is running.
+    counts: HashMap<CommandAction, usize>,
+    /// Processes killed when the daemon stops, by process id.
+    kill_on_exit: HashMap<u32, Arc<Mutex<Child>>>,
+}
+
+impl RunningCommands {
+    /// Counts the command as running unless it reached its limit. Returns whether it can start.
+    fn start(&mut self, command: &CommandAction) -> bool {
+        let count = self.counts.entry(command.clone()).or_default();
+        if command.limit.is_some_and(|limit| *count >= limit) {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    fn finish(&mut self, command: &CommandAction) {
+        if let Some(count) = self.counts.get_mut(command) {
+            *count -= 1;
+            if *count == 0 {
+                self.counts.remove(command);
+            }
+        }
+    }
+
+    /// Kills the processes started with `kill_on_exit` that are still running.
+    pub(crate) fn kill_on_exit(&mut self) {
+        for (id, child) in self.kill_on_exit.drain() {
+            if let Err(e) = child.lock().kill() {
+                log::warn!(target: "command", "Can't kill process {}: {:?}", id, e);
+            }
+        }
+    }
+}
+
+/// `ralt+h` of the keys, the way the bindings file writes them.
+fn key_names<'a>(keys: impl Iterator<Item = &'a Key>) -> String {
+    keys.map(|key| match key {
+        Key::Code(code) => code.name().to_owned(),
+        Key::Character(char) => char.clone(),
+        Key::Scancode(scancode) => format!("0x{:x}", scancode),
+    })
+    .collect::<Vec<_>>()
+    .join("+")
+}
+
+/// Starts the command on a thread of its own, which waits for it, kills it after its timeout and
+/// types its output. Nothing here waits for the process, so the hook and the dispatcher don't.
+pub(crate) fn spawn(command: &CommandAction, triggered: &TriggeredHotkey) {
+    let program = command.argv[0].clone();
+    if !RUNNING_COMMANDS.lock().start(command) {
+        log::warn!(target: "command", "{} is already running {} times, not started", program, command.limit.unwrap_or_default());
+        return;
+    }
+
+    let mut process = Command::new(&program);
+    process.args(&command.argv[1..]).stdin(Stdio::null());
+    if let Some(cwd) = &command.cwd {
+        process.current_dir(cwd);
+    }
+    if command.clear_env {
+        process.env_clear();
+    }
+    process.envs(command.env.iter().map(|(name, value)| (name, value)));
+    if command.pass_keys {
+        process.env(ENV_BINDING, key_names(triggered.0.keys().iter()));
+        let pressed = triggered.1.iter().map(|&code| Key::Code(code)).collect::<Vec<_>>();
+        process.env(ENV_KEYS, key_names(pressed.iter()));
+    }
+    if command.type_output {
+        process.stdout(Stdio::piped());
+    }
+    #[cfg(target_os = "linux")]
+    if command.kill_on_exit {
+        die_with_daemon(&mut process);
+    }
+
+    let (running, triggered) = (command.clone(), triggered.clone());
+    let spawned = thread::Builder::new()
+        .name(format!("command::{}", program))
+        .spawn(move || {
+            run(&running, process, triggered);
+            RUNNING_COMMANDS.lock().finish(&running);
+        });
+    if let Err(e) = spawned {
+        log::error!(target: "command", "Can't start a thread for {}: {:?}", program, e);
+        RUNNING_COMMANDS.lock().finish(command);
+    }
+}
+
+/// Runs the process until it exits or its timeout passes.
+fn run(command: &CommandAction, mut process: Command, triggered: TriggeredHotkey) {
+    let program = &command.argv[0];
+    let mut child = match process.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            log::error!(target: "command", "Can't run {}: {:?}", program, e);
+            return;
+        }
+    };
+    let id = child.id();
+    log::info!(target: "command", "Started {} as process {}", program, id);
+
+    // Read while it runs, a full pipe would stop the process
+    let output = child.stdout.take().map(|mut stdout| {
+        thread::spawn(move || {
+            let mut output = String::new();
+            stdout.read_to_string(&mut output).map(|_| output)
+        })
+    });
+    #[cfg(windows)]
+    if command.kill_on_exit {
+        job::assign(&child);
+    }
+    let child = Arc::new(Mutex::new(child));
+    if command.kill_on_exit {
+        RUNNING_COMMANDS.lock().kill_on_exit.insert(id, child.clone());
+    }
+
+    let deadline = command.timeout.map(|timeout| Instant::now() + timeout);
+    let status = loop {
+        let exited = child.lock().try_wait();
+        match exited {
+            Ok(Some(status)) => break Some(status),
+            Ok(None) if deadline.is_some_and(|deadline| Instant::now() >= deadline) => {
+                log::warn!(target: "command", "{} runs longer than {:?}, killing it", program, command.timeout.unwrap_or_default());
+                let mut child = child.lock();
+                let _ = child.kill();
+                let _ = child.wait();
+                break None;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                log::error!(target: "command", "Can't wait for {}: {:?}", program, e);
+                break None;
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    RUNNING_COMMANDS.lock().kill_on_exit.remove(&id);
+
+    match status {
+        Some(status) if status.success() => log::info!(target: "command", "{} exited with {}", program, status),
+        Some(status) => {
+            log::warn!(target: "command", "{} exited with {}", program, status);
+            return;
+        }
+        None => return,
+    }
+    match output.map(|reader| reader.join()) {
+        Some(Ok(Ok(output))) => {
+            // Typed in turn with the bindings, so it doesn't get mixed into what they type
+            let text = output.trim_end_matches(['\r', '\n']).to_owned();
+            let callback: Arc<Callback> = Arc::new(Box::new(move |_| type_text(&text)));
+            ACTION_DISPATCHER.dispatch(Phase::Press, &callback, triggered);
+        }
+        Some(Ok(Err(e))) => log::error!(target: "command", "Can't read the output of {}: {:?}", program, e),
+        Some(Err(_)) => log::error!(target: "command", "Reading the output of {} failed", program),
+        None => {}
+    }
+}
+
+/// Has the kernel kill the process when the thread starting it ends, so it goes down with the
+/// daemon even when the daemon is killed. The thread waits for the process, it never ends first.
+#[cfg(target_os = "linux")]
+fn die_with_daemon(process: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    let daemon = std::process::id();
+    // Only async-signal-safe calls between fork and exec
+    unsafe {
+        process.pre_exec(move || {
+            libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM);
+            // The daemon is already gone
+            if libc::getppid() as u32 != daemon {
+                libc::_exit(1);
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Stops the daemon on `SIGINT`, `SIGTERM` and `SIGHUP` after killing the `kill_on_exit`
+/// commands. Has to be called before any other thread starts, they inherit the blocked signals.
+#[cfg(target_os = "linux")]
+pub(crate) fn kill_on_signals() {
+    let signals = unsafe {
+        let mut signals = std::mem::zeroed::<libc::sigset_t>();
+        libc::sigemptyset(&mut signals);
+        for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+            libc::sigaddset(&mut signals, signal);
+        }
+        libc::pthread_sigmask(libc::SIG_BLOCK, &signals, std::ptr::null_mut());
+        signals
+    };
+    let spawned = thread::Builder::new().name("signals".to_owned()).spawn(move || {
+        let mut signal = 0;
+        unsafe { libc::sigwait(&signals, &mut signal) };
+        log::info!(target: "command", "Stopping on signal {}", signal);
+        RUNNING_COMMANDS.lock().kill_on_exit();
+        std::process::exit(128 + signal);
+    });
+    if let Err(e) = spawned {
+        log::error!(target: "command", "Can't start the signal thread, commands survive a stop: {:?}", e);
+    }
+}
+
+/// Job object closing when the daemon exits, however it does, which kills the processes in it.
+#[cfg(windows)]
+mod job {
+    use std::mem::{size_of, zeroed};
+    use std::os::windows::io::AsRawHandle;
+    use std::process::Child;
+    use std::ptr::null_mut;
+
+    use once_cell::sync::Lazy;
+    use winapi::um::jobapi2::{AssignProcessToJobObject, CreateJobObjectW, SetInformationJobObject};
+    use winapi::um::winnt::{
+        JobObjectExtendedLimitInformation, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+    };
+
+    /// The handle, as a number so it can be shared. Never closed, the OS does that on exit.
+    static JOB: Lazy<Option<usize>> = Lazy::new(|| unsafe {
+        let job = CreateJobObjectW(null_mut(), null_mut());
+        if job.is_null() {
+            log::error!(target: "command", "Can't create a job object, commands survive the daemon");
+            return None;
+        }
+        let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = zeroed();
+        limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        SetInformationJobObject(
+            job,
+            JobObjectExtendedLimitInformation,
+            &mut limits as *mut _ as *mut _,
+            size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+        );
+        Some(job as usize)
+    });
+
+    pub(super) fn assign(child: &Child) {
+        if let Some(job) = *JOB {
+            if unsafe { AssignProcessToJobObject(job as _, child.as_raw_handle() as _) } == 0 {
+                log::warn!(target: "command", "Can't add process {} to the job object", child.id());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn command(text: &str) -> CommandAction {
+        text.parse().unwrap_or_else(|e| panic!("`{}` doesn't parse: {:#}", text, e))
+    }
+
+    fn error(text: &str) -> String {
+        match text.parse::<CommandAction>() {
+            Ok(command) => panic!("`{}` parses as {:?}", text, command),
+            Err(e) => format!("{:#}", e),
+        }
+    }
+
+    #[test]
+    fn options_end_at_the_program_or_the_separator() {
+        let parsed = command("cwd=/tmp env=LANG=C.UTF-8 timeout=500 limit=2 kill_on_exit type_output date +%F");
+        assert_eq!(parsed.cwd, Some(PathBuf::from("/tmp")));
+        assert_eq!(parsed.env, vec![("LANG".to_owned(), "C.UTF-8".to_owned())]);
+        assert_eq!(parsed.timeout, Some(Duration::from_millis(500)));
+        assert_eq!(parsed.limit, Some(2));
+        assert!(parsed.kill_on_exit && parsed.type_output && !parsed.clear_env && !parsed.pass_keys);
+        assert_eq!(parsed.argv, ["date", "+%F"]);
+
+        // Options after the program are its arguments, `--` lets a program look like an option
+        assert_eq!(command("echo limit=1 --").argv, ["echo", "limit=1", "--"]);
+        let parsed = command("clear_env -- pass_keys --version");
+        assert!(parsed.clear_env && !parsed.pass_keys);
+        assert_eq!(parsed.argv, ["pass_keys", "--version"]);
+    }
+
+    #[test]
+    fn broken_options_are_errors() {
+        assert!(error("limit=0 date").contains("`0` is not a number"));
+        assert!(error("limit=many date").contains("`many` is not a number"));
+        assert!(error("env=LANG date").contains("expected `env=NAME=value` instead of `env=LANG`"));
+        assert!(error("env==C date").contains("expected `env=NAME=value`"));
+        assert!(error("cwd= date").contains("expected `cwd=directory`"));
+        assert!(error("timeout=soon date").contains("soon"));
+        assert!(error("kill_on_exit --").contains("expected the program"));
+    }
+
+    #[test]
+    fn running_commands_stop_at_their_limit() {
+        let limited = command("limit=2 true");
+        let mut running = RunningCommands::default();
+        assert!(running.start(&limited) && running.start(&limited));
+        assert!(!running.start(&limited));
+        running.finish(&limited);
+        assert!(running.start(&limited));
+
+        let unlimited = command("true");
+        assert!((0..10).all(|_| running.start(&unlimited)));
+    }
+
+    #[cfg(target_os = "linux")]
+    mod linux {
+        use super::*;
+        use crate::backend::recorder::record;
+        use crate::keycode::KeyCode;
+        use crate::r#type::hotkeymanager::HotkeyManager;
+
+        fn triggered() -> TriggeredHotkey {
+            let mut manager = HotkeyManager::new();
+            let (id, _press, _release) = manager.add_channel_binding([Key::Code(KeyCode::AltRight)].into_iter().collect());
+            let binding = manager.binding(id).cloned().expect("The binding was added");
+            TriggeredHotkey(binding, Default::default())
+        }
+
+        /// Waits until no instance of `command` runs anymore.
+        fn wait_for(command: &CommandAction) {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while RUNNING_COMMANDS.lock().counts.contains_key(command) {
+                assert!(Instant::now() < deadline, "{} still runs", command);
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+
+        #[test]
+        fn limit_keeps_more_from_starting() {
+            let sleeping = command("limit=1 sh -c \"sleep 0.3 # limit\"");
+            spawn(&sleeping, &triggered());
+            spawn(&sleeping, &triggered());
+            assert_eq!(RUNNING_COMMANDS.lock().counts.get(&sleeping), Some(&1));
+            wait_for(&sleeping);
+        }
+
+        #[test]
+        fn timeout_kills_the_process() {
+            let sleeping = command("timeout=100 kill_on_exit sleep 30");
+            let started = Instant::now();
+            spawn(&sleeping, &triggered());
+            wait_for(&sleeping);
+            assert!(started.elapsed() < Duration::from_secs(5), "{:?}", started.elapsed());
+            assert!(RUNNING_COMMANDS.lock().kill_on_exit.is_empty());
+        }
+
+        #[test]
+        fn output_is_typed_without_the_last_newline() {
+            let typing = command("type_output -- sh -c \"echo Díky; echo\"");
+            let typed = record(|| {
+                spawn(&typing, &triggered());
+                wait_for(&typing);
+            });
+            let text = typed
+                .iter()
+                .filter(|stroke| stroke.action == crate::backend::KeyAction::Press)
+                .filter_map(|stroke| char::from_u32(stroke.scancode))
+                .collect::<String>();
+            assert_eq!(text, "Díky");
+
+            let failing = command("type_output sh -c \"echo no; exit 1\"");
+            let typed = record(|| {
+                spawn(&failing, &triggered());
+                wait_for(&failing);
+            });
+            assert!(typed.is_empty());
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::r#type::hotkeymanager::{Callback, TriggeredHotkey};
+
+/// How many actions can wait for the dispatcher, 256 by default.
+pub const ENV_QUEUE_CAPACITY: &str = "DIAKRITIKA_QUEUE_CAPACITY";
+const DEFAULT_QUEUE_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Press,
+    Release,
+}
+
+struct QueuedAction {
+    phase: Phase,
+    callback: Arc<Callback>,
+    hotkey: TriggeredHotkey,
+    queued_at: Instant,
+}
+
+enum Job {
+    Action(Box<QueuedAction>),
+    /// Answers once everything queued before it ran.
+    Flush(Sender<()>),
+}
+
+#[derive(Default)]
+struct Metrics {
+    queued: AtomicU64,
+    executed: AtomicU64,
+    dropped: AtomicU64,
+    depth: AtomicUsize,
+    max_depth: AtomicUsize,
+    max_wait_micros: AtomicU64,
+}
+
+/// Counters of the [`Dispatcher`] queue.
+#[derive(Debug, Clone, Copy)]
+pub struct DispatcherStats {
+    pub capacity: usize,
+    /// Actions put into the queue.
+    pub queued: u64,
+    pub executed: u64,
+    /// Presses thrown away because the queue was full.
+    pub dropped: u64,
+    /// Actions in the queue right now.
+    pub depth: usize,
+    pub max_depth: usize,
+    /// Longest time an action spent in the queue before it ran.
+    pub max_wait: Duration,
+}
+
+impl Display for DispatcherStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{} waiting (max {}), {} queued, {} executed, {} dropped, max wait {:?}",
+            self.depth, self.capacity, self.max_depth, self.queued, self.executed, self.dropped, self.max_wait
+        )
+    }
+}
+
+/// Runs the actions of every binding on a single thread, in the order the hook queued them, so
+/// the release of a hotkey never overtakes its press or the press of the next hotkey.
+///
+/// Presses are dropped once `capacity` actions wait. Releases are always queued, so whatever a
+/// press typed is released again, and never wait: the hook queues them while holding the hotkey
+/// manager, which the actions lock too. There is at most one release per press in the queue.
+pub struct Dispatcher {
+    sender: Sender<Job>,
+    capacity: usize,
+    metrics: Arc<Metrics>,
+}
+
+impl Dispatcher {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let (sender, receiver) = mpsc::channel();
+        let metrics = Arc::new(Metrics::default());
+
+        let thread_metrics = metrics.clone();
+        thread::Builder::new()
+            .name("hotkey::dispatch".to_string())
+            .spawn(move || Self::run(receiver, &thread_metrics))
+            .unwrap_or_else(|e| panic!("Thread hotkey::dispatch failed. {:?}", e));
+
+        Self {
+            sender,
+            capacity,
+            metrics,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let capacity = match env::var(ENV_QUEUE_CAPACITY) {
+            Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+                log::warn!(target: "Dispatcher", "{} is not a number: {:?}", ENV_QUEUE_CAPACITY, value);
+                DEFAULT_QUEUE_CAPACITY
+            }),
+            Err(_) => DEFAULT_QUEUE_CAPACITY,
+        };
+        Self::new(capacity)
+    }
+
+    fn run(receiver: Receiver<Job>, metrics: &Metrics) {
+        for job in receiver {
+            metrics.depth.fetch_sub(1, Ordering::Relaxed);
+            match job {
+                Job::Action(action) => {
+                    let QueuedAction {
+                        phase,
+                        callback,
+                        hotkey,
+                        queued_at,
+                    } = *action;
+                    let waited = queued_at.elapsed().as_micros().try_into().unwrap_or(u64::MAX);
+                    metrics.max_wait_micros.fetch_max(waited, Ordering::Relaxed);
+                    log::trace!(target: "Dispatcher", "[{:?}] running after {}µs", phase, waited);
+                    // One broken action must not stop the others
+                    if let Err(e) = panic::catch_unwind(AssertUnwindSafe(|| callback(hotkey))) {
+                        log::error!(target: "Dispatcher", "{:?} action panicked: {:?}", phase, e);
+                    }
+                    metrics.executed.fetch_add(1, Ordering::Relaxed);
+                }
+                Job::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    }
+
+    /// Queues `callback` without waiting. Returns false if a press was dropped because the queue
+    /// is full.
+    pub fn dispatch(&self, phase: Phase, callback: &Arc<Callback>, hotkey: TriggeredHotkey) -> bool {
+        // Counted before sending, the dispatcher may take the job right away
+        let depth = self.metrics.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        if phase == Phase::Press && depth > self.capacity {
+            self.metrics.depth.fetch_sub(1, Ordering::Relaxed);
+            self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+            log::warn!(target: "Dispatcher", "Queue is full, dropping a press: {}", self.stats());
+            return false;
+        }
+        self.metrics.max_depth.fetch_max(depth, Ordering::Relaxed);
+        self.metrics.queued.fetch_add(1, Ordering::Relaxed);
+
+        let job = Job::Action(Box::new(QueuedAction {
+            phase,
+            callback: callback.clone(),
+            hotkey,
+            queued_at: Instant::now(),
+        }));
+        if self.sender.send(job).is_err() {
+            log::error!(target: "Dispatcher", "Dispatcher thread is gone, dropping a {:?}", phase);
+            self.metrics.queued.fetch_sub(1, Ordering::Relaxed);
+            self.metrics.depth.fetch_sub(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    /// Waits until the actions queued so far ran. Must not be called from an action.
+    pub fn flush(&self) {
+        let (done, wait) = mpsc::channel();
+        self.metrics.depth.fetch_add(1, Ordering::Relaxed);
+        if self.sender.send(Job::Flush(done)).is_ok() {
+            let _ = wait.recv();
+        } else {
+            self.metrics.depth.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stats(&self) -> DispatcherStats {
+        let metrics = &self.metrics;
+        DispatcherStats {
+            capacity: self.capacity,
+            queued: metrics.queued.load(Ordering::Relaxed),
+            executed: metrics.executed.load(Ordering::Relaxed),
+            dropped: metrics.dropped.load(Ordering::Relaxed),
+            depth: metrics.depth.load(Ordering::Relaxed),
+            max_depth: metrics.max_depth.load(Ordering::Relaxed),
+            max_wait: Duration::from_micros(metrics.max_wait_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::keycode::KeyCode;
+use crate::r#static::KEY_MANAGER_INSTANCE;
+
+/// Key that types `tap` when tapped and holds `hold` while it's held down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DualRole {
+    pub key: KeyCode,
+    pub tap: KeyCode,
+    pub hold: KeyCode,
+}
+
+/// Key event as it came from the keyboard.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct KeyEvent {
+    pub key: KeyCode,
+    pub press: bool,
+    pub time: Instant,
+}
+
+/// What the hooks get instead of the event that came.
+#[derive(Debug)]
+pub(crate) enum Replay {
+    Event(KeyEvent),
+    /// The dual-role key turned out to be held.
+    Hold(DualRole, Instant),
+    /// The held dual-role key was released, with whether its hold key reached applications.
+    Release(DualRole, bool, Instant),
+}
+
+/// Dual-role key that is down, but not long enough to know whether it's a tap or a hold.
+struct Pending {
+    role: DualRole,
+    since: Instant,
+    /// Events of the other keys in the meantime.
+    events: Vec<KeyEvent>,
+}
+
+/// Tells a tap of a dual-role key from a hold.
+///
+/// The key is kept from the hooks until it's decided, together with the keys pressed in the
+/// meantime. Releasing it within the tapping term is a tap, keeping it down longer is a hold.
+/// With `permissive_hold`, another key pressed and released in the meantime makes it a hold too.
+#[derive(Default)]
+pub(crate) struct TapHold {
+    roles: HashMap<KeyCode, DualRole>,
+    term: Duration,
+    permissive_hold: bool,
+    pending: Option<Pending>,
+    /// Held dual-role keys, with whether their hold key reached applications yet.
+    held: HashMap<KeyCode, (DualRole, bool)>,
+    timer: Option<Sender<Instant>>,
+}
+
+impl TapHold {
+    pub(crate) fn set(&mut self, roles: Vec<DualRole>, term: Duration, permissive_hold: bool) {
+        self.roles = roles.into_iter().map(|role| (role.key, role)).collect();
+        self.term = term;
+        self.permissive_hold = permissive_hold;
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.roles.is_empty() && self.pending.is_none() && self.held.is_empty()
+    }
+
+    /// What the press of `key` turns into, `None` when it goes on as it is.
+    pub(crate) fn press(&mut self, key: KeyCode, time: Instant) -> Option<Vec<Replay>> {
+        let mut replay = self.expire(time);
+        if let Some(pending) = &mut self.pending {
+            // Key repeat of the pending key is dropped
+            if key != pending.role.key {
+                pending.events.push(KeyEvent { key, press: true, time });
+            }
+            return Some(replay);
+        }
+        if self.held.contains_key(&key) {
+            return Some(replay);
+        }
+        if let Some(role) = self.roles.get(&key) {
+            self.pending = Some(Pending {
+                role: *role,
+                since: time,
+                events: Vec::new(),
+            });
+            if let Some(timer) = &self.timer {
+                let _ = timer.send(time + self.term);
+            }
+            return Some(replay);
+        }
+        if replay.is_empty() {
+            return None;
+        }
+        replay.push(Replay::Event(KeyEvent { key, press: true, time }));
+        Some(replay)
+    }
+
+    /// What the release of `key` turns into, `None` when it goes on as it is.
+    pub(crate) fn release(&mut self, key: KeyCode, time: Instant) -> Option<Vec<Replay>> {
+        let mut replay = self.expire(time);
+        if let Some(mut pending) = self.pending.take() {
+            if key == pending.role.key {
+                log::debug!(target: "TapHold", "{:?} tapped", key);
+                let tap = pending.role.tap;
+                replay.push(Replay::Event(KeyEvent { key: tap, press: true, time: pending.since }));
+                replay.push(Replay::Event(KeyEvent { key: tap, press: false, time }));
+                replay.extend(pending.events.into_iter().map(Replay::Event));
+                return Some(replay);
+            }
+            if !pending.events.iter().any(|event| event.press && event.key == key) {
+                // Pressed before the dual-role key, nothing to wait for
+                self.pending = Some(pending);
+                return None;
+            }
+            pending.events.push(KeyEvent { key, press: false, time });
+            if self.permissive_hold {
+                return Some(self.hold(pending, time));
+            }
+            self.pending = Some(pending);
+            return Some(replay);
+        }
+        if let Some((role, sent)) = self.held.remove(&key) {
+            replay.push(Replay::Release(role, sent, time));
+            return Some(replay);
+        }
+        if replay.is_empty() {
+            return None;
+        }
+        replay.push(Replay::Event(KeyEvent { key, press: false, time }));
+        Some(replay)
+    }
+
+    /// Decides the pending key as held once it's down for longer than the tapping term.
+    pub(crate) fn expire(&mut self, now: Instant) -> Vec<Replay> {
+        match self.pending.take() {
+            Some(pending) if now >= pending.since + self.term => {
+                let at = pending.since + self.term;
+                self.hold(pending, at)
+            }
+            pending => {
+                self.pending = pending;
+                Vec::new()
+            }
+        }
+    }
+
+    fn hold(&mut self, pending: Pending, time: Instant) -> Vec<Replay> {
+        log::debug!(target: "TapHold", "{:?} held, replaying {} events", pending.role.key, pending.events.len());
+        self.held.insert(pending.role.key, (pending.role, pending.role.hold != pending.role.key));
+        let mut replay = vec![Replay::Hold(pending.role, time)];
+        replay.extend(pending.events.into_iter().map(Replay::Event));
+        replay
+    }
+
+    /// Held keys that hold themselves only reach applications with the first key no hotkey
+    /// takes, so a lone hold or a hotkey never shows up there. Returns the ones still to be sent.
+    pub(crate) fn take_unsent(&mut self) -> Vec<KeyCode> {
+        self.held
+            .iter_mut()
+            .filter(|(_, (_, sent))| !*sent)
+            .map(|(key, (_, sent))| {
+                *sent = true;
+                *key
+            })
+            .collect()
+    }
+
+    /// Starts the thread that decides the pending key as held once the tapping term passes,
+    /// even when nothing else is pressed.
+    pub(crate) fn spawn_timer(&mut self) {
+        let (timer, deadlines) = mpsc::channel::<Instant>();
+        self.timer = Some(timer);
+
+        thread::Builder::new()
+            .name("keyboard::tap_hold".to_string())
+            .spawn(move || {
+                let mut deadline: Option<Instant> = None;
+                loop {
+                    let next = match deadline {
+                        Some(deadline) => deadlines.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                        None => deadlines.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                    };
+                    match next {
+                        Ok(next) => deadline = Some(next),
+                        Err(RecvTimeoutError::Timeout) => {
+                            deadline = None;
+                            KEY_MANAGER_INSTANCE.write().expire_tap_hold();
+                        }
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+            })
+            .unwrap_or_else(|e| panic!("Thread keyboard::tap_hold failed. {:?}", e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keycode::KeyCode::{CapsLock, ControlLeft, Escape, KeyA, KeyB};
+
+    const TERM: Duration = Duration::from_millis(200);
+
+    /// Caps lock typing escape and holding control.
+    fn caps_lock(permissive_hold: bool) -> TapHold {
+        let mut tap_hold = TapHold::default();
+        let role = DualRole { key: CapsLock, tap: Escape, hold: ControlLeft };
+        tap_hold.set(vec![role], TERM, permissive_hold);
+        tap_hold
+    }
+
+    /// What the hooks get, the key of the dual-role key for holds and their releases.
+    fn replayed(replay: Option<Vec<Replay>>) -> Vec<(&'static str, KeyCode)> {
+        replay
+            .expect("The event was taken")
+            .into_iter()
+            .map(|replay| match replay {
+                Replay::Event(KeyEvent { key, press: true, .. }) => ("press", key),
+                Replay::Event(KeyEvent { key, press: false, .. }) => ("release", key),
+                Replay::Hold(role, _) => ("hold", role.key),
+                Replay::Release(role, _, _) => ("let go", role.key),
+            })
+            .collect()
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn release_within_the_term_taps() {
+        let mut tap_hold = caps_lock(false);
+        let start = Instant::now();
+        assert!(replayed(tap_hold.press(CapsLock, start)).is_empty());
+        assert!(tap_hold.expire(start + ms(100)).is_empty());
+        let tap = vec![("press", Escape), ("release", Escape)];
+        assert_eq!(replayed(tap_hold.release(CapsLock, start + ms(150))), tap);
+        // Decided, other keys go on as they are
+        assert!(tap_hold.press(KeyA, start + ms(160)).is_none());
+    }
+
+    #[test]
+    fn down_past_the_term_holds() {
+        let mut tap_hold = caps_lock(false);
+        let start = Instant::now();
+        tap_hold.press(CapsLock, start);
+        assert_eq!(replayed(Some(tap_hold.expire(start + TERM))), vec![("hold", CapsLock)]);
+        // Key repeat of a held key goes nowhere
+        assert!(replayed(tap_hold.press(CapsLock, start + ms(300))).is_empty());
+        assert_eq!(replayed(tap_hold.release(CapsLock, start + ms(400))), vec![("let go", CapsLock)]);
+        assert!(tap_hold.press(KeyA, start + ms(500)).is_none());
+    }
+
+    #[test]
+    fn key_tapped_in_the_meantime_holds_only_when_permissive() {
+        let start = Instant::now();
+        let mut permissive = caps_lock(true);
+        permissive.press(CapsLock, start);
+        assert!(replayed(permissive.press(KeyA, start + ms(10))).is_empty());
+        let hold = vec![("hold", CapsLock), ("press", KeyA), ("release", KeyA)];
+        assert_eq!(replayed(permissive.release(KeyA, start + ms(20))), hold);
+
+        let mut strict = caps_lock(false);
+        strict.press(CapsLock, start);
+        strict.press(KeyA, start + ms(10));
+        assert!(replayed(strict.release(KeyA, start + ms(20))).is_empty());
+        let tap = vec![("press", Escape), ("release", Escape), ("press", KeyA), ("release", KeyA)];
+        assert_eq!(replayed(strict.release(CapsLock, start + ms(30))), tap);
+    }
+
+    #[test]
+    fn keys_pressed_while_undecided_are_replayed_in_order() {
+        let mut tap_hold = caps_lock(false);
+        let start = Instant::now();
+        tap_hold.press(CapsLock, start);
+        assert!(replayed(tap_hold.press(KeyA, start + ms(10))).is_empty());
+        assert!(replayed(tap_hold.press(KeyB, start + ms(20))).is_empty());
+        assert!(replayed(tap_hold.release(KeyA, start + ms(30))).is_empty());
+
+        let held = vec![("hold", CapsLock), ("press", KeyA), ("press", KeyB), ("release", KeyA)];
+        assert_eq!(replayed(Some(tap_hold.expire(start + TERM))), held);
+        assert!(tap_hold.release(KeyB, start + ms(300)).is_none());
+    }
+}
@@ -1,21 +1,34 @@
-use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fmt::{Debug, Formatter};
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
 
 use indexmap::IndexSet;
+use parking_lot::Mutex;
 
+use crate::backend::{replace_last_output, KeyAction, KeyStroke};
+use crate::keybindings::parser::{DEFAULT_MULTI_TAP_TIMEOUT, DEFAULT_SEQUENCE_TIMEOUT};
+use crate::keycode::KeyCode;
+use crate::layout::characters_of;
+use crate::layout::compose::{can_compose, compose, variants_of};
+use crate::r#static::{ACTION_DISPATCHER, INPUT_BACKEND};
+use crate::r#type::dispatcher::Phase;
 use crate::r#type::Dump;
-use crate::r#type::hotkeymanager::Key::VirtualKey;
+use crate::r#type::hotkeymanager::Key::Code;
 use crate::r#type::keyboardhook::KeyboardHookMetadata;
-use crate::win::{is_meta_or_alt, ToScanCode, ToUnicode, VIRTUAL_KEY};
-use crate::win::keyboard_vk::KNOWN_VIRTUAL_KEY;
-use crate::win::keyboard_vk::KNOWN_VIRTUAL_KEY::{VK_LSHIFT, VK_RSHIFT, VK_SHIFT};
 
 pub type KeyBindings = Vec<KeyBinding>;
-pub type CharKeyBindings = BTreeMap<BindingChar, KeyBindings>;
+pub type CharKeyBindings = BTreeMap<BindingChar, Vec<KeySequence>>;
+/// Chords pressed one after another, `ralt, ', e` in the config. Most bindings have one step.
+pub type KeySequence = Vec<BindingKeys>;
 
 pub type BindingChar = char;
+#[allow(dead_code)]
 pub type CharBindingState<'a> = HashMap<&'a BindingChar, i32>;
 
 impl Dump for CharKeyBindings {
@@ -27,6 +40,24 @@ impl Dump for CharKeyBindings {
     }
 }
 
+impl Dump for KeySequence {
+    fn dump(&self) -> String {
+        self.iter()
+            .map(|step| step.dump())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl Dump for Vec<KeySequence> {
+    fn dump(&self) -> String {
+        self.iter()
+            .map(|sequence| sequence.dump())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
 impl Dump for KeyBindings {
     fn dump(&self) -> String {
         self.iter()
@@ -36,9 +67,9 @@ impl Dump for KeyBindings {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub enum Key {
-    VirtualKey(VIRTUAL_KEY),
+    Code(KeyCode),
     Character(String),
     Scancode(u32),
 }
@@ -49,19 +80,13 @@ impl Debug for Key {
             f,
             "{}",
             match &self {
-                Key::VirtualKey(k) => {
-                    match KNOWN_VIRTUAL_KEY::try_from(*k) {
-                        Ok(k) => format!("{:?}", k),
-                        Err(e) => {
-                            let code = e.into_inner();
-
-                            if let Some(char) = &code.to_unicode_localized() {
-                                format!("'{}'", char)
-                            } else {
-                                format!("VKey({})", &code)
-                            }
-
-                        },
+                Key::Code(code) => {
+                    if !code.is_printable() {
+                        format!("{:?}", code)
+                    } else if let Some(char) = characters_of(*code).first() {
+                        format!("'{}'", char)
+                    } else {
+                        format!("{:?}", code)
                     }
                 }
                 Key::Character(char) => format!("'{}'", char),
@@ -71,21 +96,55 @@ impl Debug for Key {
     }
 }
 
+impl Key {
+    /// Whether pressing `code` counts as this key.
+    fn matches(&self, code: KeyCode, char_cache: &mut HashMap<KeyCode, Vec<String>>) -> bool {
+        match self {
+            Key::Code(expected) => *expected == code,
+            Key::Character(expected_str) => char_cache
+                .entry(code)
+                .or_insert_with(|| characters_of(code))
+                .contains(expected_str),
+            Key::Scancode(expected_sc) => code.has_scancode(*expected_sc),
+        }
+    }
+}
+
 pub type KeyBinding = Vec<Key>; // Now a Vec to preserve order
 
 impl Dump for KeyBinding {
     fn dump(&self) -> String {
-        return String::from(format!("{:?}", self));
+        format!("{:?}", self)
     }
 }
 
-type Callback = Box<dyn Fn(TriggeredHotkey) + Send + Sync>;
+/// Keys of a binding from the config. `ordered` ones only fire when the keys go down in this
+/// order, `s>alt` in the config.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BindingKeys {
+    pub keys: KeyBinding,
+    pub ordered: bool,
+}
+
+impl Dump for BindingKeys {
+    fn dump(&self) -> String {
+        if self.ordered {
+            let keys = self.keys.iter().map(|key| format!("{:?}", key)).collect::<Vec<_>>();
+            format!("[{}]", keys.join(" > "))
+        } else {
+            self.keys.dump()
+        }
+    }
+}
+
+pub(crate) type Callback = Box<dyn Fn(TriggeredHotkey) + Send + Sync>;
 type ChannelSender = Sender<TriggeredHotkey>;
 type ChannelReceiver = Receiver<TriggeredHotkey>;
 
 pub trait HasCharacter {
     fn has_character(&self) -> bool;
 
+    #[allow(dead_code)]
     fn has_character_value(&self, exact: String) -> bool;
 }
 
@@ -95,14 +154,15 @@ pub trait HasShift {
 
 impl HasShift for KeyBinding {
     fn has_shift(&self) -> bool {
-        self.iter().any(|key| matches!(key, Key::VirtualKey(vk) if vk == &VK_SHIFT.into() || vk == &VK_LSHIFT.into() || vk == &VK_RSHIFT.into()))
+        self.iter().any(|key| matches!(key, Key::Code(code) if code.is_shift()))
     }
 }
 
-pub trait HasVirtualKey {
-    fn has_virtual_key(&self) -> bool;
+pub trait HasKeyCode {
+    #[allow(dead_code)]
+    fn has_key_code(&self) -> bool;
 
-    fn has_virtual_key_value(&self, exact: VIRTUAL_KEY) -> bool;
+    fn has_key_code_value(&self, exact: KeyCode) -> bool;
 }
 
 impl HasCharacter for KeyBinding {
@@ -120,15 +180,15 @@ impl HasCharacter for KeyBinding {
     }
 }
 
-impl HasVirtualKey for KeyBinding {
-    fn has_virtual_key(&self) -> bool {
-        self.iter().any(|key| matches!(key, Key::VirtualKey(_)))
+impl HasKeyCode for KeyBinding {
+    fn has_key_code(&self) -> bool {
+        self.iter().any(|key| matches!(key, Key::Code(_)))
     }
 
-    fn has_virtual_key_value(&self, exact: VIRTUAL_KEY) -> bool {
+    fn has_key_code_value(&self, exact: KeyCode) -> bool {
         self.iter().any(|key| {
-            if let Key::VirtualKey(vk) = key {
-                *vk == exact
+            if let Key::Code(code) = key {
+                *code == exact
             } else {
                 false
             }
@@ -136,26 +196,150 @@ impl HasVirtualKey for KeyBinding {
     }
 }
 
-#[derive(Clone, Debug)]
+/// How a binding turns its layer on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LayerSwitch {
+    /// On with one press, off with the next.
+    Toggle,
+    /// On while the keys are down.
+    Hold,
+}
+
+#[derive(Clone)]
 enum BindingAction {
     //Callback(Callback),
     Channel(ChannelSender),
-    Magic(ChannelSender),
+    /// Runs on the [`ACTION_DISPATCHER`] thread
+    Magic(Arc<Callback>),
+    /// Puts the combining mark on the next letter typed, handled by the [`HotkeyManager`] itself.
+    DeadKey(char),
+    /// Turns a layer on or off, handled by the [`HotkeyManager`] itself.
+    Layer(Arc<str>, LayerSwitch),
+}
+
+impl Debug for BindingAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BindingAction::Channel(tx) => f.debug_tuple("Channel").field(tx).finish(),
+            BindingAction::Magic(_) => f.write_str("Magic"),
+            BindingAction::DeadKey(mark) => write!(f, "DeadKey(U+{:04X})", *mark as u32),
+            BindingAction::Layer(name, switch) => write!(f, "Layer({}, {:?})", name, switch),
+        }
+    }
+}
+
+/// Handle of a binding in a [`HotkeyManager`]. Unique for the whole process, so a handle never
+/// points to another binding after the table was replaced.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BindingId(u64);
+
+impl BindingId {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        BindingId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct HotkeyBinding {
+    id: BindingId,
+    /// Steps of a sequence that have to be done before `keys`, empty for a plain chord.
+    prefix: KeySequence,
+    /// The chord that fires the binding, the last step of a sequence.
     keys: KeyBinding,
     on_press: BindingAction,
     on_release: BindingAction,
     ordered: bool,
+    enabled: bool,
+    /// Layer the binding belongs to, `None` for the bindings that always work.
+    layer: Option<Arc<str>>,
     pub triggered: bool,
+    /// The keys of the last press were withheld, applications never saw them go down.
+    pub withheld: bool,
+    /// Presses in a row, each within the tap timeout of the one before and without another key
+    /// between them. 1 for a single press.
+    taps: usize,
+    last_tap: Option<Instant>,
 }
 
 impl HotkeyBinding {
+    /// Binding for a key the manager handles itself, to queue what it does on the
+    /// [`ACTION_DISPATCHER`]. It's never added to the table.
+    fn internal(keys: KeyBinding, callback: &Arc<Callback>) -> Self {
+        HotkeyBinding {
+            id: BindingId::next(),
+            prefix: Vec::new(),
+            keys,
+            on_press: BindingAction::Magic(callback.clone()),
+            on_release: BindingAction::Magic(callback.clone()),
+            ordered: false,
+            enabled: true,
+            layer: None,
+            triggered: false,
+            withheld: false,
+            taps: 0,
+            last_tap: None,
+        }
+    }
+
     fn is_triggered(&self) -> bool {
         self.triggered
     }
+
+    /// Tells the bindings apart in what a channel binding receives.
+    pub fn id(&self) -> BindingId {
+        self.id
+    }
+
+    pub fn keys(&self) -> &KeyBinding {
+        &self.keys
+    }
+
+    pub fn taps(&self) -> usize {
+        self.taps
+    }
+
+    pub fn is_sequence(&self) -> bool {
+        !self.prefix.is_empty()
+    }
+
+    fn step_count(&self) -> usize {
+        self.prefix.len() + 1
+    }
+
+    /// Keys of the step at `index` and whether they're ordered.
+    fn step(&self, index: usize) -> (&KeyBinding, bool) {
+        match self.prefix.get(index) {
+            Some(step) => (&step.keys, step.ordered),
+            None => (&self.keys, self.ordered),
+        }
+    }
+
+    /// Whether the steps before the last one are exactly `steps_done`.
+    fn prefix_matches(&self, steps_done: &[PressedKeys], char_cache: &mut HashMap<KeyCode, Vec<String>>) -> bool {
+        self.prefix.len() == steps_done.len()
+            && self
+                .prefix
+                .iter()
+                .zip(steps_done)
+                .all(|(step, chord)| chord_matches(&step.keys, step.ordered, chord, char_cache, true))
+    }
+}
+
+/// What's left of a removed binding. Actions it already queued still run,
+/// [`RemovedBinding::join`] waits for them. Never join while holding the manager lock, the
+/// actions inject keys that go through the hook again.
+pub struct RemovedBinding {
+    pub keys: KeyBinding,
+    magic: bool,
+}
+
+impl RemovedBinding {
+    pub fn join(self) {
+        if self.magic {
+            ACTION_DISPATCHER.flush();
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -169,14 +353,14 @@ impl HotkeyBinding {
     ) -> bool {
         match metadata {
             KeyboardHookMetadata::Press { .. } => {
-                self.on_press.execute_action(self, pressed_keys);
+                self.on_press.execute_action(self, pressed_keys, Phase::Press);
                 if !self.triggered {
                     self.triggered = true
                 }
                 true
             }
             KeyboardHookMetadata::Release { .. } => {
-                self.on_release.execute_action(self, pressed_keys);
+                self.on_release.execute_action(self, pressed_keys, Phase::Release);
                 if self.triggered {
                     self.triggered = false
                 }
@@ -185,64 +369,93 @@ impl HotkeyBinding {
         }
     }
 
+    /// `in_order` makes an ordered binding also check that the keys went down in its order. The
+    /// release of a triggered binding skips that, the injected keys may have reordered them.
     fn should_trigger(
         &self,
-        pressed_keys: &IndexSet<VIRTUAL_KEY>,
-        scancode_cache: &mut HashMap<u32, u32>,
-        char_cache: &mut HashMap<u32, Option<String>>,
+        pressed_keys: &PressedKeys,
+        char_cache: &mut HashMap<KeyCode, Vec<String>>,
+        in_order: bool,
     ) -> bool {
         log::trace!("{} = {}", self.keys.dump(), pressed_keys.dump());
-        for key in &self.keys {
-            match key {
-                Key::VirtualKey(vk) => {
-                    if !pressed_keys.contains(vk) {
-                        log::trace!(" == false");
-                        return false;
-                    }
-                }
-                Key::Character(expected_str) => {
-                    let pressed_str = pressed_keys.iter().find_map(|&vk| {
-                        // Clone the String to ensure the returned value is owned and not a reference
-                        char_cache
-                            .entry(vk)
-                            .or_insert_with(|| vk.to_unicode_localized())
-                            .clone()
-                    });
-                    // Compare Option<&String> with &Option<String> using map and as_deref
-                    //log::trace!("Checking if {:?} in {:?}", expected_str, pressed_str);
-                    if pressed_str.as_deref() != Some(expected_str) {
-                        log::trace!(" == false");
-                        return false;
-                    }
-                }
-                Key::Scancode(expected_sc) => {
-                    // Find the pressed scan code by converting each virtual key code using the cache
-                    let pressed_sc_match = pressed_keys.iter().any(|&vk| {
-                        let pressed_sc = scancode_cache.entry(vk).or_insert_with(|| vk.to_code());
-                        pressed_sc == expected_sc
-                    });
-                    if !pressed_sc_match {
-                        log::trace!(" == false");
-                        return false;
-                    }
-                }
+        let matches = chord_matches(&self.keys, self.ordered, pressed_keys, char_cache, in_order);
+        log::trace!(" == {}", matches);
+        matches
+    }
+}
+
+/// 0 for `1` up to 8 for `9`.
+fn digit_index(key: KeyCode) -> Option<usize> {
+    use KeyCode::*;
+    [Digit1, Digit2, Digit3, Digit4, Digit5, Digit6, Digit7, Digit8, Digit9]
+        .iter()
+        .position(|digit| *digit == key)
+}
+
+/// Whether `pressed_keys` are the chord `keys`, in their order if `ordered` and `in_order`.
+/// Bindings are only checked against as many pressed keys as they have.
+fn chord_matches(
+    keys: &KeyBinding,
+    ordered: bool,
+    pressed_keys: &PressedKeys,
+    char_cache: &mut HashMap<KeyCode, Vec<String>>,
+    in_order: bool,
+) -> bool {
+    if keys.len() != pressed_keys.len() {
+        return false;
+    }
+    if !keys
+        .iter()
+        .all(|key| pressed_keys.iter().any(|&code| key.matches(code, char_cache)))
+    {
+        return false;
+    }
+    !(ordered && in_order)
+        || keys
+            .iter()
+            .zip(pressed_keys.iter())
+            .all(|(key, &code)| key.matches(code, char_cache))
+}
+
+/// Whether more keys can turn `pressed_keys` into the chord `keys`.
+fn chord_starts_with(
+    keys: &KeyBinding,
+    ordered: bool,
+    pressed_keys: &PressedKeys,
+    char_cache: &mut HashMap<KeyCode, Vec<String>>,
+) -> bool {
+    if ordered {
+        return pressed_keys
+            .iter()
+            .zip(keys)
+            .all(|(&code, key)| key.matches(code, char_cache));
+    }
+    let mut used = vec![false; keys.len()];
+    pressed_keys.iter().all(|&code| {
+        match (0..keys.len()).find(|&index| !used[index] && keys[index].matches(code, char_cache)) {
+            Some(index) => {
+                used[index] = true;
+                true
             }
+            None => false,
         }
-        log::trace!(" == true");
-        true
-    }
+    })
 }
 
 impl BindingAction {
-    fn execute_action(&self, binding: &HotkeyBinding, pressed_keys: &PressedKeys) {
+    fn execute_action(&self, binding: &HotkeyBinding, pressed_keys: &PressedKeys, phase: Phase) {
+        let triggered = TriggeredHotkey(binding.clone(), pressed_keys.clone());
         match self {
             //BindingAction::Callback(cb) => cb(TriggeredHotkey(binding.clone(), pressed_keys.clone())),
-            BindingAction::Channel(tx) | BindingAction::Magic(tx) => {
-                if let Err(error) = tx.send(TriggeredHotkey(binding.clone(), pressed_keys.clone()))
-                {
+            BindingAction::Channel(tx) => {
+                if let Err(error) = tx.send(triggered) {
                     log::trace!("BROKEN PIPE: {:?}", error);
                 }
             }
+            BindingAction::Magic(callback) => {
+                ACTION_DISPATCHER.dispatch(phase, callback, triggered);
+            }
+            BindingAction::DeadKey(_) | BindingAction::Layer(..) => {}
         }
     }
 }
@@ -250,8 +463,47 @@ impl BindingAction {
 pub struct HotkeyManager {
     //bindings: VecDeque<HotkeyBinding>,
     bindings_by_length: HashMap<usize, VecDeque<HotkeyBinding>>,
+    /// How long keys are withheld while they can still become a hotkey, `None` to let them through.
+    withhold: Option<Duration>,
+    /// Events kept from applications, in the order they came.
+    withheld: Vec<KeyStroke>,
+    /// When the withheld events are let through and the sequence in progress is given up.
+    expires_at: Option<Instant>,
+    /// Withheld keys that became a hotkey. Their releases are kept from applications too.
+    consumed: HashSet<KeyCode>,
+    /// Wakes up the thread replaying withheld keys when they time out.
+    timer: Option<Sender<Instant>>,
+    /// Whether the capture backend can keep keys from applications.
+    can_swallow: bool,
+    /// How long a sequence waits for its next step.
+    sequence_timeout: Duration,
+    /// Chords of the sequence in progress, each one all the keys that were down together.
+    steps_done: Vec<PressedKeys>,
+    /// Keys pressed since all keys were last up, in the order they went down.
+    chord: PressedKeys,
+    /// Combining mark of the dead key pressed last, waiting for a letter.
+    dead_key: Option<char>,
+    /// How long a binding waits for its next tap.
+    tap_timeout: Duration,
+    /// Binding pressed last, if no other key was pressed since.
+    last_tapped: Option<BindingId>,
+    /// How long a letter is held before a digit can pick one of its variants, `None` when off.
+    hold_threshold: Option<Duration>,
+    /// Variants from the config by small letter. Other letters get theirs from Unicode.
+    hold_variants: HashMap<char, Vec<char>>,
+    /// Variants of the letter held last, `1` to `9` replace the letter with one of them.
+    picking: Option<Vec<char>>,
+    /// Letter whose hold offered its variants already, until it's pressed again.
+    held_key: Option<KeyCode>,
+    /// Layers that are on, the one turned on last at the end. Its bindings win over the others.
+    active_layers: Vec<Arc<str>>,
+    /// Keys holding a layer on. They're left out of the pressed keys, so `s` works in the layer.
+    layer_keys: HashMap<KeyCode, Arc<str>>,
+    /// Where the toggled layers are kept across restarts, `None` to forget them.
+    layers_file: Option<PathBuf>,
 }
 
+#[allow(dead_code)]
 pub(crate) trait Bindable {
     fn to_virtual_key(self) -> u32;
 }
@@ -262,47 +514,460 @@ impl Bindable for u32 {
     }
 }
 
-pub type PressedKeys = IndexSet<VIRTUAL_KEY>;
+pub type PressedKeys = IndexSet<KeyCode>;
 
 impl Dump for PressedKeys {
     fn dump(&self) -> String {
         self.iter()
-            .map(|item| Key::VirtualKey(*item))
+            .map(|item| Key::Code(*item))
             .collect::<Vec<_>>()
             .dump()
     }
 }
 
+impl Default for HotkeyManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl HotkeyManager {
     pub fn new() -> Self {
         HotkeyManager {
             //bindings: VecDeque::new(),
             bindings_by_length: HashMap::new(),
+            withhold: None,
+            withheld: Vec::new(),
+            expires_at: None,
+            consumed: HashSet::new(),
+            timer: None,
+            can_swallow: false,
+            sequence_timeout: DEFAULT_SEQUENCE_TIMEOUT,
+            steps_done: Vec::new(),
+            chord: PressedKeys::new(),
+            dead_key: None,
+            tap_timeout: DEFAULT_MULTI_TAP_TIMEOUT,
+            last_tapped: None,
+            hold_threshold: None,
+            hold_variants: HashMap::new(),
+            picking: None,
+            held_key: None,
+            active_layers: Vec::new(),
+            layer_keys: HashMap::new(),
+            layers_file: None,
         }
     }
 
     fn _add_binding(
         &mut self,
+        prefix: KeySequence,
         keys: KeyBinding,
         on_press: BindingAction,
         on_release: BindingAction,
         ordered: bool,
-    ) -> &HotkeyBinding {
-        let binding_length = keys.len();
+    ) -> BindingId {
         let binding = HotkeyBinding {
+            id: BindingId::next(),
+            prefix,
             keys,
             on_press,
             on_release,
             ordered,
+            enabled: true,
+            layer: None,
             triggered: false,
+            withheld: false,
+            taps: 0,
+            last_tap: None,
+        };
+        let id = binding.id;
+
+        self.insert(binding);
+        id
+    }
+
+    /// Sequences go first, as they only match after their other steps, then the ordered bindings,
+    /// so `s>alt` wins over `alt+s` when s was first.
+    fn insert(&mut self, binding: HotkeyBinding) {
+        let rank = |binding: &HotkeyBinding| (!binding.is_sequence(), !binding.ordered);
+        let bindings = self.bindings_by_length.entry(binding.keys.len()).or_default();
+        let at = bindings
+            .iter()
+            .position(|other| rank(other) > rank(&binding))
+            .unwrap_or(bindings.len());
+        bindings.insert(at, binding);
+    }
+
+    fn binding_mut(&mut self, id: BindingId) -> Option<&mut HotkeyBinding> {
+        self.bindings_by_length
+            .values_mut()
+            .flatten()
+            .find(|binding| binding.id == id)
+    }
+
+    /// Swaps in the bindings of `other` at once, so every key event sees either the old or the new
+    /// table. Bindings held down right now stay triggered if the new table binds the same keys, so
+    /// their release still fires. Everything else, like withheld keys, stays.
+    pub(crate) fn replace_bindings(&mut self, mut other: HotkeyManager) -> HotkeyManager {
+        for binding in other.bindings_by_length.values_mut().flatten() {
+            let old = self
+                .bindings_by_length
+                .get(&binding.keys.len())
+                .and_then(|old| {
+                    old.iter()
+                        .find(|old| old.triggered && old.keys == binding.keys && old.prefix == binding.prefix)
+                });
+            binding.triggered = old.is_some();
+            binding.withheld = old.is_some_and(|old| old.withheld);
+        }
+        std::mem::swap(&mut self.bindings_by_length, &mut other.bindings_by_length);
+        other
+    }
+
+    /// Keeps keys from applications while they can still become a hotkey, for at most `timeout`.
+    /// Needs a capture backend that can swallow keys. Steps of a sequence are kept whenever it can.
+    pub(crate) fn set_withhold(&mut self, timeout: Option<Duration>) {
+        self.can_swallow = INPUT_BACKEND.capture().can_swallow();
+        let timeout = timeout.filter(|_| {
+            if !self.can_swallow {
+                log::warn!(target: "HotkeyManager", "The {} backend can't keep keys from applications, not withholding them", INPUT_BACKEND.name());
+            }
+            self.can_swallow
+        });
+        if timeout.is_none() {
+            self.replay_withheld("withholding was turned off");
+        }
+        self.withhold = timeout;
+    }
+
+    /// How long a sequence waits for its next step before it's given up.
+    pub(crate) fn set_sequence_timeout(&mut self, timeout: Duration) {
+        self.sequence_timeout = timeout;
+    }
+
+    /// How long a binding waits for its next tap, see [`HotkeyBinding::taps`].
+    pub(crate) fn set_tap_timeout(&mut self, timeout: Duration) {
+        self.tap_timeout = timeout;
+    }
+
+    /// Holding a letter for `threshold` lets `1` to `9` replace it with one of its variants. The
+    /// variants are the ones in `variants` or else the letters with marks Unicode has for it.
+    pub(crate) fn set_hold(&mut self, threshold: Option<Duration>, variants: HashMap<char, Vec<char>>) {
+        self.hold_threshold = threshold;
+        self.hold_variants = variants;
+        if threshold.is_none() {
+            self.picking = None;
+        }
+    }
+
+    /// Layers that are on, the one turned on last at the end.
+    pub fn active_layers(&self) -> &[Arc<str>] {
+        &self.active_layers
+    }
+
+    /// Turns `layer` on or off, on top of the layers that are on already.
+    pub(crate) fn set_layer(&mut self, layer: &str, on: bool) {
+        let active = self.active_layers.iter().position(|name| &**name == layer);
+        match (active, on) {
+            (None, true) => self.active_layers.push(Arc::from(layer)),
+            (Some(index), false) => {
+                self.active_layers.remove(index);
+                self.layer_keys.retain(|_, name| &**name != layer);
+                // Its hold bindings don't get their release anymore
+                for binding in self.bindings_by_length.values_mut().flatten() {
+                    if matches!(&binding.on_press, BindingAction::Layer(name, LayerSwitch::Hold) if &**name == layer) {
+                        binding.triggered = false;
+                    }
+                }
+            }
+            _ => return,
+        }
+        log::info!(target: "HotkeyManager", "Layer {} is {}, active layers: [{}]", layer, if on { "on" } else { "off" }, self.active_layers.join(", "));
+        self.save_layers();
+    }
+
+    /// Keeps the toggled layers in `file` and turns the ones kept there on. `None` forgets them.
+    pub(crate) fn remember_layers(&mut self, file: Option<PathBuf>) {
+        if self.layers_file == file {
+            return;
+        }
+        self.layers_file = file;
+        let Some(file) = &self.layers_file else {
+            return;
         };
+        match fs::read_to_string(file) {
+            Ok(saved) => {
+                for layer in saved.lines().map(str::trim).filter(|layer| !layer.is_empty()) {
+                    if !self.active_layers.iter().any(|name| &**name == layer) {
+                        self.active_layers.push(Arc::from(layer));
+                    }
+                }
+                log::info!(target: "HotkeyManager", "Active layers from {:?}: [{}]", file, self.active_layers.join(", "));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => log::error!(target: "HotkeyManager", "Can't read the layers from {:?}: {:?}", file, e),
+        }
+    }
 
-        let bindings_for_length = self.bindings_by_length.entry(binding_length).or_default();
-        bindings_for_length.push_back(binding);
+    /// Writes the layers that are on, but not just held, to the layers file.
+    fn save_layers(&self) {
+        let Some(file) = &self.layers_file else {
+            return;
+        };
+        let held = self.layer_keys.values().collect::<HashSet<_>>();
+        let toggled = self
+            .active_layers
+            .iter()
+            .filter(|layer| !held.contains(layer))
+            .map(|layer| format!("{}\n", layer))
+            .collect::<String>();
+        if let Err(e) = fs::write(file, toggled) {
+            log::error!(target: "HotkeyManager", "Can't save the layers to {:?}: {:?}", file, e);
+        }
+    }
 
-        return bindings_for_length
-            .back()
-            .expect("Can't add binding to the pile");
+    /// Whether the binding works right now, its layer is on if it has one.
+    fn is_active(&self, binding: &HotkeyBinding) -> bool {
+        binding
+            .layer
+            .as_ref()
+            .is_none_or(|layer| self.active_layers.contains(layer))
+    }
+
+    /// 0 for bindings without a layer, more for the layers turned on later.
+    fn layer_rank(&self, binding: &HotkeyBinding) -> usize {
+        binding
+            .layer
+            .as_ref()
+            .and_then(|layer| self.active_layers.iter().position(|name| name == layer))
+            .map_or(0, |index| index + 1)
+    }
+
+    /// Turns the layer of a layer switch on or off.
+    fn switch_layer(&mut self, layer: Arc<str>, switch: LayerSwitch, pressed_keys: &PressedKeys) {
+        match switch {
+            LayerSwitch::Toggle => {
+                let on = !self.active_layers.contains(&layer);
+                self.set_layer(&layer, on);
+            }
+            LayerSwitch::Hold => {
+                self.layer_keys.extend(pressed_keys.iter().map(|key| (*key, layer.clone())));
+                self.set_layer(&layer, true);
+            }
+        }
+    }
+
+    /// Events of the keys holding a layer. The layer goes off once the last of them is released.
+    fn layer_key(&mut self, metadata: &KeyboardHookMetadata) -> Option<bool> {
+        let key = *metadata.key();
+        if metadata.injected() {
+            return None;
+        }
+        if metadata.pressing() {
+            // Key repeat
+            return self.layer_keys.contains_key(&key).then_some(true);
+        }
+        let layer = self.layer_keys.remove(&key)?;
+        if !self.layer_keys.values().any(|name| *name == layer) {
+            self.set_layer(&layer, false);
+        }
+        Some(true)
+    }
+
+    /// Starts the thread that lets withheld keys through once they timed out.
+    pub(crate) fn spawn_withhold_timer(manager: &Arc<Mutex<HotkeyManager>>) {
+        let (timer, deadlines) = mpsc::channel::<Instant>();
+        manager.lock().timer = Some(timer);
+
+        let manager = Arc::downgrade(manager);
+        thread::Builder::new()
+            .name("hotkey::withhold".to_string())
+            .spawn(move || {
+                let mut deadline: Option<Instant> = None;
+                loop {
+                    let next = match deadline {
+                        Some(deadline) => deadlines.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                        None => deadlines.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                    };
+                    match next {
+                        Ok(next) => deadline = Some(next),
+                        Err(RecvTimeoutError::Timeout) => {
+                            deadline = None;
+                            let Some(manager) = manager.upgrade() else {
+                                break;
+                            };
+                            manager.lock().expire_withheld();
+                        }
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+            })
+            .unwrap_or_else(|e| panic!("Thread hotkey::withhold failed. {:?}", e));
+    }
+
+    fn expire_withheld(&mut self) {
+        if self.expires_at.is_some_and(|at| at <= Instant::now()) {
+            if !self.steps_done.is_empty() {
+                log::debug!(target: "HotkeyManager", "Sequence timed out after {} steps", self.steps_done.len());
+                self.steps_done.clear();
+            }
+            self.replay_withheld("they timed out");
+        }
+    }
+
+    /// Wakes up the timer thread after `timeout`.
+    fn expire_in(&mut self, timeout: Duration) {
+        let at = Instant::now() + timeout;
+        self.expires_at = Some(at);
+        if let Some(timer) = &self.timer {
+            let _ = timer.send(at);
+        }
+    }
+
+    /// Lets the withheld events through unchanged.
+    fn replay_withheld(&mut self, reason: &str) {
+        self.expires_at = None;
+        if self.withheld.is_empty() {
+            return;
+        }
+        let events = std::mem::take(&mut self.withheld);
+        log::debug!(target: "HotkeyManager", "Replaying {} withheld events, {}", events.len(), reason);
+        INPUT_BACKEND.injection().send_keystrokes(&events);
+    }
+
+    /// Forgets the withheld events a binding was made of. Returns whether all the keys of the
+    /// binding but `key` were withheld, `None` if nothing was.
+    fn take_withheld(&mut self, pressed_keys: &PressedKeys, key: KeyCode) -> Option<bool> {
+        if self.withheld.is_empty() {
+            return None;
+        }
+        let withheld = pressed_keys.iter().filter(|&&code| code != key).all(|&code| {
+            self.withheld
+                .iter()
+                .any(|stroke| stroke.key == Some(code) && stroke.action == KeyAction::Press)
+        });
+        // Keys of earlier steps were released already, only the ones still down get a release
+        for stroke in self.withheld.drain(..) {
+            if let Some(code) = stroke.key.filter(|code| pressed_keys.contains(code)) {
+                self.consumed.insert(code);
+            }
+        }
+        self.expires_at = None;
+        Some(withheld)
+    }
+
+    /// Whether more keys can turn the pressed ones into an enabled binding.
+    fn is_prefix(&self, pressed_keys: &PressedKeys, char_cache: &mut HashMap<KeyCode, Vec<String>>) -> bool {
+        self.bindings_by_length
+            .iter()
+            .filter(|(length, _)| **length > pressed_keys.len())
+            .flat_map(|(_, bindings)| bindings)
+            .filter(|binding| binding.enabled && !binding.is_sequence() && self.is_active(binding))
+            .any(|binding| chord_starts_with(&binding.keys, binding.ordered, pressed_keys, char_cache))
+    }
+
+    fn sequences(&self) -> impl Iterator<Item = &HotkeyBinding> {
+        self.bindings_by_length
+            .values()
+            .flatten()
+            .filter(|binding| binding.enabled && binding.is_sequence() && self.is_active(binding))
+    }
+
+    /// Whether an enabled sequence has more steps than `steps` and starts with them.
+    fn continues_sequence(&self, steps: &[PressedKeys], char_cache: &mut HashMap<KeyCode, Vec<String>>) -> bool {
+        self.sequences().any(|binding| {
+            binding.step_count() > steps.len()
+                && steps.iter().enumerate().all(|(index, chord)| {
+                    let (keys, ordered) = binding.step(index);
+                    chord_matches(keys, ordered, chord, char_cache, true)
+                })
+        })
+    }
+
+    /// Whether the chord being pressed can still become the next step of a sequence.
+    fn chord_continues_sequence(&self, char_cache: &mut HashMap<KeyCode, Vec<String>>) -> bool {
+        let next = self.steps_done.len();
+        self.sequences().any(|binding| {
+            if binding.step_count() <= next {
+                return false;
+            }
+            let (keys, ordered) = binding.step(next);
+            self.chord.len() <= keys.len()
+                && chord_starts_with(keys, ordered, &self.chord, char_cache)
+                && binding.prefix.len() >= next
+                && binding.prefix[..next]
+                    .iter()
+                    .zip(&self.steps_done)
+                    .all(|(step, chord)| chord_matches(&step.keys, step.ordered, chord, char_cache, true))
+        })
+    }
+
+    /// All keys are up again. The chord becomes the next step of the sequence in progress, starts a
+    /// new one or cancels it.
+    fn finish_chord(&mut self, char_cache: &mut HashMap<KeyCode, Vec<String>>) {
+        let chord = std::mem::take(&mut self.chord);
+        if chord.is_empty() {
+            return;
+        }
+        let mut steps = std::mem::take(&mut self.steps_done);
+        steps.push(chord.clone());
+        if !self.continues_sequence(&steps, char_cache) {
+            if steps.len() > 1 {
+                log::debug!(target: "HotkeyManager", "{} doesn't continue the sequence, cancelling it", chord.dump());
+            }
+            steps = vec![chord];
+            if !self.continues_sequence(&steps, char_cache) {
+                steps.clear();
+            }
+        }
+
+        if steps.is_empty() {
+            self.replay_withheld("the sequence was cancelled");
+        } else {
+            log::debug!(target: "HotkeyManager", "Step {} of a sequence: {}", steps.len(), steps.last().map(Dump::dump).unwrap_or_default());
+            self.expire_in(self.sequence_timeout);
+        }
+        self.steps_done = steps;
+    }
+
+    /// Called for events no binding took. Presses that can still become a hotkey are kept, the
+    /// first event that can't lets everything kept so far through, itself included.
+    fn withhold_event(
+        &mut self,
+        metadata: &KeyboardHookMetadata,
+        char_cache: &mut HashMap<KeyCode, Vec<String>>,
+    ) -> bool {
+        let key = *metadata.key();
+        if metadata.releasing() && self.consumed.remove(&key) {
+            log::debug!(target: "HotkeyManager", "Withholding the release of {:?}, its press became a hotkey", Code(key));
+            return true;
+        }
+        let action = if metadata.pressing() {
+            KeyAction::Press
+        } else {
+            KeyAction::Release
+        };
+
+        // Steps of a sequence are released before the next one, so releases are kept too
+        let sequence = self.can_swallow && self.chord_continues_sequence(char_cache);
+        let chord = self.withhold.is_some() && metadata.pressing() && self.is_prefix(metadata.pressed_keys(), char_cache);
+        if sequence || chord {
+            if sequence {
+                self.expire_in(self.sequence_timeout);
+            } else if let (true, Some(timeout)) = (self.withheld.is_empty(), self.withhold) {
+                self.expire_in(timeout);
+            }
+            log::debug!(target: "HotkeyManager", "Withholding {:?}, it can still become a hotkey", Code(key));
+            self.withheld.push(KeyStroke::classic(key, action));
+            return true;
+        }
+        if self.withheld.is_empty() {
+            return false;
+        }
+        self.withheld.push(KeyStroke::classic(key, action));
+        self.steps_done.clear();
+        self.replay_withheld("they can't become a hotkey anymore");
+        true
     }
 
     /*pub(crate) fn add_binding(&mut self, keys: KeyBinding, on_press: Callback, on_release:Callback, ordered: bool) -> &HotkeyBinding  {
@@ -315,50 +980,209 @@ impl HotkeyManager {
         on_press: Callback,
         on_release: Callback,
         ordered: bool,
-    ) -> &HotkeyBinding {
-        let (on_press_tx, on_press_rx): (ChannelSender, ChannelReceiver) = mpsc::channel();
-
-        let (on_release_tx, on_release_rx): (ChannelSender, ChannelReceiver) = mpsc::channel();
-
-        std::thread::Builder::new()
-            .name("hotkey::press".to_string())
-            .spawn(move || {
-                for data in on_press_rx {
-                    log::trace!(target: "MagicBinding", "[keypress] received new data");
-                    on_press(data);
-                }
-            }).unwrap_or_else(|e|panic!("Thread hotkey::release failed. {:?}", e));
-        std::thread::Builder::new()
-            .name("hotkey::release".to_string())
-            .spawn(move || {
-                for data in on_release_rx {
-                    log::trace!(target: "MagicBinding", "[keyrelease] received new data");
-                    on_release(data);
-                }
-            }).unwrap_or_else(|e|panic!("Thread hotkey::release failed. {:?}", e));
+    ) -> BindingId {
         self._add_binding(
+            KeySequence::new(),
             keys,
-            BindingAction::Magic(on_press_tx),
-            BindingAction::Magic(on_release_tx),
+            BindingAction::Magic(Arc::new(on_press)),
+            BindingAction::Magic(Arc::new(on_release)),
             ordered,
         )
     }
 
-    pub fn add_channel_binding(&mut self, keys: KeyBinding) -> (ChannelReceiver, ChannelReceiver) {
-        let (on_press_tx, on_press_rx) = mpsc::channel();
-        let (on_release_tx, on_release_rx) = mpsc::channel();
+    /// Fires when the chords of `steps` are pressed one after another, each released before the
+    /// next. The actions run for the last step, like for a plain binding.
+    pub(crate) fn add_magic_sequence(
+        &mut self,
+        mut steps: KeySequence,
+        on_press: Callback,
+        on_release: Callback,
+    ) -> BindingId {
+        let last = steps.pop().expect("Sequence without steps");
         self._add_binding(
-            keys,
-            BindingAction::Channel(on_press_tx),
-            BindingAction::Channel(on_release_tx),
-            false,
-        );
-        (on_press_rx, on_release_rx)
+            steps,
+            last.keys,
+            BindingAction::Magic(Arc::new(on_press)),
+            BindingAction::Magic(Arc::new(on_release)),
+            last.ordered,
+        )
+    }
+
+    /// The keys put `mark` on the next letter typed, precomposed if Unicode has such a character.
+    pub(crate) fn add_dead_key(&mut self, mut steps: KeySequence, mark: char) -> BindingId {
+        let last = steps.pop().expect("Dead key without keys");
+        self._add_binding(
+            steps,
+            last.keys,
+            BindingAction::DeadKey(mark),
+            BindingAction::DeadKey(mark),
+            last.ordered,
+        )
+    }
+
+    /// The keys turn `layer` on and off, or keep it on while they're down.
+    pub(crate) fn add_layer_switch(&mut self, mut steps: KeySequence, layer: &str, switch: LayerSwitch) -> BindingId {
+        let last = steps.pop().expect("Layer switch without keys");
+        let action = BindingAction::Layer(Arc::from(layer), switch);
+        self._add_binding(steps, last.keys, action.clone(), action, last.ordered)
+    }
+
+    /// Makes the binding work only while `layer` is on, or always with `None`. Returns false for an
+    /// unknown binding.
+    pub(crate) fn move_to_layer(&mut self, id: BindingId, layer: Option<&str>) -> bool {
+        match self.binding_mut(id) {
+            Some(binding) => {
+                binding.layer = layer.map(Arc::from);
+                true
+            }
+            None => false,
+        }
     }
 
     pub(crate) fn check_and_trigger(&mut self, metadata: &KeyboardHookMetadata) -> bool {
+        let mut char_cache: HashMap<KeyCode, Vec<String>> = HashMap::new();
+        self.expire_withheld();
+        if !metadata.injected() && metadata.pressing() {
+            if metadata.pressed_keys().len() == 1 {
+                self.chord.clear();
+            }
+            self.chord.insert(*metadata.key());
+        }
+
+        // Any other key in between starts the taps over
+        let last_tapped = match !metadata.injected() && metadata.pressing() && !metadata.key().is_modifier() {
+            true => self.last_tapped.take(),
+            false => self.last_tapped,
+        };
+        if let Some(handled) = self.layer_key(metadata) {
+            return handled;
+        }
+        if let Some(handled) = self.compose_dead_key(metadata) {
+            return handled;
+        }
+        if let Some(handled) = self.hold_picker(metadata) {
+            return handled;
+        }
+        let handled = self.match_event(metadata, &mut char_cache, last_tapped);
+
+        if !metadata.injected() && metadata.releasing() && metadata.pressed_keys_after().is_empty() {
+            self.finish_chord(&mut char_cache);
+        }
+        handled
+    }
+
+    /// Puts the mark of a pending dead key on the letter being pressed. The key itself is kept from
+    /// applications. Any other key but shift cancels the dead key and goes on as usual.
+    fn compose_dead_key(&mut self, metadata: &KeyboardHookMetadata) -> Option<bool> {
         let key = *metadata.key();
-        if is_meta_or_alt(key) {
+        if metadata.injected() || !metadata.pressing() || key.is_shift() {
+            return None;
+        }
+        let mark = self.dead_key.take()?;
+        let pressed_keys = metadata.pressed_keys();
+        let modified = pressed_keys.iter().any(|code| code.is_modifier() && !code.is_shift());
+        let typed = characters_of(key).into_iter().next().filter(|typed| can_compose(typed));
+        let (Some(typed), false) = (typed, modified) else {
+            log::debug!(target: "HotkeyManager", "{:?} cancelled dead key U+{:04X}", Code(key), mark as u32);
+            return None;
+        };
+
+        let base = if pressed_keys.iter().any(|code| code.is_shift()) {
+            typed.to_uppercase()
+        } else {
+            typed
+        };
+        let composed = compose(&base, mark);
+        log::debug!(target: "HotkeyManager", "Dead key U+{:04X} on {} makes {}", mark as u32, base, composed);
+        let strokes = composed
+            .chars()
+            .flat_map(|char| [KeyStroke::unicode(char, KeyAction::Press), KeyStroke::unicode(char, KeyAction::Release)])
+            .collect::<Vec<_>>();
+        INPUT_BACKEND.injection().send_keystrokes(&strokes);
+        self.consumed.insert(key);
+        Some(true)
+    }
+
+    /// Variants offered for the letter `key` types, capitals when shift is down. `None` if it's no
+    /// letter, has no variants or other modifiers are down.
+    fn variants_of_key(&self, key: KeyCode, pressed_keys: &PressedKeys) -> Option<Vec<char>> {
+        if pressed_keys.iter().any(|code| code.is_modifier() && !code.is_shift()) {
+            return None;
+        }
+        let typed = characters_of(key).into_iter().next().filter(|typed| can_compose(typed))?;
+        let letter = typed.chars().next()?;
+        let variants = match self.hold_variants.get(&letter) {
+            Some(variants) => variants.clone(),
+            None => variants_of(letter),
+        };
+        let variants = if pressed_keys.iter().any(|code| code.is_shift()) {
+            variants
+                .iter()
+                .map(|variant| variant.to_uppercase().next().unwrap_or(*variant))
+                .collect()
+        } else {
+            variants
+        };
+        (!variants.is_empty()).then_some(variants)
+    }
+
+    /// Press-and-hold: the repeats of a letter with variants are kept from applications, and once
+    /// it's held long enough a digit replaces the typed letter with a variant. Escape or any other
+    /// key ends the choice.
+    fn hold_picker(&mut self, metadata: &KeyboardHookMetadata) -> Option<bool> {
+        let threshold = self.hold_threshold?;
+        let key = *metadata.key();
+        if metadata.injected() {
+            return None;
+        }
+        if metadata.pressing() && !metadata.repeat() {
+            if self.held_key == Some(key) {
+                self.held_key = None;
+            }
+            if key.is_modifier() {
+                return None;
+            }
+            let variants = self.picking.take()?;
+            if key == KeyCode::Escape {
+                log::debug!(target: "HotkeyManager", "Picking a variant was cancelled");
+                self.consumed.insert(key);
+                return Some(true);
+            }
+            let variant = digit_index(key).and_then(|index| variants.get(index))?;
+            log::debug!(target: "HotkeyManager", "Replacing the held letter with {}", variant);
+            let variant = [KeyStroke::unicode(*variant, KeyAction::Press), KeyStroke::unicode(*variant, KeyAction::Release)];
+            // Queued like the action of a binding, so it doesn't overtake what bindings type
+            let callback: Arc<Callback> = Arc::new(Box::new(move |_| replace_last_output(&[], 1, &variant)));
+            let picked = HotkeyBinding::internal(vec![Key::Code(key)], &callback);
+            ACTION_DISPATCHER.dispatch(Phase::Press, &callback, TriggeredHotkey(picked, metadata.pressed_keys().clone()));
+            self.consumed.insert(key);
+            return Some(true);
+        }
+
+        let variants = self.variants_of_key(key, metadata.pressed_keys())?;
+        if metadata.held_for() >= threshold && self.held_key != Some(key) {
+            let choices = variants
+                .iter()
+                .take(9)
+                .enumerate()
+                .map(|(index, variant)| format!("{} {}", index + 1, variant))
+                .collect::<Vec<_>>();
+            log::info!(target: "HotkeyManager", "Holding {:?}: {}", Code(key), choices.join("  "));
+            self.picking = Some(variants);
+            self.held_key = Some(key);
+        }
+        // The release goes on, the letter was typed by its first press
+        metadata.pressing().then_some(true)
+    }
+
+    fn match_event(
+        &mut self,
+        metadata: &KeyboardHookMetadata,
+        char_cache: &mut HashMap<KeyCode, Vec<String>>,
+        last_tapped: Option<BindingId>,
+    ) -> bool {
+        let key = *metadata.key();
+        if key.is_meta_or_alt() {
             let activated = self
                 .bindings_by_length
                 .values()
@@ -368,51 +1192,591 @@ impl HotkeyManager {
                 .collect::<Vec<_>>(); // Collect the filtered and cloned bindings into a Vec
             if !activated
                 .iter()
-                .filter(|f| f.keys.has_virtual_key_value(key))
+                .filter(|f| f.keys.has_key_code_value(key))
                 .collect::<Vec<_>>()
                 .is_empty()
             {
                 if metadata.pressing() {
                     log::debug!(target: "HotkeyManager",
                         "We think that we are sill pressing a binding, so ignoring {:?}",
-                        VirtualKey(key.clone())
+                        Code(key)
                     );
                     return true;
                 } else if metadata.releasing() && metadata.injected() {
                     log::debug!(target: "HotkeyManager",
                         "Ignoring injected releases {:?}",
-                        VirtualKey(key.clone())
+                        Code(key)
                     );
                     return true;
                 }
             }
         }
 
-        let pressed_keys = metadata.pressed_keys();
+        let without_layer_keys;
+        let pressed_keys = match self.layer_keys.is_empty() {
+            true => metadata.pressed_keys(),
+            false => {
+                without_layer_keys = metadata
+                    .pressed_keys()
+                    .iter()
+                    .filter(|key| !self.layer_keys.contains_key(key))
+                    .copied()
+                    .collect::<PressedKeys>();
+                &without_layer_keys
+            }
+        };
         let pressed_count = pressed_keys.len();
-        let mut scancode_cache: HashMap<u32, u32> = HashMap::new();
-        let mut char_cache: HashMap<u32, Option<String>> = HashMap::new();
-
-        if let Some(bindings) = self.bindings_by_length.get_mut(&pressed_count) {
-            for binding in bindings.iter_mut() {
-                if binding.should_trigger(pressed_keys, &mut scancode_cache, &mut char_cache) {
-                    log::trace!(target: "HotkeyManager", "Got a match, firing callback {:?}", binding);
-                    return binding.execute_binding_actions(metadata, pressed_keys);
+        let steps_done = &self.steps_done;
+
+        // The binding of the layer turned on last wins
+        let found = self.bindings_by_length.get(&pressed_count).and_then(|bindings| {
+            bindings
+                .iter()
+                .enumerate()
+                .filter(|(_, binding)| {
+                    let releasing_triggered = binding.triggered && metadata.releasing();
+                    let active = binding.enabled && self.is_active(binding);
+                    (releasing_triggered || active && binding.prefix_matches(steps_done, char_cache))
+                        && binding.should_trigger(pressed_keys, char_cache, !releasing_triggered)
+                })
+                .min_by_key(|(_, binding)| std::cmp::Reverse(self.layer_rank(binding)))
+                .map(|(index, _)| index)
+        });
+        if let Some(index) = found {
+            let withheld = if metadata.pressing() {
+                self.take_withheld(pressed_keys, key)
+            } else {
+                None
+            };
+            let binding = &mut self
+                .bindings_by_length
+                .get_mut(&pressed_count)
+                .expect("Bindings of a matched length are gone")[index];
+            match withheld {
+                Some(withheld) => binding.withheld = withheld,
+                None if metadata.pressing() && !binding.triggered => binding.withheld = false,
+                None => {}
+            }
+            // Key repeat isn't a tap. Keys kept by a dual-role key count from when they were pressed
+            if metadata.pressing() && !binding.triggered {
+                let now = metadata.time();
+                let again = last_tapped == Some(binding.id)
+                    && binding.last_tap.is_some_and(|at| now.duration_since(at) <= self.tap_timeout);
+                binding.taps = if again { binding.taps + 1 } else { 1 };
+                binding.last_tap = Some(now);
+            }
+            if metadata.pressing() {
+                self.last_tapped = Some(binding.id);
+            }
+            log::trace!(target: "HotkeyManager", "Got a match, firing callback {:?}", binding);
+            let handled = binding.execute_binding_actions(metadata, pressed_keys);
+            if metadata.pressing() {
+                if binding.is_sequence() {
+                    self.steps_done.clear();
+                }
+                match binding.on_press.clone() {
+                    BindingAction::DeadKey(mark) => {
+                        log::debug!(target: "HotkeyManager", "Dead key U+{:04X} waits for a letter", mark as u32);
+                        self.dead_key = Some(mark);
+                    }
+                    BindingAction::Layer(layer, switch) if !metadata.repeat() => {
+                        self.switch_layer(layer, switch, pressed_keys)
+                    }
+                    _ => {}
                 }
             }
+            return handled;
         }
 
-        false
+        if metadata.injected() {
+            return false;
+        }
+        self.withhold_event(metadata, char_cache)
     }
+}
 
-    /*    fn is_triggered(&self, pressed_keys: &HashSet<VIRTUAL_KEY>, binding: &HotkeyBinding) -> bool {
-        let result = if binding.ordered {
-            binding.keys.iter().all(|f| {
-                pressed_keys.contains(&f.vk)
-            })
-        } else {
-            binding.keys.iter().all(|key| pressed_keys.contains(&key.vk))
+/// Changing single bindings while the manager runs, for tools that embed it. The daemon itself
+/// swaps whole tables with [`HotkeyManager::replace_bindings`].
+impl HotkeyManager {
+    /// Length bucket and index of the binding.
+    fn position(&self, id: BindingId) -> Option<(usize, usize)> {
+        self.bindings_by_length.iter().find_map(|(length, bindings)| {
+            bindings
+                .iter()
+                .position(|binding| binding.id == id)
+                .map(|index| (*length, index))
+        })
+    }
+
+    pub fn binding(&self, id: BindingId) -> Option<&HotkeyBinding> {
+        self.bindings_by_length
+            .values()
+            .flatten()
+            .find(|binding| binding.id == id)
+    }
+
+    /// Removes the binding. The channels of a channel binding are closed, so its receivers get
+    /// disconnected.
+    pub fn remove(&mut self, id: BindingId) -> Option<RemovedBinding> {
+        let (length, index) = self.position(id)?;
+        let bindings = self.bindings_by_length.get_mut(&length)?;
+        let binding = bindings.remove(index)?;
+        if bindings.is_empty() {
+            self.bindings_by_length.remove(&length);
+        }
+        if binding.triggered {
+            log::warn!(target: "HotkeyManager", "Removing {:?} while it's pressed, its release won't fire", binding.keys);
+        }
+        log::debug!(target: "HotkeyManager", "Removed binding {:?} {:?}", id, binding.keys);
+        Some(RemovedBinding {
+            magic: matches!(binding.on_press, BindingAction::Magic(_)),
+            keys: binding.keys,
+        })
+    }
+
+    /// Binds the actions of `id` to other keys. Returns false for an unknown binding.
+    pub fn replace(&mut self, id: BindingId, keys: KeyBinding) -> bool {
+        let Some((length, index)) = self.position(id) else {
+            return false;
         };
-        result
-    }*/
+        let Some(mut binding) = self
+            .bindings_by_length
+            .get_mut(&length)
+            .and_then(|bindings| bindings.remove(index))
+        else {
+            return false;
+        };
+        if self.bindings_by_length.get(&length).is_some_and(VecDeque::is_empty) {
+            self.bindings_by_length.remove(&length);
+        }
+        log::debug!(target: "HotkeyManager", "Rebinding {:?} from {:?} to {:?}", id, binding.keys, keys);
+        binding.keys = keys;
+        binding.triggered = false;
+        self.insert(binding);
+        true
+    }
+
+    /// A disabled binding keeps its place but doesn't fire. If it's pressed right
+    /// now, its release still does. Returns false for an unknown binding.
+    pub fn set_enabled(&mut self, id: BindingId, enabled: bool) -> bool {
+        match self.binding_mut(id) {
+            Some(binding) => {
+                binding.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn enable(&mut self, id: BindingId) -> bool {
+        self.set_enabled(id, true)
+    }
+
+    pub fn disable(&mut self, id: BindingId) -> bool {
+        self.set_enabled(id, false)
+    }
+
+    pub fn add_channel_binding(&mut self, keys: KeyBinding) -> (BindingId, ChannelReceiver, ChannelReceiver) {
+        let (on_press_tx, on_press_rx) = mpsc::channel();
+        let (on_release_tx, on_release_rx) = mpsc::channel();
+        let id = self._add_binding(
+            KeySequence::new(),
+            keys,
+            BindingAction::Channel(on_press_tx),
+            BindingAction::Channel(on_release_tx),
+            false,
+        );
+        (id, on_press_rx, on_release_rx)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::mpsc::TryRecvError;
+
+    use super::*;
+    use crate::backend::recorder::record;
+    use crate::keycode::KeyCode::{
+        AltLeft, ControlLeft, Digit1, Digit8, Digit9, KeyA, KeyB, KeyE, KeyS, KeyX, ShiftLeft,
+    };
+    use crate::layout::load_test_layouts;
+    use crate::r#type::keyboardhook::KeyManager;
+
+    pub(crate) fn keys(codes: &[KeyCode]) -> KeyBinding {
+        codes.iter().map(|code| Key::Code(*code)).collect()
+    }
+
+    /// Key manager running the events through `manager`, the way the daemon hooks them up.
+    fn hooked(manager: HotkeyManager) -> KeyManager {
+        let manager = Arc::new(Mutex::new(manager));
+        HotkeyManager::spawn_withhold_timer(&manager);
+        let mut key_manager = KeyManager::with_storage(PressedKeys::new());
+        key_manager.add_hook(
+            |metadata, manager: &Arc<Mutex<HotkeyManager>>| {
+                let metadata = metadata
+                    .as_any()
+                    .downcast_ref::<KeyboardHookMetadata>()
+                    .expect("Failed to downcast metadata as keyboard hook.");
+                Ok(manager.lock().check_and_trigger(metadata))
+            },
+            manager,
+        );
+        key_manager
+    }
+
+    fn strokes(typed: &[KeyStroke]) -> Vec<(KeyCode, KeyAction)> {
+        typed
+            .iter()
+            .filter_map(|stroke| stroke.key.map(|key| (key, stroke.action)))
+            .collect()
+    }
+
+    /// Callback counting how often it ran.
+    fn counter() -> (Arc<AtomicUsize>, Callback) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let counted = count.clone();
+        (count, Box::new(move |_| {
+            counted.fetch_add(1, Ordering::SeqCst);
+        }))
+    }
+
+    fn type_keys(key_manager: &mut KeyManager, codes: &[KeyCode]) {
+        for &key in codes {
+            key_manager.keydown(key, false, "test");
+        }
+        for &key in codes.iter().rev() {
+            key_manager.keyup(key, false, "test");
+        }
+    }
+
+    /// Manager with the sequence `ctrl+x, 8, e`.
+    fn ctrl_x_8_e(timeout: Duration) -> (KeyManager, Arc<AtomicUsize>) {
+        let mut manager = withholding(Duration::from_secs(10));
+        manager.set_sequence_timeout(timeout);
+        let steps = [keys(&[ControlLeft, KeyX]), keys(&[Digit8]), keys(&[KeyE])]
+            .into_iter()
+            .map(|keys| BindingKeys { keys, ordered: false })
+            .collect();
+        let (count, on_press) = counter();
+        manager.add_magic_sequence(steps, on_press, Box::new(|_| {}));
+        (hooked(manager), count)
+    }
+
+    fn withholding(timeout: Duration) -> HotkeyManager {
+        let mut manager = HotkeyManager::new();
+        manager.set_withhold(Some(timeout));
+        manager
+    }
+
+    /// Presses the keys one after another and lets them go in reverse. Whether each of the events
+    /// was kept from applications.
+    pub(crate) fn tap(manager: &mut HotkeyManager, codes: &[KeyCode]) -> Vec<bool> {
+        let mut pressed = PressedKeys::new();
+        let mut handled = Vec::new();
+        for &key in codes {
+            let before = pressed.clone();
+            pressed.insert(key);
+            handled.push(manager.check_and_trigger(&KeyboardHookMetadata::Press {
+                key,
+                injected: false,
+                pressed_keys: pressed.clone(),
+                pressed_keys_before_hook: before,
+                held_for: Duration::ZERO,
+                time: Instant::now(),
+            }));
+        }
+        for &key in codes.iter().rev() {
+            let before = pressed.clone();
+            pressed.shift_remove(&key);
+            handled.push(manager.check_and_trigger(&KeyboardHookMetadata::Release {
+                key,
+                injected: false,
+                pressed_keys: pressed.clone(),
+                pressed_keys_before_hook: before,
+                held_for: Duration::ZERO,
+                time: Instant::now(),
+            }));
+        }
+        handled
+    }
+
+    /// The characters typed as unicode strokes.
+    fn characters(typed: &[KeyStroke]) -> String {
+        typed
+            .iter()
+            .filter(|stroke| stroke.key.is_none() && stroke.action == KeyAction::Press)
+            .filter_map(|stroke| char::from_u32(stroke.scancode))
+            .collect()
+    }
+
+    /// Manager with an acute accent dead key on `alt+8`.
+    fn acute_on_alt_8() -> HotkeyManager {
+        load_test_layouts();
+        let mut manager = HotkeyManager::new();
+        let steps = vec![BindingKeys { keys: keys(&[AltLeft, Digit8]), ordered: false }];
+        manager.add_dead_key(steps, '\u{301}');
+        manager
+    }
+
+    #[test]
+    fn channel_binding_fires_with_its_id() {
+        let mut manager = HotkeyManager::new();
+        let (id, press, release) = manager.add_channel_binding(keys(&[AltLeft, KeyA]));
+        tap(&mut manager, &[AltLeft, KeyA]);
+        assert_eq!(press.try_recv().map(|hotkey| hotkey.0.id()), Ok(id));
+        assert_eq!(release.try_recv().map(|hotkey| hotkey.0.id()), Ok(id));
+    }
+
+    #[test]
+    fn disabled_binding_fires_again_once_enabled() {
+        let mut manager = HotkeyManager::new();
+        let (id, press, _release) = manager.add_channel_binding(keys(&[AltLeft, KeyA]));
+
+        assert!(manager.disable(id));
+        tap(&mut manager, &[AltLeft, KeyA]);
+        assert_eq!(press.try_recv().err(), Some(TryRecvError::Empty));
+
+        assert!(manager.enable(id));
+        tap(&mut manager, &[AltLeft, KeyA]);
+        assert!(press.try_recv().is_ok());
+    }
+
+    #[test]
+    fn replaced_binding_fires_on_the_new_keys() {
+        let mut manager = HotkeyManager::new();
+        let (id, press, _release) = manager.add_channel_binding(keys(&[AltLeft, KeyA]));
+
+        assert!(manager.replace(id, keys(&[AltLeft, KeyB])));
+        assert_eq!(manager.binding(id).map(HotkeyBinding::keys), Some(&keys(&[AltLeft, KeyB])));
+        tap(&mut manager, &[AltLeft, KeyA]);
+        assert_eq!(press.try_recv().err(), Some(TryRecvError::Empty));
+        tap(&mut manager, &[AltLeft, KeyB]);
+        assert_eq!(press.try_recv().map(|hotkey| hotkey.0.id()), Ok(id));
+    }
+
+    #[test]
+    fn removed_binding_disconnects_its_receivers() {
+        let mut manager = HotkeyManager::new();
+        let (id, press, release) = manager.add_channel_binding(keys(&[AltLeft, KeyA]));
+
+        let removed = manager.remove(id).expect("The binding was added");
+        assert_eq!(removed.keys, keys(&[AltLeft, KeyA]));
+        assert!(manager.binding(id).is_none());
+        assert_eq!(press.try_recv().err(), Some(TryRecvError::Disconnected));
+        assert_eq!(release.recv_timeout(Duration::from_secs(1)).err(), Some(RecvTimeoutError::Disconnected));
+
+        tap(&mut manager, &[AltLeft, KeyA]);
+        assert!(manager.remove(id).is_none());
+        assert!(!manager.replace(id, keys(&[KeyB])));
+        assert!(!manager.enable(id));
+    }
+
+    #[test]
+    fn join_waits_for_the_queued_actions() {
+        static RAN: AtomicUsize = AtomicUsize::new(0);
+        let mut manager = HotkeyManager::new();
+        let slow = Box::new(|_| {
+            thread::sleep(Duration::from_millis(50));
+            RAN.fetch_add(1, Ordering::SeqCst);
+        });
+        let id = manager.add_magic_binding(keys(&[AltLeft, KeyB]), slow, Box::new(|_| {}), false);
+
+        tap(&mut manager, &[AltLeft, KeyB]);
+        manager.remove(id).expect("The binding was added").join();
+        assert_eq!(RAN.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn withheld_prefix_is_let_through_when_the_chord_cant_match() {
+        let mut manager = withholding(Duration::from_secs(10));
+        let (_id, press, _release) = manager.add_channel_binding(keys(&[AltLeft, KeyA]));
+        let mut key_manager = hooked(manager);
+
+        let typed = record(|| {
+            assert!(key_manager.keydown(AltLeft, false, "test"));
+            assert!(key_manager.keydown(KeyB, false, "test"));
+            assert!(!key_manager.keyup(KeyB, false, "test"));
+            assert!(!key_manager.keyup(AltLeft, false, "test"));
+        });
+        assert_eq!(strokes(&typed), vec![(AltLeft, KeyAction::Press), (KeyB, KeyAction::Press)]);
+        assert!(press.try_recv().is_err());
+    }
+
+    #[test]
+    fn withheld_prefix_is_let_through_after_the_timeout() {
+        let mut manager = withholding(Duration::from_millis(20));
+        let (_id, _press, _release) = manager.add_channel_binding(keys(&[AltLeft, KeyA]));
+        let mut key_manager = hooked(manager);
+
+        let typed = record(|| {
+            assert!(key_manager.keydown(AltLeft, false, "test"));
+            thread::sleep(Duration::from_millis(200));
+        });
+        assert_eq!(strokes(&typed), vec![(AltLeft, KeyAction::Press)]);
+        // Went through already
+        assert!(!key_manager.keyup(AltLeft, false, "test"));
+    }
+
+    #[test]
+    fn completed_chord_fires_without_replaying_its_keys() {
+        let mut manager = withholding(Duration::from_secs(10));
+        let (id, press, release) = manager.add_channel_binding(keys(&[AltLeft, KeyA]));
+        let mut key_manager = hooked(manager);
+
+        let typed = record(|| {
+            assert!(key_manager.keydown(AltLeft, false, "test"));
+            assert!(key_manager.keydown(KeyA, false, "test"));
+            assert!(key_manager.keyup(KeyA, false, "test"));
+            assert!(key_manager.keyup(AltLeft, false, "test"));
+        });
+        assert!(typed.is_empty(), "{:?}", typed);
+        assert_eq!(press.try_recv().map(|hotkey| hotkey.0.id()), Ok(id));
+        assert_eq!(release.try_recv().map(|hotkey| hotkey.0.id()), Ok(id));
+    }
+
+    #[test]
+    fn sequence_fires_after_its_last_step() {
+        let (mut key_manager, count) = ctrl_x_8_e(Duration::from_secs(10));
+        let typed = record(|| {
+            type_keys(&mut key_manager, &[ControlLeft, KeyX]);
+            type_keys(&mut key_manager, &[Digit8]);
+            type_keys(&mut key_manager, &[KeyE]);
+        });
+        assert!(typed.is_empty(), "{:?}", typed);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn wrong_step_cancels_the_sequence() {
+        let (mut key_manager, count) = ctrl_x_8_e(Duration::from_secs(10));
+        let typed = record(|| {
+            type_keys(&mut key_manager, &[ControlLeft, KeyX]);
+            type_keys(&mut key_manager, &[Digit9]);
+            type_keys(&mut key_manager, &[Digit8]);
+            type_keys(&mut key_manager, &[KeyE]);
+        });
+        let replayed = vec![
+            (ControlLeft, KeyAction::Press),
+            (KeyX, KeyAction::Press),
+            (KeyX, KeyAction::Release),
+            (ControlLeft, KeyAction::Release),
+            (Digit9, KeyAction::Press),
+        ];
+        assert_eq!(strokes(&typed), replayed);
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn sequence_times_out_between_steps() {
+        let (mut key_manager, count) = ctrl_x_8_e(Duration::from_millis(20));
+        let typed = record(|| {
+            type_keys(&mut key_manager, &[ControlLeft, KeyX]);
+            thread::sleep(Duration::from_millis(200));
+            type_keys(&mut key_manager, &[Digit8]);
+            type_keys(&mut key_manager, &[KeyE]);
+        });
+        let replayed = vec![
+            (ControlLeft, KeyAction::Press),
+            (KeyX, KeyAction::Press),
+            (KeyX, KeyAction::Release),
+            (ControlLeft, KeyAction::Release),
+        ];
+        assert_eq!(strokes(&typed), replayed);
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn chord_order_matters_only_when_ordered() {
+        let mut cache = HashMap::new();
+        let alt_then_s = [AltLeft, KeyS].into_iter().collect::<PressedKeys>();
+        let s_then_alt = [KeyS, AltLeft].into_iter().collect::<PressedKeys>();
+        let binding = keys(&[AltLeft, KeyS]);
+        assert!(chord_matches(&binding, true, &alt_then_s, &mut cache, true));
+        assert!(!chord_matches(&binding, true, &s_then_alt, &mut cache, true));
+        assert!(chord_matches(&binding, true, &s_then_alt, &mut cache, false));
+        assert!(chord_matches(&binding, false, &s_then_alt, &mut cache, true));
+    }
+
+    #[test]
+    fn ordered_chords_fire_for_their_order() {
+        let mut manager = HotkeyManager::new();
+        let (alt_s, on_alt_s) = counter();
+        let (s_alt, on_s_alt) = counter();
+        manager.add_magic_binding(keys(&[AltLeft, KeyS]), on_alt_s, Box::new(|_| {}), true);
+        manager.add_magic_binding(keys(&[KeyS, AltLeft]), on_s_alt, Box::new(|_| {}), true);
+
+        tap(&mut manager, &[AltLeft, KeyS]);
+        ACTION_DISPATCHER.flush();
+        assert_eq!((alt_s.load(Ordering::SeqCst), s_alt.load(Ordering::SeqCst)), (1, 0));
+
+        tap(&mut manager, &[KeyS, AltLeft]);
+        ACTION_DISPATCHER.flush();
+        assert_eq!((alt_s.load(Ordering::SeqCst), s_alt.load(Ordering::SeqCst)), (1, 1));
+    }
+
+    #[test]
+    fn unordered_chord_fires_in_either_order() {
+        let mut manager = HotkeyManager::new();
+        let (count, on_press) = counter();
+        manager.add_magic_binding(keys(&[AltLeft, KeyS]), on_press, Box::new(|_| {}), false);
+
+        tap(&mut manager, &[AltLeft, KeyS]);
+        tap(&mut manager, &[KeyS, AltLeft]);
+        ACTION_DISPATCHER.flush();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn character_binding_matches_the_key_typing_it() {
+        load_test_layouts();
+        let mut manager = HotkeyManager::new();
+        let alt_y = [Key::Code(AltLeft), Key::Character("ы".to_owned())].into_iter().collect();
+        let (id, press, _release) = manager.add_channel_binding(alt_y);
+        tap(&mut manager, &[AltLeft, KeyA]);
+        assert!(press.try_recv().is_err());
+        tap(&mut manager, &[AltLeft, KeyS]);
+        assert_eq!(press.try_recv().map(|hotkey| hotkey.0.id()), Ok(id));
+    }
+
+    #[test]
+    fn dead_key_puts_its_mark_on_the_next_letter() {
+        let mut manager = acute_on_alt_8();
+        let typed = record(|| {
+            tap(&mut manager, &[AltLeft, Digit8]);
+            assert_eq!(tap(&mut manager, &[KeyE]), vec![true, true]);
+        });
+        assert_eq!(characters(&typed), "é");
+        assert!(strokes(&typed).is_empty(), "{:?}", typed);
+
+        // Used up by the letter
+        let typed = record(|| {
+            assert_eq!(tap(&mut manager, &[KeyE]), vec![false, false]);
+        });
+        assert!(typed.is_empty(), "{:?}", typed);
+    }
+
+    #[test]
+    fn dead_key_waits_through_shift_for_a_capital() {
+        let mut manager = acute_on_alt_8();
+        let typed = record(|| {
+            tap(&mut manager, &[AltLeft, Digit8]);
+            tap(&mut manager, &[ShiftLeft, KeyE]);
+        });
+        assert_eq!(characters(&typed), "É");
+    }
+
+    #[test]
+    fn other_keys_cancel_the_dead_key() {
+        let mut manager = acute_on_alt_8();
+        let typed = record(|| {
+            tap(&mut manager, &[AltLeft, Digit8]);
+            assert_eq!(tap(&mut manager, &[Digit1]), vec![false, false]);
+            assert_eq!(tap(&mut manager, &[KeyE]), vec![false, false]);
+
+            tap(&mut manager, &[AltLeft, Digit8]);
+            assert_eq!(tap(&mut manager, &[ControlLeft, KeyE]), vec![false, false, false, false]);
+        });
+        assert!(typed.is_empty(), "{:?}", typed);
+    }
 }
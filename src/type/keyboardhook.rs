@@ -1,28 +1,44 @@
+use crate::backend::{KeyAction, KeyStroke};
+use crate::r#static::INPUT_BACKEND;
+use crate::r#type::dualrole::{DualRole, KeyEvent, Replay, TapHold};
 use crate::r#type::hook::{HookContainer, HookMetadata};
-use crate::win::VIRTUAL_KEY;
+use crate::r#type::sticky::{Latch, StickyKeys};
+use crate::keycode::KeyCode;
 use anyhow::Error;
 use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
 use crate::r#type::Dump;
 
-use crate::r#type::hotkeymanager::Key::VirtualKey;
+use crate::r#type::hotkeymanager::Key::Code;
 use crate::r#type::hotkeymanager::PressedKeys;
-use crate::win::keyboard::KBDStructWrapper;
 
-pub struct KeyManager(PressedKeys, Vec<HookContainer>);
+/// Pressed keys, the hooks, when each pressed key went down, the dual-role keys and the sticky
+/// modifiers.
+pub struct KeyManager(PressedKeys, Vec<HookContainer>, HashMap<KeyCode, Instant>, TapHold, StickyKeys);
 
 pub enum KeyboardHookMetadata {
     Press {
-        key: VIRTUAL_KEY,
+        key: KeyCode,
         injected: bool,
         pressed_keys: PressedKeys,
         pressed_keys_before_hook: PressedKeys,
+        /// Zero for the first press, how long the key is down for the repeats.
+        held_for: Duration,
+        /// When it happened on the keyboard, events kept by a dual-role key reach the hooks later.
+        time: Instant,
         //key_manager: &'a KeyManager
     },
     Release {
-        key: VIRTUAL_KEY,
+        key: KeyCode,
         injected: bool,
         pressed_keys: PressedKeys,
         pressed_keys_before_hook: PressedKeys,
+        /// How long the key was down.
+        held_for: Duration,
+        /// When it happened on the keyboard.
+        time: Instant,
     },
 }
 impl HookMetadata for KeyboardHookMetadata {
@@ -42,7 +58,42 @@ impl KeyboardHookMetadata {
         }
     }
 
-    pub fn key(&self) -> &VIRTUAL_KEY {
+    /// Keys still down once the event went through, without the released key.
+    pub fn pressed_keys_after(&self) -> &PressedKeys {
+        match &self {
+            KeyboardHookMetadata::Press { pressed_keys, .. } => pressed_keys,
+            KeyboardHookMetadata::Release { pressed_keys, .. } => pressed_keys,
+        }
+    }
+
+    pub fn held_for(&self) -> Duration {
+        match &self {
+            KeyboardHookMetadata::Press { held_for, .. } => *held_for,
+            KeyboardHookMetadata::Release { held_for, .. } => *held_for,
+        }
+    }
+
+    /// When the event happened on the keyboard.
+    pub fn time(&self) -> Instant {
+        match &self {
+            KeyboardHookMetadata::Press { time, .. } => *time,
+            KeyboardHookMetadata::Release { time, .. } => *time,
+        }
+    }
+
+    /// A press of a key that is already down, sent by the key repeat.
+    pub fn repeat(&self) -> bool {
+        match &self {
+            KeyboardHookMetadata::Press {
+                key,
+                pressed_keys_before_hook,
+                ..
+            } => pressed_keys_before_hook.contains(key),
+            KeyboardHookMetadata::Release { .. } => false,
+        }
+    }
+
+    pub fn key(&self) -> &KeyCode {
         match &self {
             KeyboardHookMetadata::Press { key, .. } => key,
             KeyboardHookMetadata::Release { key, .. } => key,
@@ -72,14 +123,182 @@ impl KeyboardHookMetadata {
 
 impl KeyManager {
     pub(crate) fn with_storage(storage: PressedKeys) -> Self {
-        Self(storage, Vec::new())
+        Self(storage, Vec::new(), HashMap::new(), TapHold::default(), StickyKeys::default())
+    }
+
+    pub(crate) fn spawn_tap_hold_timer(&mut self) {
+        self.3.spawn_timer();
+    }
+
+    /// Keys that type one key when tapped and hold another one when held. Needs a capture backend
+    /// that can swallow keys.
+    pub(crate) fn set_dual_roles(&mut self, roles: Vec<DualRole>, term: Duration, permissive_hold: bool) {
+        if !roles.is_empty() && !INPUT_BACKEND.capture().can_swallow() {
+            log::warn!(target: "KeyboardHook", "The {} backend can't keep keys from applications, dual-role keys are off", INPUT_BACKEND.name());
+            self.3.set(Vec::new(), term, permissive_hold);
+            return;
+        }
+        self.3.set(roles, term, permissive_hold);
+    }
+
+    /// Modifiers in `keys` latch for the next key when tapped and lock when tapped twice within
+    /// `double_tap`.
+    pub(crate) fn set_sticky(&mut self, keys: Vec<KeyCode>, double_tap: Duration) {
+        self.4.set(keys, double_tap);
+    }
+
+    /// Sticky modifiers that are latched or locked right now, for tools that embed the manager and
+    /// show them. The daemon only logs them.
+    pub fn sticky_modifiers(&self) -> Vec<(KeyCode, Latch)> {
+        self.4.state()
+    }
+
+    pub fn keydown(&mut self, key: KeyCode, injected: bool, raw: impl Debug) -> bool {
+        let time = Instant::now();
+        if injected {
+            return self.press(key, injected, time, raw);
+        }
+        if self.3.is_empty() {
+            return self.handle(KeyEvent { key, press: true, time }, raw);
+        }
+        match self.3.press(key, time) {
+            Some(replay) => {
+                self.replay(replay);
+                true
+            }
+            None => self.handle(KeyEvent { key, press: true, time }, raw),
+        }
+    }
+
+    pub fn keyup(&mut self, key: KeyCode, injected: bool, raw: impl Debug) -> bool {
+        let time = Instant::now();
+        if injected {
+            return self.release(key, injected, time, raw);
+        }
+        if self.3.is_empty() {
+            return self.handle(KeyEvent { key, press: false, time }, raw);
+        }
+        match self.3.release(key, time) {
+            Some(replay) => {
+                self.replay(replay);
+                true
+            }
+            None => self.handle(KeyEvent { key, press: false, time }, raw),
+        }
+    }
+
+    /// Decides a dual-role key as held once the tapping term passed.
+    pub(crate) fn expire_tap_hold(&mut self) {
+        let replay = self.3.expire(Instant::now());
+        self.replay(replay);
+    }
+
+    /// Runs an event of the keyboard through the hooks, returns whether applications shouldn't
+    /// get it. Latched sticky modifiers are pressed before the next key and released after it.
+    fn handle(&mut self, event: KeyEvent, raw: impl Debug) -> bool {
+        let key = event.key;
+        if self.4.is_empty() {
+            return self.dispatch(event, raw);
+        }
+        if !event.press {
+            if self.4.take_cancelled(key) {
+                return true;
+            }
+            let result = self.dispatch(event, raw);
+            for modifier in self.4.release(key, event.time) {
+                self.feed(KeyEvent { key: modifier, press: false, time: event.time });
+            }
+            return result;
+        }
+        if self.0.contains(&key) {
+            // Key repeat
+            return self.dispatch(event, raw);
+        }
+        if key == KeyCode::Escape && self.4.cancel(key) {
+            return true;
+        }
+        if self.4.is_sticky(key) {
+            self.4.press_modifier(key);
+            return self.dispatch(event, raw);
+        }
+        let modifiers = self.4.press_key(key, &self.0);
+        if modifiers.is_empty() {
+            return self.dispatch(event, raw);
+        }
+        for modifier in modifiers {
+            self.feed(KeyEvent { key: modifier, press: true, time: event.time });
+        }
+        // Sent after the modifiers, not passed through ahead of them
+        self.feed(event);
+        true
+    }
+
+    /// Runs an event through the hooks like one of the keyboard, applications get it unless a
+    /// hook takes it.
+    fn feed(&mut self, event: KeyEvent) {
+        if !self.dispatch(event, "sticky") {
+            let action = if event.press { KeyAction::Press } else { KeyAction::Release };
+            INPUT_BACKEND.injection().send_keystrokes(&[KeyStroke::classic(event.key, action)]);
+        }
+    }
+
+    /// Held dual-role keys that hold themselves are sent first when the event gets through.
+    fn dispatch(&mut self, event: KeyEvent, raw: impl Debug) -> bool {
+        if !event.press {
+            return self.release(event.key, false, event.time, raw);
+        }
+        let result = self.press(event.key, false, event.time, raw);
+        if result || event.key.is_modifier() {
+            return result;
+        }
+        let unsent = self.3.take_unsent();
+        if unsent.is_empty() {
+            return false;
+        }
+        let mut strokes = unsent
+            .into_iter()
+            .map(|key| KeyStroke::classic(key, KeyAction::Press))
+            .collect::<Vec<_>>();
+        strokes.push(KeyStroke::classic(event.key, KeyAction::Press));
+        INPUT_BACKEND.injection().send_keystrokes(&strokes);
+        true
+    }
+
+    /// Runs events kept by the dual-role keys through the hooks, applications get what the hooks
+    /// don't take.
+    fn replay(&mut self, replay: Vec<Replay>) {
+        for item in replay {
+            match item {
+                Replay::Event(event) => {
+                    if !self.handle(event, "replayed") {
+                        let action = if event.press { KeyAction::Press } else { KeyAction::Release };
+                        INPUT_BACKEND.injection().send_keystrokes(&[KeyStroke::classic(event.key, action)]);
+                    }
+                }
+                // Goes to applications only with a key the hotkeys don't take, see `handle`
+                Replay::Hold(role, time) if role.hold == role.key => {
+                    self.press(role.key, false, time, "held");
+                }
+                Replay::Hold(role, time) => {
+                    if !self.press(role.hold, false, time, "held") {
+                        INPUT_BACKEND.injection().send_keystrokes(&[KeyStroke::classic(role.hold, KeyAction::Press)]);
+                    }
+                }
+                Replay::Release(role, sent, time) => {
+                    if !self.release(role.hold, false, time, "released") && sent {
+                        INPUT_BACKEND.injection().send_keystrokes(&[KeyStroke::classic(role.hold, KeyAction::Release)]);
+                    }
+                }
+            }
+        }
     }
 
-    pub fn keydown(&mut self, key: VIRTUAL_KEY, injected: bool, raw: KBDStructWrapper) -> bool {
+    fn press(&mut self, key: KeyCode, injected: bool, time: Instant, raw: impl Debug) -> bool {
         let old_pressed = self.0.clone();
         let existed = self.0.insert(key);
+        let held_for = time.saturating_duration_since(*self.2.entry(key).or_insert(time));
         if existed {
-            log::debug!(target: "KeyboardHook", "Pressing  key: {:width$?}. Keys pressed: {:?} | {:?}", VirtualKey(key), self.dump().dump(), raw, width=15)
+            log::debug!(target: "KeyboardHook", "Pressing  key: {:width$?}. Keys pressed: {:?} | {:?}", Code(key), self.dump().dump(), raw, width=15)
         }
         //if existed {
         let mut result = false;
@@ -90,6 +309,8 @@ impl KeyManager {
                     injected,
                     pressed_keys: self.0.clone(),
                     pressed_keys_before_hook: old_pressed.clone(),
+                    held_for,
+                    time,
                 })
                 .unwrap_or_else(|e| {
                     log::error!("Error processing hook #{}: {:?}", i, e);
@@ -97,7 +318,7 @@ impl KeyManager {
                 });
             log::trace!(
                 "Hooking keydown of {:?} resulted in {}",
-                VirtualKey(key),
+                Code(key),
                 result
             );
             if result {
@@ -114,11 +335,13 @@ impl KeyManager {
         &(self.0)
     }
 
-    pub fn keyup(&mut self, key: VIRTUAL_KEY, injected: bool, raw: KBDStructWrapper) -> bool {
+    fn release(&mut self, key: KeyCode, injected: bool, time: Instant, raw: impl Debug) -> bool {
         let old_pressed = self.0.clone();
-        let existed = self.0.remove(&key);
+        // Keeps the order of the other keys for ordered bindings
+        let existed = self.0.shift_remove(&key);
+        let held_for = self.2.remove(&key).map(|at| time.saturating_duration_since(at)).unwrap_or_default();
         if existed {
-            log::debug!(target: "KeyboardHook", "Releasing key: {:width$?}. Keys pressed: {:?} | {:?}", VirtualKey(key), self.dump().dump(), raw, width=15)
+            log::debug!(target: "KeyboardHook", "Releasing key: {:width$?}. Keys pressed: {:?} | {:?}", Code(key), self.dump().dump(), raw, width=15)
         }
         let mut result = false;
         for (i, item) in self.1.iter().enumerate() {
@@ -128,6 +351,8 @@ impl KeyManager {
                     injected,
                     pressed_keys: self.0.clone(),
                     pressed_keys_before_hook: old_pressed.clone(),
+                    held_for,
+                    time,
                 })
                 .unwrap_or_else(|e| {
                     log::error!("Error processing hook #{}: {:?}", i, e);
@@ -135,7 +360,7 @@ impl KeyManager {
                 });
             log::trace!(
                 "Hooking keyup of {:?} resulted in {}",
-                VirtualKey(key),
+                Code(key),
                 result
             );
             if result {
@@ -1,6 +1,10 @@
+pub(crate) mod command;
+pub(crate) mod dispatcher;
+pub(crate) mod dualrole;
 pub(crate) mod hook;
 pub(crate) mod hotkeymanager;
 pub(crate) mod keyboardhook;
+pub(crate) mod sticky;
 
 pub trait Dump {
     fn dump(&self) -> String;
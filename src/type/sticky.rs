@@ -0,0 +1,247 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use crate::keycode::KeyCode;
+use crate::r#type::hotkeymanager::{Key, PressedKeys};
+use crate::r#type::Dump;
+
+/// How a sticky modifier stays down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Latch {
+    /// For the next key only, after a tap.
+    Latched,
+    /// Until it's tapped again, after a double tap.
+    Locked,
+}
+
+/// One-shot modifiers for people who can't hold two keys at once.
+///
+/// Tapping a sticky modifier latches it, the next key is pressed as if the modifier was held. A
+/// second tap within the double tap timeout locks it until it's tapped once more. Escape lets go
+/// of all of them.
+#[derive(Default)]
+pub(crate) struct StickyKeys {
+    keys: HashSet<KeyCode>,
+    double_tap: Duration,
+    /// Sticky modifier that went down with no other key since.
+    tapping: Option<KeyCode>,
+    /// Latched modifiers with when they were tapped.
+    latched: Vec<(KeyCode, Instant)>,
+    locked: Vec<KeyCode>,
+    /// Key pressed with the latched modifiers, and the modifiers to release with it.
+    applied: Option<(KeyCode, Vec<KeyCode>)>,
+    /// Escape that let go of the modifiers, its release is kept from applications too.
+    cancelled_by: Option<KeyCode>,
+}
+
+impl StickyKeys {
+    pub(crate) fn set(&mut self, keys: Vec<KeyCode>, double_tap: Duration) {
+        self.keys = keys.into_iter().collect();
+        self.double_tap = double_tap;
+        let before = self.latched.len() + self.locked.len();
+        self.latched.retain(|(key, _)| self.keys.contains(key));
+        self.locked.retain(|key| self.keys.contains(key));
+        if self.latched.len() + self.locked.len() != before {
+            log::info!(target: "StickyKeys", "Modifiers that aren't sticky anymore let go, sticky modifiers: {}", self.dump());
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.latched.is_empty() && self.locked.is_empty() && self.applied.is_none()
+    }
+
+    pub(crate) fn is_sticky(&self, key: KeyCode) -> bool {
+        self.keys.contains(&key)
+    }
+
+    /// Modifiers that are latched or locked right now.
+    pub(crate) fn state(&self) -> Vec<(KeyCode, Latch)> {
+        self.latched
+            .iter()
+            .map(|(key, _)| (*key, Latch::Latched))
+            .chain(self.locked.iter().map(|key| (*key, Latch::Locked)))
+            .collect()
+    }
+
+    /// A sticky modifier went down, it's a tap if nothing else does before it's released.
+    pub(crate) fn press_modifier(&mut self, key: KeyCode) {
+        self.tapping = Some(key);
+    }
+
+    /// Another key went down. Returns the modifiers to press before it, the latched ones are used up.
+    pub(crate) fn press_key(&mut self, key: KeyCode, pressed_keys: &PressedKeys) -> Vec<KeyCode> {
+        self.tapping = None;
+        if self.applied.is_some() {
+            return Vec::new();
+        }
+        let modifiers = self
+            .latched
+            .drain(..)
+            .map(|(key, _)| key)
+            .chain(self.locked.iter().copied())
+            .filter(|modifier| !pressed_keys.contains(modifier))
+            .collect::<Vec<_>>();
+        if !modifiers.is_empty() {
+            log::info!(target: "StickyKeys", "Pressing {:?} with {}, sticky modifiers: {}", Key::Code(key), modifiers.iter().copied().collect::<PressedKeys>().dump(), self.dump());
+            self.applied = Some((key, modifiers.clone()));
+        }
+        modifiers
+    }
+
+    /// A key went up. Returns the modifiers to release after it.
+    pub(crate) fn release(&mut self, key: KeyCode, time: Instant) -> Vec<KeyCode> {
+        if self.tapping == Some(key) {
+            self.tapping = None;
+            self.tap(key, time);
+        }
+        match self.applied.take() {
+            Some((applied, modifiers)) if applied == key => modifiers,
+            applied => {
+                self.applied = applied;
+                Vec::new()
+            }
+        }
+    }
+
+    fn tap(&mut self, key: KeyCode, time: Instant) {
+        if let Some(index) = self.locked.iter().position(|locked| *locked == key) {
+            self.locked.remove(index);
+            log::info!(target: "StickyKeys", "{:?} unlocked, sticky modifiers: {}", Key::Code(key), self.dump());
+        } else if let Some(index) = self.latched.iter().position(|(latched, _)| *latched == key) {
+            let (_, at) = self.latched.remove(index);
+            if time.saturating_duration_since(at) <= self.double_tap {
+                self.locked.push(key);
+                log::info!(target: "StickyKeys", "{:?} locked, sticky modifiers: {}", Key::Code(key), self.dump());
+            } else {
+                log::info!(target: "StickyKeys", "{:?} released, sticky modifiers: {}", Key::Code(key), self.dump());
+            }
+        } else {
+            self.latched.push((key, time));
+            log::info!(target: "StickyKeys", "{:?} latched for the next key, sticky modifiers: {}", Key::Code(key), self.dump());
+        }
+    }
+
+    /// Escape lets go of all the modifiers. Returns whether there were any.
+    pub(crate) fn cancel(&mut self, key: KeyCode) -> bool {
+        if self.latched.is_empty() && self.locked.is_empty() {
+            return false;
+        }
+        self.latched.clear();
+        self.locked.clear();
+        self.cancelled_by = Some(key);
+        log::info!(target: "StickyKeys", "Sticky modifiers cancelled");
+        true
+    }
+
+    /// Whether the release of `key` belongs to the Escape that cancelled the modifiers.
+    pub(crate) fn take_cancelled(&mut self, key: KeyCode) -> bool {
+        if self.cancelled_by == Some(key) {
+            self.cancelled_by = None;
+            return true;
+        }
+        false
+    }
+}
+
+impl Dump for StickyKeys {
+    fn dump(&self) -> String {
+        let state = self
+            .state()
+            .into_iter()
+            .map(|(key, latch)| format!("{:?} {:?}", Key::Code(key), latch))
+            .collect::<Vec<_>>();
+        format!("[{}]", state.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keycode::KeyCode::{ControlLeft, Escape, KeyA, KeyB, ShiftLeft};
+
+    const DOUBLE_TAP: Duration = Duration::from_millis(300);
+
+    fn sticky() -> StickyKeys {
+        let mut sticky = StickyKeys::default();
+        sticky.set(vec![ShiftLeft, ControlLeft], DOUBLE_TAP);
+        sticky
+    }
+
+    fn tap(sticky: &mut StickyKeys, key: KeyCode, time: Instant) {
+        sticky.press_modifier(key);
+        assert!(sticky.release(key, time).is_empty());
+    }
+
+    /// Presses and releases `key`, returns the modifiers pressed with it.
+    fn type_key(sticky: &mut StickyKeys, key: KeyCode) -> Vec<KeyCode> {
+        let pressed = [key].into_iter().collect::<PressedKeys>();
+        let modifiers = sticky.press_key(key, &pressed);
+        assert_eq!(sticky.release(key, Instant::now()), modifiers);
+        modifiers
+    }
+
+    #[test]
+    fn tap_latches_for_the_next_key() {
+        let mut sticky = sticky();
+        tap(&mut sticky, ShiftLeft, Instant::now());
+        assert_eq!(sticky.state(), vec![(ShiftLeft, Latch::Latched)]);
+        assert_eq!(type_key(&mut sticky, KeyA), vec![ShiftLeft]);
+        assert!(sticky.state().is_empty());
+        assert!(type_key(&mut sticky, KeyB).is_empty());
+    }
+
+    #[test]
+    fn modifier_held_with_a_key_is_no_tap() {
+        let mut sticky = sticky();
+        sticky.press_modifier(ShiftLeft);
+        let pressed = [ShiftLeft, KeyA].into_iter().collect::<PressedKeys>();
+        assert!(sticky.press_key(KeyA, &pressed).is_empty());
+        assert!(sticky.release(KeyA, Instant::now()).is_empty());
+        assert!(sticky.release(ShiftLeft, Instant::now()).is_empty());
+        assert!(sticky.state().is_empty());
+    }
+
+    #[test]
+    fn double_tap_locks_until_the_next_tap() {
+        let mut sticky = sticky();
+        let start = Instant::now();
+        tap(&mut sticky, ShiftLeft, start);
+        tap(&mut sticky, ShiftLeft, start + DOUBLE_TAP / 2);
+        assert_eq!(sticky.state(), vec![(ShiftLeft, Latch::Locked)]);
+        assert_eq!(type_key(&mut sticky, KeyA), vec![ShiftLeft]);
+        assert_eq!(type_key(&mut sticky, KeyB), vec![ShiftLeft]);
+
+        tap(&mut sticky, ShiftLeft, start + DOUBLE_TAP * 2);
+        assert!(sticky.state().is_empty());
+        assert!(type_key(&mut sticky, KeyA).is_empty());
+    }
+
+    #[test]
+    fn slow_second_tap_releases() {
+        let mut sticky = sticky();
+        let start = Instant::now();
+        tap(&mut sticky, ShiftLeft, start);
+        tap(&mut sticky, ShiftLeft, start + DOUBLE_TAP * 2);
+        assert!(sticky.state().is_empty());
+    }
+
+    #[test]
+    fn escape_cancels_all_modifiers() {
+        let mut sticky = sticky();
+        assert!(!sticky.cancel(Escape));
+        assert!(!sticky.take_cancelled(Escape));
+
+        let start = Instant::now();
+        tap(&mut sticky, ShiftLeft, start);
+        tap(&mut sticky, ControlLeft, start);
+        tap(&mut sticky, ControlLeft, start);
+        assert_eq!(sticky.state(), vec![(ShiftLeft, Latch::Latched), (ControlLeft, Latch::Locked)]);
+
+        assert!(sticky.cancel(Escape));
+        assert!(sticky.state().is_empty());
+        assert!(!sticky.take_cancelled(KeyA));
+        assert!(sticky.take_cancelled(Escape));
+        assert!(!sticky.take_cancelled(Escape));
+        assert!(type_key(&mut sticky, KeyA).is_empty());
+    }
+}
@@ -1,7 +1,7 @@
-use std::iter::once;
 use std::time::{Duration, Instant};
-use log::{Level, logger};
+use log::Level;
 
+#[allow(dead_code)]
 pub fn measure_and_return<T, F>(f: F) -> (T, Duration)
     where
         F: FnOnce() -> T,
@@ -12,6 +12,7 @@ pub fn measure_and_return<T, F>(f: F) -> (T, Duration)
     (result, duration) // Return the result and the duration
 }
 
+#[allow(dead_code)]
 pub fn measure<T, F>(f: F) -> T
     where
         F: FnOnce() -> T,
@@ -21,6 +22,7 @@ pub fn measure<T, F>(f: F) -> T
     r
 }
 
+#[allow(dead_code)]
 pub enum ProfilerLogger {
     Static(fn(&str)),
     Boxed(Box<dyn Fn(&str)>),
@@ -32,11 +34,12 @@ impl ProfilerLogger {
         match self {
             ProfilerLogger::Static(f) => f(message),
             ProfilerLogger::Boxed(f) => f(message),
-            _ => {}
+            ProfilerLogger::Noop => {}
         }
     }
 }
 
+#[allow(dead_code)]
 type BoxedProfilerLogger = Box<dyn Fn(&str)>;
 type ConstProfilerLogger = fn(&str);
 pub struct ProfilerFactory<'a> {
@@ -47,8 +50,9 @@ pub struct ProfilerFactory<'a> {
 
 impl <'a>ProfilerFactory<'a> {
 
+    #[allow(dead_code)]
     pub(crate) fn new(prefix: &'a str, logger: ProfilerLogger) -> Self {
-        ProfilerFactory { prefix, logger: logger }
+        ProfilerFactory { prefix, logger }
     }
 
     pub const fn static_new(prefix: &'a str, logger: ConstProfilerLogger) -> Self {
@@ -58,16 +62,14 @@ impl <'a>ProfilerFactory<'a> {
     }
 
     // Invoke operator to create a Profiler
-    pub fn dispense(&self, message: &str) -> Profiler {
+    pub fn dispense(&self, message: &str) -> Profiler<'_> {
         Profiler::new(message.to_string(), self.prefix, &self.logger)
     }
     pub(crate) fn oneshot<F, T>(&self, msg: &str, func: F) -> T
         where
             F: FnOnce(Profiler) -> T,
     {
-        let s = self.dispense(msg);
-        let r = func(s);
-        r
+        func(self.dispense(msg))
     }
 }
 
@@ -95,6 +97,7 @@ impl <'a> Profiler<'a> {
         self.logger.log(&format!("[⏲] [{}] {} {}", self.prefix, self.message, text));
     }
 
+    #[allow(dead_code)]
     pub fn elapsed(&self) {
         self.log(&format!("running for {:?}", self.start.elapsed()));
     }
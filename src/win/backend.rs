@@ -0,0 +1,52 @@
+use std::ptr::null_mut;
+
+use crate::backend::{CaptureBackend, InjectionBackend, InputBackend, KeyStroke, KeyTranslator};
+use crate::keycode::KeyCode;
+use crate::win::keyboard::virtual_keys;
+use crate::win::window::create_window;
+use crate::win::{get_foreground_window_keyboard_layout, to_unicode};
+
+/// Lowlevel keyboard hook for capturing, `SendInput`/`keybd_event` for injection and the
+/// keyboard layout of the foreground window for translation.
+pub struct WindowsBackend;
+
+impl InputBackend for WindowsBackend {
+    fn name(&self) -> &'static str {
+        "windows"
+    }
+
+    fn capture(&self) -> &dyn CaptureBackend {
+        self
+    }
+
+    fn injection(&self) -> &dyn InjectionBackend {
+        self
+    }
+
+    fn translator(&self) -> &dyn KeyTranslator {
+        self
+    }
+}
+
+impl CaptureBackend for WindowsBackend {
+    fn run(&self) -> anyhow::Result<()> {
+        create_window();
+        Ok(())
+    }
+}
+
+impl InjectionBackend for WindowsBackend {
+    fn send_keystrokes(&self, strokes: &[KeyStroke]) {
+        virtual_keys(strokes);
+    }
+}
+
+impl KeyTranslator for WindowsBackend {
+    fn to_unicode(&self, key: KeyCode, localized: bool) -> Option<String> {
+        if localized {
+            to_unicode(key.vk(), get_foreground_window_keyboard_layout())
+        } else {
+            to_unicode(key.vk(), null_mut())
+        }
+    }
+}
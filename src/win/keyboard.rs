@@ -1,26 +1,28 @@
-use crate::r#static::{KEY_MANAGER_INSTANCE, SCANCODE_BLACKLIST};
-use crate::r#type::hotkeymanager::Key::VirtualKey;
+use crate::backend::{KeyAction, KeyStroke, KeyType};
+use crate::keycode::KeyCode;
+use crate::r#static::{KEYBOARD_LAYOUTS, KEY_MANAGER_INSTANCE, SCANCODE_BLACKLIST};
 use crate::r#type::hotkeymanager::PressedKeys;
-use crate::win::{ToChar, ToScanCode, ToUnicode, HC_ACTION, KEYBOARD_HOOK, VIRTUAL_KEY};
+use crate::win::{
+    base_char, get_foreground_window_keyboard_layout, to_unicode, ToChar, HC_ACTION, KEYBOARD_HOOK,
+};
 use num_traits::FromPrimitive;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::fmt::Formatter;
 
 use std::ptr;
+use std::ptr::null_mut;
 use log::Level;
 use winapi::shared::minwindef::{BYTE, DWORD, UINT};
 use winapi::um::winuser::{
     keybd_event, CallNextHookEx, SendInput, INPUT, INPUT_KEYBOARD, KBDLLHOOKSTRUCT, KEYBDINPUT,
-    KEYEVENTF_KEYUP, KEYEVENTF_SCANCODE, KEYEVENTF_UNICODE, LLKHF_INJECTED,
+    KEYEVENTF_EXTENDEDKEY, KEYEVENTF_KEYUP, KEYEVENTF_SCANCODE, KEYEVENTF_UNICODE, LLKHF_EXTENDED,
+    LLKHF_INJECTED,
 };
 use crate::util::{default_logger, ProfilerFactory};
 
-use crate::win::keyboard::KeyAction::Press;
-use crate::win::keyboard::KeyType::Classic;
-use crate::win::keyboard_vk::KNOWN_VIRTUAL_KEY;
-use crate::win::keyboard_vk::KNOWN_VIRTUAL_KEY::{
-    VK_LMENU, VK_LSHIFT, VK_LWIN, VK_MENU, VK_RMENU, VK_RSHIFT, VK_RWIN, VK_SHIFT,
-};
+use crate::backend::keyboard_vk::KNOWN_VIRTUAL_KEY;
 
 pub const KEYSTROKE_MARKER: usize = 0x666;
 
@@ -30,8 +32,13 @@ impl KBDStructWrapper {
     fn is_injected(&self) -> bool {
         self.0.flags & LLKHF_INJECTED != 0
     }
+
+    fn key_code(&self) -> Option<KeyCode> {
+        KeyCode::from_windows(self.0.vkCode, self.0.scanCode, self.0.flags & LLKHF_EXTENDED != 0)
+    }
 }
 
+#[allow(dead_code)]
 fn test_flag(what: DWORD, flag: DWORD) -> bool {
     what & flag != 0
 }
@@ -44,18 +51,41 @@ impl Debug for KBDStructWrapper {
         }, match self.0.vkCode.to_char_localized() {
             '\x1b' => "ESC".to_string(),
             char => char.to_string()
-        }, self.0.vkCode.to_unicode(), self.0.vkCode.to_unicode_localized())
+        }, to_unicode(self.0.vkCode, null_mut()), to_unicode(self.0.vkCode, get_foreground_window_keyboard_layout()))
+    }
+}
+
+thread_local! {
+    /// Keyboard layout of the foreground window the last key went to.
+    static FOREGROUND_LAYOUT: Cell<usize> = const { Cell::new(0) };
+    /// Loaded layout matching each keyboard layout of Windows, looked up the first time it's used.
+    static MATCHING_LAYOUTS: RefCell<HashMap<usize, Option<usize>>> = RefCell::new(HashMap::new());
+}
+
+/// Makes the loaded layout matching the one of the foreground window active.
+fn follow_foreground_layout() {
+    let hkl = get_foreground_window_keyboard_layout();
+    if hkl.is_null() || FOREGROUND_LAYOUT.replace(hkl as usize) == hkl as usize {
+        return;
     }
+    let layout = MATCHING_LAYOUTS.with_borrow_mut(|layouts| {
+        *layouts.entry(hkl as usize).or_insert_with(|| {
+            KEYBOARD_LAYOUTS
+                .write()
+                .activate_matching(|key| base_char(key.vk(), hkl))
+        })
+    });
+    KEYBOARD_LAYOUTS.write().set_active(layout);
 }
 
 const PROFILER: ProfilerFactory = ProfilerFactory::static_new("Lowlevel keyboard hook", default_logger(Level::Debug));
 //const PROFILER: ProfilerFactory = ProfilerFactory::static_new("Lowlevel keyboard hook", |txt|println!("!!! TIMER {}", txt));
 pub extern "system" fn keyboard_hook_proc(n_code: i32, w_param: usize, l_param: isize) -> isize {
     let handled = if n_code == HC_ACTION {
-    PROFILER.dispense("keyboard_hook_proc execution").r#do(|prof|{
+    PROFILER.dispense("keyboard_hook_proc execution").r#do(|_prof|{
         if let Some(ev) = KEYBOARD_HOOK::from_u32(w_param as u32) {
             let kbd_struct = unsafe { *(l_param as *const KBDLLHOOKSTRUCT) };
-            /// We intercept the keystrokes from the blacklist
+            // We intercept the keystrokes from the blacklist
             if SCANCODE_BLACKLIST.contains(&kbd_struct.scanCode) {
                 log::trace!(target: "keyboard_hook_proc",
                     "key intercepted: {:?}: {:?}",
@@ -64,14 +94,14 @@ pub extern "system" fn keyboard_hook_proc(n_code: i32, w_param: usize, l_param:
                 );
                 Some(1)
             } else if kbd_struct.dwExtraInfo == KEYSTROKE_MARKER {
-                /// We ignore and pass the keystrokes that we produced ourselves
+                // We ignore and pass the keystrokes that we produced ourselves
                 log::trace!(target: "keyboard_hook_proc",
                     "key_ignored: {:?}: {:?}",
                     ev,
                     KBDStructWrapper(kbd_struct)
                 );
                 None
-            } else {
+            } else if let Some(key) = KBDStructWrapper(kbd_struct).key_code() {
                 match ev {
                     KEYBOARD_HOOK::WM_KEYDOWN | KEYBOARD_HOOK::WM_SYSKEYDOWN => {
                         log::debug!(
@@ -79,9 +109,10 @@ pub extern "system" fn keyboard_hook_proc(n_code: i32, w_param: usize, l_param:
                             ev,
                             KBDStructWrapper(kbd_struct)
                         );
-                        let result = PROFILER.oneshot("keydown",|profiler| {
+                        follow_foreground_layout();
+                        let result = PROFILER.oneshot("keydown",|_profiler| {
                              KEY_MANAGER_INSTANCE.write().keydown(
-                                kbd_struct.vkCode as _,
+                                key,
                                 kbd_struct.flags & LLKHF_INJECTED != 0,
                                 KBDStructWrapper(kbd_struct),
                             )
@@ -98,9 +129,9 @@ pub extern "system" fn keyboard_hook_proc(n_code: i32, w_param: usize, l_param:
                             ev,
                             KBDStructWrapper(kbd_struct)
                         );
-                        let result = PROFILER.oneshot("keyup", |prof| {
+                        let result = PROFILER.oneshot("keyup", |_prof| {
                             KEY_MANAGER_INSTANCE.write().keyup(
-                                kbd_struct.vkCode as _,
+                                key,
                                 kbd_struct.flags & LLKHF_INJECTED != 0,
                                 KBDStructWrapper(kbd_struct)
                             )
@@ -113,6 +144,13 @@ pub extern "system" fn keyboard_hook_proc(n_code: i32, w_param: usize, l_param:
                         }
                     }
                 }
+            } else {
+                log::trace!(target: "keyboard_hook_proc",
+                    "key_unknown: {:?}: {:?}",
+                    ev,
+                    KBDStructWrapper(kbd_struct)
+                );
+                None
             }
         } else {
             None
@@ -134,16 +172,17 @@ pub fn virtual_keys<'a, T: AsRef<[KeyStroke]> + IntoIterator<Item = &'a KeyStrok
 ) {
     for stroke in keys {
         log::trace!("{:?}", stroke);
-        if stroke.key_type == Classic {
+        if let (KeyType::Classic, Some(key)) = (stroke.key_type, stroke.key) {
             unsafe {
                 keybd_event(
-                    stroke.virtual_key as BYTE,
+                    key.vk() as BYTE,
                     stroke.scancode as BYTE,
-                    if stroke.action == KeyAction::Release {
-                        KEYEVENTF_KEYUP
-                    } else {
-                        0
-                    },
+                    extended_flag(stroke)
+                        | if stroke.action == KeyAction::Release {
+                            KEYEVENTF_KEYUP
+                        } else {
+                            0
+                        },
                     KEYSTROKE_MARKER,
                 );
             }
@@ -153,17 +192,16 @@ pub fn virtual_keys<'a, T: AsRef<[KeyStroke]> + IntoIterator<Item = &'a KeyStrok
     }
 }
 
-pub fn filter_modifier_keys(vk_list: &PressedKeys) -> Vec<VIRTUAL_KEY> {
+#[allow(dead_code)]
+pub fn filter_modifier_keys(vk_list: &PressedKeys) -> Vec<KeyCode> {
     // Define a list of modifier keys
-    let modifiers: Vec<VIRTUAL_KEY> = vec![
-        VK_SHIFT as u32,  // VK_SHIFT
-        VK_LSHIFT as u32, // VK_LSHIFT
-        VK_RSHIFT as u32, // VK_RSHIFT
-        VK_MENU as u32,   // VK_MENU (Alt)
-        VK_LMENU as u32,  // VK_LMENU (LAlt)
-        VK_RMENU as u32,  // VK_RMENU (RAlt)
-        VK_LWIN as u32,   // VK_LWIN
-        VK_RWIN as u32,   // VK_RWIN
+    let modifiers: Vec<KeyCode> = vec![
+        KeyCode::ShiftLeft,
+        KeyCode::ShiftRight,
+        KeyCode::AltLeft,
+        KeyCode::AltRight,
+        KeyCode::MetaLeft,
+        KeyCode::MetaRight,
     ];
 
     // Filter the input list to include only the modifier keys
@@ -174,77 +212,6 @@ pub fn filter_modifier_keys(vk_list: &PressedKeys) -> Vec<VIRTUAL_KEY> {
         .collect()
 }
 
-#[derive(Clone, Copy)]
-pub(crate) struct KeyStroke {
-    key_type: KeyType,
-    virtual_key: u32,
-    scancode: u32,
-    action: KeyAction,
-}
-
-impl Debug for KeyStroke {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "[{}] {:?} {:?} key 0x{:x}",
-            if self.action == Press {
-                "Pressing"
-            } else {
-                "Releasing"
-            },
-            self.key_type,
-            VirtualKey(self.virtual_key),
-            self.scancode
-        )
-    }
-}
-
-impl KeyStroke {
-    pub fn classic(virtual_key: VIRTUAL_KEY, action: KeyAction) -> Self {
-        Self {
-            key_type: KeyType::Classic,
-            virtual_key,
-            scancode: virtual_key.to_code(),
-            action,
-        }
-    }
-
-    pub fn unicode(char: char, action: KeyAction) -> Self {
-        Self {
-            key_type: KeyType::Unicode,
-            virtual_key: 0,
-            scancode: char as u32,
-            action,
-        }
-    }
-}
-
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum KeyType {
-    Unicode,
-    Classic,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum KeyAction {
-    Press,
-    Release,
-}
-
-impl KeyStroke {
-    fn clone_as_press(self) -> KeyStroke {
-        let mut pew = self.clone();
-        pew.action = KeyAction::Press;
-        return pew;
-    }
-
-    pub(crate) fn clone_as_release(self) -> KeyStroke {
-        let mut pew = self;
-        pew.action = KeyAction::Release;
-        pew
-    }
-}
-
 fn send_keystrokes<'a, T: AsRef<[KeyStroke]> + IntoIterator<Item = &'a KeyStroke> + Clone>(
     keys: T,
 ) {
@@ -264,13 +231,12 @@ fn send_keystrokes<'a, T: AsRef<[KeyStroke]> + IntoIterator<Item = &'a KeyStroke
     }
 }
 
-pub fn send_key_sequence(pre_keys: &[KeyStroke], the_char: &[KeyStroke], post_keys: &[KeyStroke]) {
-    let mut inputs = Vec::new();
-    inputs.is_empty();
-    inputs.extend(pre_keys);
-    inputs.extend(the_char);
-    inputs.extend(post_keys);
-    virtual_keys(&inputs);
+fn extended_flag(stroke: &KeyStroke) -> DWORD {
+    if stroke.key.is_some_and(KeyCode::is_extended) {
+        KEYEVENTF_EXTENDEDKEY
+    } else {
+        0
+    }
 }
 
 fn create_input(stroke: KeyStroke) -> INPUT {
@@ -298,8 +264,9 @@ fn create_input(stroke: KeyStroke) -> INPUT {
             KeyType::Classic => {
                 KEYBDINPUT {
                     wVk: 0,                        // Virtual-key code is not needed for scancode input
-                    wScan: stroke.scancode as u16, // Scancode
+                    wScan: stroke.scancode as u8 as u16, // Scancode without the 0xE0 prefix
                     dwFlags: KEYEVENTF_SCANCODE
+                        | extended_flag(&stroke)
                         | if stroke.action == KeyAction::Release {
                             KEYEVENTF_KEYUP
                         } else {
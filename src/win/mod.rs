@@ -1,8 +1,8 @@
+pub(crate) mod backend;
 pub(crate) mod keyboard;
-pub(crate) mod keyboard_vk;
 pub(crate) mod window;
 
-use crate::win::keyboard_vk::KNOWN_VIRTUAL_KEY::{VK_LMENU, VK_RMENU, VK_RWIN};
+use crate::backend::VIRTUAL_KEY;
 use crate::win::MapType::MAPVK_VK_TO_CHAR;
 use num_derive::FromPrimitive;
 use std::ffi::{c_int, OsString};
@@ -15,11 +15,9 @@ use winapi::um::winbase::FormatMessageW;
 use winapi::um::winnt::{LPWSTR, WCHAR};
 use winapi::um::winuser::{
     GetForegroundWindow, GetKeyboardLayout, GetKeyboardState, GetWindowThreadProcessId,
-    LoadKeyboardLayoutW, MapVirtualKeyExW, MapVirtualKeyW, ToUnicode, ToUnicodeEx, VkKeyScanW,
-    MAPVK_VK_TO_VSC, VK_LWIN,
+    MapVirtualKeyExW, MapVirtualKeyW, ToUnicodeEx, VkKeyScanW,
+    MAPVK_VK_TO_VSC,
 };
-use winreg::enums::HKEY_CURRENT_USER;
-use winreg::RegKey;
 
 #[allow(non_camel_case_types)]
 #[repr(u32)]
@@ -41,26 +39,13 @@ enum MessageType {
     WM_DESTROY = 2,
 }
 
-#[allow(non_camel_case_types)]
-pub type VIRTUAL_KEY = u32;
-
 pub trait ToChar {
     fn to_char(&self) -> char;
 
     fn to_char_localized(&self) -> char;
 }
 
-pub trait ToUnicode {
-    fn to_unicode(&self) -> Option<String>;
-
-    fn to_unicode_localized(&self) -> Option<String>;
-}
-
-pub trait ToScanCode {
-    fn to_code(&self) -> u32;
-}
-
-#[allow(non_camel_case_types)]
+#[allow(non_camel_case_types, dead_code)]
 #[repr(u32)]
 enum MapType {
     MAPVK_VK_TO_VSC = 0, // 	The uCode parameter is a virtual-key code and is translated into a scan code. If it is a virtual-key code that does not distinguish between left- and right-hand keys, the left-hand scan code is returned. If there is no translation, the function returns 0.
@@ -80,13 +65,7 @@ impl ToChar for VIRTUAL_KEY {
     }
 }
 
-pub fn is_meta_or_alt(key: VIRTUAL_KEY) -> bool {
-    key == VK_LMENU as u32
-        || key == VK_RMENU as u32
-        || key == VK_LWIN as u32
-        || key == VK_RWIN as u32
-}
-
+#[allow(dead_code)]
 pub fn char_to_vk_key_scan(ch: char) -> (u8, u8) {
     // Returns (virtual key code, shift state)
     unsafe {
@@ -97,21 +76,6 @@ pub fn char_to_vk_key_scan(ch: char) -> (u8, u8) {
     }
 }
 
-impl ToUnicode for VIRTUAL_KEY {
-    fn to_unicode(&self) -> Option<String> {
-        to_unicode(*self, null_mut())
-    }
-
-    fn to_unicode_localized(&self) -> Option<String> {
-        to_unicode(*self, get_foreground_window_keyboard_layout())
-    }
-}
-
-impl ToScanCode for VIRTUAL_KEY {
-    fn to_code(&self) -> u32 {
-        unsafe { MapVirtualKeyW(*self, MAPVK_VK_TO_VSC) }
-    }
-}
 
 fn to_char(key: VIRTUAL_KEY, locale: HKL) -> char {
     let char = unsafe { MapVirtualKeyExW(key, MAPVK_VK_TO_CHAR as u32, locale) };
@@ -119,7 +83,17 @@ fn to_char(key: VIRTUAL_KEY, locale: HKL) -> char {
         .unwrap_or_else(|e| panic!("Failed to extract char from {} [{}]: {}", key, char, e))
 }
 
-fn to_unicode(key: VIRTUAL_KEY, locale: HKL) -> Option<String> {
+/// What `key` types without modifiers in `locale`, in lowercase. Unlike [`to_unicode`] it leaves
+/// a pending dead key alone.
+pub(crate) fn base_char(key: VIRTUAL_KEY, locale: HKL) -> Option<String> {
+    // Dead keys set the top bit
+    let char = unsafe { MapVirtualKeyExW(key, MAPVK_VK_TO_CHAR as u32, locale) } & 0x7FFF_FFFF;
+    char::from_u32(char)
+        .filter(|char| *char != '\0')
+        .map(|char| char.to_lowercase().collect())
+}
+
+pub(crate) fn to_unicode(key: VIRTUAL_KEY, locale: HKL) -> Option<String> {
     //let buf = [0u16;2];
     let mut key_state = [0u8; 256]; // Array to hold the state of each key
 
@@ -193,7 +167,7 @@ fn get_last_error_message() -> String {
     }
 }
 
-fn get_foreground_window_keyboard_layout() -> HKL {
+pub(crate) fn get_foreground_window_keyboard_layout() -> HKL {
     unsafe {
         let hwnd = GetForegroundWindow(); // Get handle to the foreground window
         let mut process_id: DWORD = 0;
@@ -205,36 +179,3 @@ fn get_foreground_window_keyboard_layout() -> HKL {
         layout
     }
 }
-
-pub(crate) fn load_preload_keyboard_layouts() {
-    let hklm = RegKey::predef(HKEY_CURRENT_USER);
-    let preload_key_result = hklm.open_subkey("Keyboard Layout\\Preload");
-
-    if preload_key_result.is_err() {
-        log::trace!("Failed to open registry key for keyboard layout preload.");
-        return;
-    }
-
-    let preload_key = preload_key_result.unwrap();
-
-    for i in 1.. {
-        let value_name = format!("{}", i);
-        let layout_id_result: Result<String, _> = preload_key.get_value(&value_name);
-
-        match layout_id_result {
-            Ok(layout_id) => {
-                // Convert the layout ID to the format expected by LoadKeyboardLayout (e.g., "00000409")
-                let layout_id_wide = format!("{:08}", layout_id)
-                    .encode_utf16()
-                    .chain(Some(0))
-                    .collect::<Vec<u16>>();
-
-                unsafe {
-                    let _hkl = LoadKeyboardLayoutW(layout_id_wide.as_ptr(), 0);
-                    // Note: You might want to check if hkl is null (0) which indicates failure to load the layout.
-                }
-            }
-            Err(_) => break, // No more preload entries
-        }
-    }
-}
@@ -73,7 +73,7 @@ pub fn create_window() {
         let hwnd = CreateWindowExW(
             0,
             class_name.as_ptr() as _,
-            "My Window\0".as_ptr() as _,
+            c"My Window".as_ptr() as _,
             WS_OVERLAPPEDWINDOW,
             CW_USEDEFAULT,
             CW_USEDEFAULT,